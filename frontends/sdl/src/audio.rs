@@ -1,3 +1,5 @@
+use std::mem::size_of;
+
 use sdl2::{
     audio::{AudioQueue, AudioSpecDesired},
     AudioSubsystem, Sdl,
@@ -31,4 +33,10 @@ impl Audio {
             audio_subsystem,
         }
     }
+
+    /// Obtains the number of sample frames (one sample per
+    /// channel) that are currently pending in the queue.
+    pub fn queued(&self) -> usize {
+        self.device.size() as usize / (size_of::<f32>() * self.device.spec().channels as usize)
+    }
 }
@@ -7,10 +7,13 @@ pub mod test;
 
 use audio::Audio;
 use boytacean::{
-    devices::{printer::PrinterDevice, stdout::StdoutDevice},
+    devices::{
+        link::LinkCable, network::NetworkDevice, printer::PrinterDevice, stdout::StdoutDevice,
+    },
+    filter::{scale_frame, ScaleFilter},
     gb::{AudioProvider, GameBoy, GameBoyMode},
     pad::PadKey,
-    ppu::{PaletteInfo, PpuMode},
+    ppu::{ColorCorrection, PaletteInfo, PpuMode, PpuRenderer, WIDE_DISPLAY_WIDTH},
     rom::Cartridge,
     serial::{NullDevice, SerialDevice},
 };
@@ -18,7 +21,7 @@ use chrono::Utc;
 use clap::Parser;
 use image::{ColorType, ImageBuffer, Rgb};
 use sdl::{surface_from_bytes, SdlSystem};
-use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum, Sdl};
+use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum, rect::Rect, Sdl};
 use std::{
     cmp::max,
     path::Path,
@@ -36,7 +39,12 @@ const TITLE: &str = "Boytacean";
 
 /// Base audio volume to be used as the basis of the
 /// amplification level of the volume
-const VOLUME: f32 = 64.0;
+const VOLUME: f32 = 32768.0;
+
+/// The number of sample frames that the audio queue should
+/// ideally hold, used as the reference for the dynamic rate
+/// control of the APU (about 140ms at 44.1kHz)
+const AUDIO_TARGET: usize = 6144;
 
 pub struct Benchmark {
     count: usize,
@@ -58,13 +66,20 @@ impl Default for Benchmark {
 pub struct EmulatorOptions {
     auto_mode: Option<bool>,
     unlimited: Option<bool>,
+    filter: Option<ScaleFilter>,
+    filter_scale: Option<usize>,
     features: Option<Vec<&'static str>>,
 }
 
 pub struct Emulator {
     system: GameBoy,
+    link: Option<GameBoy>,
+    link_cable: LinkCable,
+    link_focus: bool,
     auto_mode: bool,
     unlimited: bool,
+    filter: ScaleFilter,
+    filter_scale: usize,
     sdl: Option<SdlSystem>,
     audio: Option<Audio>,
     title: &'static str,
@@ -82,8 +97,13 @@ impl Emulator {
     pub fn new(system: GameBoy, options: EmulatorOptions) -> Self {
         Self {
             system,
+            link: None,
+            link_cable: LinkCable::new(),
+            link_focus: false,
             auto_mode: options.auto_mode.unwrap_or(true),
             unlimited: options.unlimited.unwrap_or(false),
+            filter: options.filter.unwrap_or(ScaleFilter::Nearest),
+            filter_scale: options.filter_scale.unwrap_or(1),
             sdl: None,
             audio: None,
             title: TITLE,
@@ -186,11 +206,17 @@ impl Emulator {
     }
 
     pub fn start_graphics(&mut self, sdl: &Sdl, screen_scale: f32) {
+        let (width, height) = self.frame_size();
+        let width = if self.link.is_some() {
+            width * 2
+        } else {
+            width
+        };
         self.sdl = Some(SdlSystem::new(
             sdl,
             self.title,
-            self.system.display_width() as u32,
-            self.system.display_height() as u32,
+            width as u32,
+            height as u32,
             screen_scale,
             !self.features.contains(&"no-accelerated"),
             !self.features.contains(&"no-vsync"),
@@ -201,8 +227,43 @@ impl Emulator {
         self.audio = Some(Audio::new(sdl));
     }
 
+    /// Connects a second Game Boy to the main one using a link cable,
+    /// with both of them running side by side (split-screen).
+    pub fn attach_link(&mut self, mut link: GameBoy) {
+        self.link_cable.connect(&mut self.system, &mut link);
+        self.link = Some(link);
+    }
+
+    /// Runs a clock of the main Game Boy, keeping the linked one
+    /// (if any) in sync with it through the link cable.
+    fn clock(&mut self) -> u16 {
+        match self.link.as_mut() {
+            Some(link) => self.link_cable.clock(&mut self.system, link),
+            None => self.system.clock(),
+        }
+    }
+
+    /// Obtains the Game Boy that is currently receiving the input,
+    /// switched with the Tab key when running in split-screen.
+    fn focused(&mut self) -> &mut GameBoy {
+        match self.link.as_mut() {
+            Some(link) if self.link_focus => link,
+            _ => &mut self.system,
+        }
+    }
+
     pub fn load_rom(&mut self, path: Option<&str>) {
         let path_res = path.unwrap_or(&self.rom_path);
+        if is_gbs_path(path_res) {
+            self.system.load_gbs_file(path_res);
+            println!(
+                "========= GBS File ==========\n{}\n=============================",
+                self.system.gbs().unwrap()
+            );
+            self.rom_path = String::from(path_res);
+            self.update_gbs_title();
+            return;
+        }
         let rom = self.system.load_rom_file(path_res);
         println!(
             "========= Cartridge =========\n{}\n=============================",
@@ -216,6 +277,36 @@ impl Emulator {
         self.rom_path = String::from(path_res);
     }
 
+    /// Changes the GBS track currently being played by the provided
+    /// offset (wrapping around), no-op in case no GBS is loaded.
+    pub fn change_gbs_track(&mut self, offset: i16) {
+        if !self.system.gbs_loaded() || self.system.gbs_track_count() == 0 {
+            return;
+        }
+        let count = self.system.gbs_track_count() as i16;
+        let track = (self.system.gbs_track() as i16 + offset).rem_euclid(count);
+        self.system.set_gbs_track(track as u8);
+        self.update_gbs_title();
+    }
+
+    fn update_gbs_title(&mut self) {
+        let title = format!(
+            "{} [{} - Track {}/{}]",
+            self.title,
+            self.system.gbs().unwrap().title(),
+            self.system.gbs_track() + 1,
+            self.system.gbs_track_count()
+        );
+        println!(
+            "Playing GBS track {}/{}",
+            self.system.gbs_track() + 1,
+            self.system.gbs_track_count()
+        );
+        if let Some(ref mut sdl) = self.sdl {
+            sdl.window_mut().set_title(title.as_str()).unwrap();
+        }
+    }
+
     pub fn reset(&mut self) {
         self.system.reset();
         self.system.load(true);
@@ -249,9 +340,9 @@ impl Emulator {
     }
 
     fn save_image(&mut self, file_path: &str) {
-        let width = self.system.display_width() as u32;
-        let height = self.system.display_height() as u32;
-        let pixels = self.system.frame_buffer();
+        let (width, height) = self.output_size();
+        let (width, height) = (width as u32, height as u32);
+        let pixels = self.output_pixels();
 
         let mut image_buffer: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(width, height);
 
@@ -277,14 +368,64 @@ impl Emulator {
         self.palette_index = (self.palette_index + 1) % self.palettes.len();
     }
 
+    /// Obtains the dimensions of the frame to be displayed, taking
+    /// into account the (optional) widescreen enhancement mode.
+    fn frame_size(&mut self) -> (usize, usize) {
+        if self.system.ppu_widescreen() {
+            (WIDE_DISPLAY_WIDTH, self.system.display_height())
+        } else {
+            (self.system.display_width(), self.system.display_height())
+        }
+    }
+
+    /// Obtains the RGB pixels of the frame to be displayed, taking
+    /// into account the (optional) widescreen enhancement mode.
+    fn frame_pixels(&mut self) -> &[u8] {
+        if self.system.ppu_widescreen() {
+            self.system.frame_buffer_wide().as_ref()
+        } else {
+            self.system.frame_buffer().as_ref()
+        }
+    }
+
+    /// Obtains the dimensions of the output frame, meaning the
+    /// frame after the (CPU side) scale filter has been applied.
+    fn output_size(&mut self) -> (usize, usize) {
+        let (width, height) = self.frame_size();
+        let factor = self.filter.factor(self.filter_scale);
+        (width * factor, height * factor)
+    }
+
+    /// Obtains the RGB pixels of the output frame, upscaling the
+    /// frame to be displayed with the selected scale filter.
+    fn output_pixels(&mut self) -> Vec<u8> {
+        let (width, height) = self.frame_size();
+        let (filter, filter_scale) = (self.filter, self.filter_scale);
+        scale_frame(filter, self.frame_pixels(), width, height, filter_scale)
+    }
+
+    /// Obtains the RGB pixels of the output frame of the linked
+    /// Game Boy, upscaled with the same scale filter as the main one.
+    fn link_output_pixels(&mut self) -> Vec<u8> {
+        let (width, height) = self.frame_size();
+        let (filter, filter_scale) = (self.filter, self.filter_scale);
+        let link = self.link.as_mut().unwrap();
+        let pixels: &[u8] = if link.ppu_widescreen() {
+            link.frame_buffer_wide().as_ref()
+        } else {
+            link.frame_buffer().as_ref()
+        };
+        scale_frame(filter, pixels, width, height, filter_scale)
+    }
+
     pub fn limited(&self) -> bool {
         !self.unlimited
     }
 
     pub fn run(&mut self) {
-        // obtains the dimensions of the display that are going
-        // to be used for the graphics rendering
-        let (width, height) = (self.system.display_width(), self.system.display_height());
+        // obtains the dimensions of the (upscaled) output frame that
+        // are going to be used for the graphics rendering
+        let (width, height) = self.output_size();
 
         // updates the icon of the window to reflect the image
         // and style of the emulator
@@ -300,9 +441,15 @@ impl Emulator {
         let texture_creator = self.sdl.as_mut().unwrap().canvas.texture_creator();
 
         // creates the texture streaming that is going to be used
-        // as the target for the pixel buffer
+        // as the target for the (upscaled) pixel buffer, in split-screen
+        // the linked Game Boy is drawn on the right half of it
+        let texture_width = if self.link.is_some() {
+            width * 2
+        } else {
+            width
+        };
         let mut texture = texture_creator
-            .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+            .create_texture_streaming(PixelFormatEnum::RGB24, texture_width as u32, height as u32)
             .unwrap();
 
         // starts the variable that will control the number of cycles that
@@ -349,6 +496,18 @@ impl Emulator {
                         keycode: Some(Keycode::P),
                         ..
                     } => self.toggle_palette(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::PageUp),
+                        ..
+                    } => self.change_gbs_track(1),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::PageDown),
+                        ..
+                    } => self.change_gbs_track(-1),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Tab),
+                        ..
+                    } => self.link_focus = !self.link_focus && self.link.is_some(),
                     Event::KeyDown {
                         keycode: Some(Keycode::Plus),
                         ..
@@ -362,7 +521,7 @@ impl Emulator {
                         ..
                     } => {
                         if let Some(key) = key_to_pad(keycode) {
-                            self.system.key_press(key)
+                            self.focused().key_press(key)
                         }
                     }
                     Event::KeyUp {
@@ -370,12 +529,12 @@ impl Emulator {
                         ..
                     } => {
                         if let Some(key) = key_to_pad(keycode) {
-                            self.system.key_lift(key)
+                            self.focused().key_lift(key)
                         }
                     }
                     Event::DropFile { filename, .. } => {
                         if self.auto_mode {
-                            let mode = Cartridge::from_file(&filename).gb_mode();
+                            let mode = file_gb_mode(&filename);
                             self.system.set_mode(mode);
                         }
                         self.system.reset();
@@ -395,6 +554,7 @@ impl Emulator {
                 // being drawn in the current tick
                 let mut counter_cycles = pending_cycles;
                 let mut last_frame = 0xffffu16;
+                let mut last_link_frame = 0xffffu16;
 
                 // calculates the number of cycles that are meant to be the target
                 // for the current "tick" operation this is basically the current
@@ -415,24 +575,52 @@ impl Emulator {
                     // runs the Game Boy clock, this operation should
                     // include the advance of both the CPU, PPU, APU
                     // and any other frequency based component of the system
-                    counter_cycles += self.system.clock() as u32;
+                    counter_cycles += self.clock() as u32;
 
-                    // in case a V-Blank state has been reached a new frame is available
-                    // then the frame must be pushed into SDL for display
-                    if self.system.ppu_mode() == PpuMode::VBlank
+                    // in case a V-Blank state has been reached (or the LCD is off) a new
+                    // frame is available then the frame must be pushed into SDL for display
+                    if (self.system.ppu_mode() == PpuMode::VBlank || !self.system.ppu_lcd())
                         && self.system.ppu_frame() != last_frame
                     {
                         // obtains the frame buffer of the Game Boy PPU and uses it
                         // to update the stream texture, that will latter be copied
                         // to the canvas
-                        let frame_buffer = self.system.frame_buffer().as_ref();
-                        texture.update(None, frame_buffer, width * 3).unwrap();
+                        let frame_buffer = self.output_pixels();
+                        texture
+                            .update(
+                                Rect::new(0, 0, width as u32, height as u32),
+                                &frame_buffer,
+                                width * 3,
+                            )
+                            .unwrap();
 
                         // obtains the index of the current PPU frame, this value
                         // is going to be used to detect for new frame presence
                         last_frame = self.system.ppu_frame();
                     }
 
+                    // the same is done for the linked Game Boy (if any), with
+                    // its frame being drawn to the right half of the texture
+                    let mut link_frame = None;
+                    if let Some(link) = self.link.as_mut() {
+                        if (link.ppu_mode() == PpuMode::VBlank || !link.ppu_lcd())
+                            && link.ppu_frame() != last_link_frame
+                        {
+                            link_frame = Some(link.ppu_frame());
+                        }
+                    }
+                    if let Some(link_frame) = link_frame {
+                        let frame_buffer = self.link_output_pixels();
+                        texture
+                            .update(
+                                Rect::new(width as i32, 0, width as u32, height as u32),
+                                &frame_buffer,
+                                width * 3,
+                            )
+                            .unwrap();
+                        last_link_frame = link_frame;
+                    }
+
                     // in case the audio subsystem is enabled, then the audio buffer
                     // must be queued into the SDL audio subsystem
                     if let Some(audio) = self.audio.as_mut() {
@@ -448,6 +636,17 @@ impl Emulator {
                     // clears the audio buffer to prevent it from
                     // "exploding" in size, this is required GC operation
                     self.system.clear_audio_buffer();
+                    if let Some(link) = self.link.as_mut() {
+                        link.clear_audio_buffer();
+                    }
+                }
+
+                // adjusts the APU sampling rate according to the fill level
+                // of the audio queue, keeping audio in sync with the video
+                // (eg: 59.7275 Hz emulation on a 60 Hz display) while
+                // preventing both crackling (starvation) and drifting latency
+                if let Some(audio) = self.audio.as_ref() {
+                    self.system.apu().rate_control(audio.queued(), AUDIO_TARGET);
                 }
 
                 // in case there's at least one new frame that was drawn during
@@ -455,7 +654,7 @@ impl Emulator {
                 // this separation between texture creation and canvas flush prevents
                 // resources from being over-used in situations where multiple frames
                 // are generated during the same tick cycle
-                if last_frame != 0xffffu16 {
+                if last_frame != 0xffffu16 || last_link_frame != 0xffffu16 {
                     // clears the graphics canvas, making sure that no garbage
                     // pixel data remaining in the pixel buffer, not doing this would
                     // create visual glitches in OSs like Mac OS X
@@ -585,7 +784,7 @@ impl Emulator {
                     // runs the Game Boy clock, this operation should
                     // include the advance of both the CPU, PPU, APU
                     // and any other frequency based component of the system
-                    counter_cycles += self.system.clock() as u32;
+                    counter_cycles += self.clock() as u32;
                 }
 
                 // increments the total number of cycles with the cycle limit
@@ -665,6 +864,76 @@ struct Args {
     #[arg(long, default_value_t = false, help = "If set no PPU will be used")]
     no_ppu: bool,
 
+    #[arg(long, default_value_t = String::from("line"), help = "PPU renderer (ex: line, fifo) to be used")]
+    renderer: String,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "If set the 10 objects per line limit is lifted (enhancement)"
+    )]
+    unlimited_objects: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "If set the background is rendered beyond the screen in a 16:9 frame (enhancement)"
+    )]
+    widescreen: bool,
+
+    #[arg(
+        long,
+        default_value_t = String::from("none"),
+        help = "CGB color correction (ex: none, gbc, gba) to be used"
+    )]
+    color_correction: String,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "If set each frame is blended with the previous one (LCD ghosting)"
+    )]
+    frame_blending: bool,
+
+    #[arg(
+        long,
+        default_value_t = String::from("nearest"),
+        help = "Scale filter (ex: nearest, scale2x, scale3x, epx, xbr, lcd) to be used"
+    )]
+    filter: String,
+
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "The scale to be used by the nearest and LCD grid filters"
+    )]
+    filter_scale: usize,
+
+    #[arg(
+        long,
+        help = "Path of the PNG screenshot saved at the end of the headless run"
+    )]
+    screenshot: Option<String>,
+
+    #[arg(
+        long,
+        help = "Path of the WAV file where the audio output is going to be recorded"
+    )]
+    record_audio: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "If set each audio channel is also recorded to its own WAV file"
+    )]
+    record_stems: bool,
+
+    #[arg(
+        long,
+        help = "Path of the VGM file where the APU register writes are going to be logged"
+    )]
+    record_vgm: Option<String>,
+
     #[arg(long, default_value_t = false, help = "If set no APU will be used")]
     no_apu: bool,
 
@@ -712,6 +981,31 @@ struct Args {
     )]
     cycles: u64,
 
+    #[arg(
+        long,
+        help = "Path of the ROM loaded in a second Game Boy connected by link cable (split-screen)"
+    )]
+    link_rom: Option<String>,
+
+    #[arg(
+        long,
+        help = "Address (ex: 0.0.0.0:5555) where to wait for a remote emulator to connect by link cable"
+    )]
+    link_listen: Option<String>,
+
+    #[arg(
+        long,
+        help = "Address (ex: 127.0.0.1:5555) of the remote emulator to connect to by link cable"
+    )]
+    link_connect: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 60,
+        help = "Number of seconds to wait for a remote emulator to connect by link cable"
+    )]
+    link_timeout: u64,
+
     #[arg(short, long, default_value_t = String::from("../../res/roms/demo/pocket.gb"), help = "Path to the ROM file to be loaded")]
     rom_path: String,
 }
@@ -731,9 +1025,25 @@ fn run(args: Args, emulator: &mut Emulator) {
         } else {
             None
         });
+        if let Some(screenshot) = args.screenshot {
+            emulator.save_image(&screenshot);
+        }
     } else {
         emulator.run();
     }
+
+    // saves the audio (WAV) and the APU writes (VGM) captured during
+    // the execution, in case their recording has been requested
+    if let Some(record_audio) = args.record_audio {
+        if let Err(message) = emulator.system.save_wav_file(&record_audio) {
+            println!("Failed to save WAV file: {}", message);
+        }
+    }
+    if let Some(record_vgm) = args.record_vgm {
+        if let Err(message) = emulator.system.save_vgm_file(&record_vgm) {
+            println!("Failed to save VGM file: {}", message);
+        }
+    }
 }
 
 fn main() {
@@ -751,12 +1061,41 @@ fn main() {
     // and the initial game ROM to "start the engine"
     let mut game_boy = GameBoy::new(Some(mode));
     if auto_mode {
-        let mode = Cartridge::from_file(&args.rom_path).gb_mode();
+        let mode = file_gb_mode(&args.rom_path);
         game_boy.set_mode(mode);
     }
-    let device: Box<dyn SerialDevice> = build_device(&args.device);
+    let network = if let Some(addr) = &args.link_listen {
+        println!("Waiting for link cable connection on {}...", addr);
+        Some(NetworkDevice::listen(
+            addr,
+            Duration::from_secs(args.link_timeout),
+        ))
+    } else {
+        args.link_connect
+            .as_ref()
+            .map(|addr| NetworkDevice::connect(addr))
+    };
+    let device: Box<dyn SerialDevice> = match network {
+        Some(Ok(network)) => Box::new(network),
+        Some(Err(message)) => {
+            println!("Failed to establish link cable connection: {}", message);
+            build_device(&args.device)
+        }
+        None => build_device(&args.device),
+    };
     game_boy.set_ppu_enabled(!args.no_ppu);
+    game_boy.set_ppu_renderer(PpuRenderer::from_string(&args.renderer));
+    game_boy.set_ppu_unlimited_objects(args.unlimited_objects);
+    game_boy.set_ppu_widescreen(args.widescreen);
+    game_boy.set_ppu_color_correction(ColorCorrection::from_string(&args.color_correction));
+    game_boy.set_ppu_frame_blending(args.frame_blending);
     game_boy.set_apu_enabled(!args.no_apu);
+    if args.record_audio.is_some() {
+        game_boy.enable_wav_recorder(args.record_stems);
+    }
+    if args.record_vgm.is_some() {
+        game_boy.enable_vgm_logger();
+    }
     game_boy.set_dma_enabled(!args.no_dma);
     game_boy.set_timer_enabled(!args.no_timer);
     game_boy.attach_serial(device);
@@ -771,6 +1110,8 @@ fn main() {
     let options = EmulatorOptions {
         auto_mode: Some(auto_mode),
         unlimited: Some(args.unlimited),
+        filter: Some(ScaleFilter::from_string(&args.filter)),
+        filter_scale: Some(args.filter_scale),
         features: if args.headless || args.benchmark {
             Some(vec![])
         } else {
@@ -778,6 +1119,30 @@ fn main() {
         },
     };
     let mut emulator = Emulator::new(game_boy, options);
+
+    // in case a ROM for the second Game Boy has been provided it's
+    // connected to the main one using a link cable (split-screen)
+    if let Some(link_rom) = &args.link_rom {
+        let mut link = GameBoy::new(Some(if auto_mode {
+            file_gb_mode(link_rom)
+        } else {
+            mode
+        }));
+        link.set_ppu_renderer(PpuRenderer::from_string(&args.renderer));
+        link.set_ppu_unlimited_objects(args.unlimited_objects);
+        link.set_ppu_widescreen(args.widescreen);
+        link.set_ppu_color_correction(ColorCorrection::from_string(&args.color_correction));
+        link.set_ppu_frame_blending(args.frame_blending);
+        link.set_apu_enabled(false);
+        link.load(true);
+        let rom = link.load_rom_file(link_rom);
+        println!(
+            "====== Link Cartridge =======\n{}\n=============================",
+            rom
+        );
+        emulator.attach_link(link);
+    }
+
     emulator.start(SCREEN_SCALE);
     emulator.load_rom(Some(&args.rom_path));
     emulator.toggle_palette();
@@ -785,6 +1150,24 @@ fn main() {
     run(args, &mut emulator);
 }
 
+/// Checks if the file at the provided path is a GBS (Game Boy
+/// Sound System) file, using its extension.
+fn is_gbs_path(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map(|extension| extension.eq_ignore_ascii_case("gbs"))
+        .unwrap_or(false)
+}
+
+/// Obtains the Game Boy mode to be used for the file at the
+/// provided path, GBS files are always played in DMG mode.
+fn file_gb_mode(path: &str) -> GameBoyMode {
+    if is_gbs_path(path) {
+        return GameBoyMode::Dmg;
+    }
+    Cartridge::from_file(path).gb_mode()
+}
+
 fn build_device(device: &str) -> Box<dyn SerialDevice> {
     match device {
         "null" => Box::<NullDevice>::default(),
@@ -288,11 +288,12 @@ export class GameboyEmulator extends EmulatorBase implements Emulator {
             const tickCycles = this.gameBoy.clock();
             counterCycles += tickCycles;
 
-            // in case the current PPU mode is VBlank and the
-            // frame is different from the previously rendered
-            // one then it's time to update the canvas
+            // in case the current PPU mode is VBlank (or the LCD
+            // is off) and the frame is different from the previously
+            // rendered one then it's time to update the canvas
             if (
-                this.gameBoy.ppu_mode() === PpuMode.VBlank &&
+                (this.gameBoy.ppu_mode() === PpuMode.VBlank ||
+                    !this.gameBoy.ppu_lcd()) &&
                 this.gameBoy.ppu_frame() !== lastFrame
             ) {
                 // updates the reference to the last frame index
@@ -616,8 +617,8 @@ export class GameboyEmulator extends EmulatorBase implements Emulator {
         const leftStream = new Float32Array(internalBuffer.length / 2);
         const rightStream = new Float32Array(internalBuffer.length / 2);
         for (let index = 0; index < internalBuffer.length; index += 2) {
-            leftStream[index / 2] = internalBuffer[index] / 100.0;
-            rightStream[index / 2] = internalBuffer[index + 1] / 100.0;
+            leftStream[index / 2] = internalBuffer[index] / 32768.0;
+            rightStream[index / 2] = internalBuffer[index + 1] / 32768.0;
         }
         return [leftStream, rightStream];
     }
@@ -800,6 +801,22 @@ export class GameboyEmulator extends EmulatorBase implements Emulator {
         return this.gameBoy?.get_tile_buffer(index) ?? new Uint8Array();
     }
 
+    getTileMap(map: boolean): Uint8Array {
+        return this.gameBoy?.get_tile_map_buffer(map) ?? new Uint8Array();
+    }
+
+    getObject(index: number): Uint8Array {
+        return this.gameBoy?.get_object_buffer(index) ?? new Uint8Array();
+    }
+
+    getPalettesBg(): Uint8Array {
+        return this.gameBoy?.get_palettes_bg_buffer() ?? new Uint8Array();
+    }
+
+    getPalettesObj(): Uint8Array {
+        return this.gameBoy?.get_palettes_obj_buffer() ?? new Uint8Array();
+    }
+
     changePalette(): string {
         this.paletteIndex += 1;
         this.paletteIndex %= PALETTES.length;
@@ -1,6 +1,13 @@
 use std::collections::VecDeque;
 
-use crate::{gb::GameBoy, warnln};
+use crate::{
+    blip::{BlipBuffer, HighPass},
+    gb::{GameBoy, GameBoyMode},
+    state::{Serializer, StateComponent},
+    vgm::VgmLogger,
+    warnln,
+    wav::WavRecorder,
+};
 
 const DUTY_TABLE: [[u8; 8]; 4] = [
     [0, 0, 0, 0, 0, 0, 0, 1],
@@ -11,6 +18,28 @@ const DUTY_TABLE: [[u8; 8]; 4] = [
 
 const CH4_DIVISORS: [u8; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
 
+/// The extra delay (in cycles) before channel 3 reads its first
+/// sample after being triggered.
+const CH3_DELAY: i16 = 6;
+
+/// The charge factor (per clock) of the capacitor of the DMG
+/// output high-pass filter.
+const DMG_CHARGE: f64 = 0.999958;
+
+/// The charge factor (per clock) of the capacitor of the CGB
+/// output high-pass filter, which removes DC a lot faster.
+const CGB_CHARGE: f64 = 0.998943;
+
+/// The multiplier that converts the mixed output level (0-60)
+/// into the signed 16 bit range, leaving enough headroom for
+/// the swing of the high-pass filter.
+const SAMPLE_SCALE: f32 = 256.0;
+
+/// The maximum deviation (0.5%) of the effective sampling rate
+/// allowed to the dynamic rate control, small enough for the
+/// pitch change to be inaudible.
+const RATE_CONTROL_DELTA: f32 = 0.005;
+
 pub enum Channel {
     Ch1,
     Ch2,
@@ -18,6 +47,7 @@ pub enum Channel {
     Ch4,
 }
 
+#[derive(Clone)]
 pub struct Apu {
     ch1_timer: i16,
     ch1_sequence: u8,
@@ -28,10 +58,14 @@ pub struct Apu {
     ch1_sweep_slope: u8,
     ch1_sweep_increase: bool,
     ch1_sweep_pace: u8,
-    ch1_length_timer: u8,
+    ch1_sweep_enabled: bool,
+    ch1_sweep_shadow: u16,
+    ch1_sweep_negated: bool,
+    ch1_length_timer: u16,
     ch1_wave_duty: u8,
     ch1_pace: u8,
     ch1_direction: u8,
+    ch1_start_volume: u8,
     ch1_volume: u8,
     ch1_wave_length: u16,
     ch1_length_stop: bool,
@@ -42,10 +76,11 @@ pub struct Apu {
     ch2_envelope_sequence: u8,
     ch2_envelope_enabled: bool,
     ch2_output: u8,
-    ch2_length_timer: u8,
+    ch2_length_timer: u16,
     ch2_wave_duty: u8,
     ch2_pace: u8,
     ch2_direction: u8,
+    ch2_start_volume: u8,
     ch2_volume: u8,
     ch2_wave_length: u16,
     ch2_length_stop: bool,
@@ -54,8 +89,10 @@ pub struct Apu {
     ch3_timer: i16,
     ch3_position: u8,
     ch3_output: u8,
+    ch3_sample: u8,
+    ch3_fetch_age: u16,
     ch3_dac: bool,
-    ch3_length_timer: u8,
+    ch3_length_timer: u16,
     ch3_output_level: u8,
     ch3_wave_length: u16,
     ch3_length_stop: bool,
@@ -65,9 +102,10 @@ pub struct Apu {
     ch4_envelope_sequence: u8,
     ch4_envelope_enabled: bool,
     ch4_output: u8,
-    ch4_length_timer: u8,
+    ch4_length_timer: u16,
     ch4_pace: u8,
     ch4_direction: u8,
+    ch4_start_volume: u8,
     ch4_volume: u8,
     ch4_divisor: u8,
     ch4_width_mode: bool,
@@ -77,6 +115,10 @@ pub struct Apu {
     ch4_enabled: bool,
 
     glob_panning: u8,
+    glob_volume_left: u8,
+    glob_volume_right: u8,
+    glob_vin_left: bool,
+    glob_vin_right: bool,
 
     right_enabled: bool,
     left_enabled: bool,
@@ -92,15 +134,26 @@ pub struct Apu {
     sampling_rate: u16,
     sequencer: u16,
     sequencer_step: u8,
-    output_timer: i16,
-    audio_buffer: VecDeque<u8>,
+    blip_left: BlipBuffer,
+    blip_right: BlipBuffer,
+    high_pass_left: HighPass,
+    high_pass_right: HighPass,
+    last_left: u8,
+    last_right: u8,
+    audio_buffer: VecDeque<i16>,
     audio_buffer_max: usize,
 
+    rate_adjust: f32,
+    recorder: Option<WavRecorder>,
+    vgm: Option<VgmLogger>,
+
     clock_freq: u32,
+    gb_mode: GameBoyMode,
 }
 
 impl Apu {
-    pub fn new(sampling_rate: u16, buffer_size: f32, clock_freq: u32) -> Self {
+    pub fn new(mode: GameBoyMode, sampling_rate: u16, buffer_size: f32, clock_freq: u32) -> Self {
+        let charge = Self::charge(mode);
         Self {
             ch1_timer: 0,
             ch1_sequence: 0,
@@ -111,10 +164,14 @@ impl Apu {
             ch1_sweep_slope: 0x0,
             ch1_sweep_increase: false,
             ch1_sweep_pace: 0x0,
+            ch1_sweep_enabled: false,
+            ch1_sweep_shadow: 0x0,
+            ch1_sweep_negated: false,
             ch1_length_timer: 0x0,
             ch1_wave_duty: 0x0,
             ch1_pace: 0x0,
             ch1_direction: 0x0,
+            ch1_start_volume: 0x0,
             ch1_volume: 0x0,
             ch1_wave_length: 0x0,
             ch1_length_stop: false,
@@ -129,6 +186,7 @@ impl Apu {
             ch2_wave_duty: 0x0,
             ch2_pace: 0x0,
             ch2_direction: 0x0,
+            ch2_start_volume: 0x0,
             ch2_volume: 0x0,
             ch2_wave_length: 0x0,
             ch2_length_stop: false,
@@ -137,6 +195,8 @@ impl Apu {
             ch3_timer: 0,
             ch3_position: 0,
             ch3_output: 0,
+            ch3_sample: 0,
+            ch3_fetch_age: 0,
             ch3_dac: false,
             ch3_length_timer: 0x0,
             ch3_output_level: 0x0,
@@ -151,6 +211,7 @@ impl Apu {
             ch4_length_timer: 0x0,
             ch4_pace: 0x0,
             ch4_direction: 0x0,
+            ch4_start_volume: 0x0,
             ch4_volume: 0x0,
             ch4_divisor: 0x0,
             ch4_width_mode: false,
@@ -160,6 +221,10 @@ impl Apu {
             ch4_enabled: false,
 
             glob_panning: 0x0,
+            glob_volume_left: 0x0,
+            glob_volume_right: 0x0,
+            glob_vin_left: false,
+            glob_vin_right: false,
 
             left_enabled: true,
             right_enabled: true,
@@ -184,12 +249,29 @@ impl Apu {
             /// used for the activation of the tick actions.
             sequencer: 0,
             sequencer_step: 0,
-            output_timer: 0,
+
+            // Band-limited synthesis buffers that convert the
+            // amplitude changes of the left and right outputs
+            // into samples at the sampling rate.
+            blip_left: BlipBuffer::new(clock_freq, sampling_rate as u32),
+            blip_right: BlipBuffer::new(clock_freq, sampling_rate as u32),
+
+            // The DC blocking filters that emulate the output
+            // capacitors, the charge depends on the model.
+            high_pass_left: HighPass::from_charge(charge, clock_freq, sampling_rate as u32),
+            high_pass_right: HighPass::from_charge(charge, clock_freq, sampling_rate as u32),
+
+            last_left: 0,
+            last_right: 0,
             audio_buffer: VecDeque::with_capacity(
                 (sampling_rate as f32 * buffer_size) as usize * 2,
             ),
             audio_buffer_max: (sampling_rate as f32 * buffer_size) as usize * 2,
             clock_freq,
+            rate_adjust: 1.0,
+            recorder: None,
+            vgm: None,
+            gb_mode: mode,
         }
     }
 
@@ -203,10 +285,14 @@ impl Apu {
         self.ch1_sweep_slope = 0x0;
         self.ch1_sweep_increase = false;
         self.ch1_sweep_pace = 0x0;
+        self.ch1_sweep_enabled = false;
+        self.ch1_sweep_shadow = 0x0;
+        self.ch1_sweep_negated = false;
         self.ch1_length_timer = 0x0;
         self.ch1_wave_duty = 0x0;
         self.ch1_pace = 0x0;
         self.ch1_direction = 0x0;
+        self.ch1_start_volume = 0x0;
         self.ch1_volume = 0x0;
         self.ch1_wave_length = 0x0;
         self.ch1_length_stop = false;
@@ -221,6 +307,7 @@ impl Apu {
         self.ch2_wave_duty = 0x0;
         self.ch2_pace = 0x0;
         self.ch2_direction = 0x0;
+        self.ch2_start_volume = 0x0;
         self.ch2_volume = 0x0;
         self.ch2_wave_length = 0x0;
         self.ch2_length_stop = false;
@@ -229,6 +316,8 @@ impl Apu {
         self.ch3_timer = 0;
         self.ch3_position = 0;
         self.ch3_output = 0;
+        self.ch3_sample = 0;
+        self.ch3_fetch_age = 0;
         self.ch3_dac = false;
         self.ch3_length_timer = 0x0;
         self.ch3_output_level = 0x0;
@@ -243,6 +332,7 @@ impl Apu {
         self.ch4_length_timer = 0x0;
         self.ch4_pace = 0x0;
         self.ch4_direction = 0x0;
+        self.ch4_start_volume = 0x0;
         self.ch4_volume = 0x0;
         self.ch4_divisor = 0x0;
         self.ch4_width_mode = false;
@@ -252,6 +342,10 @@ impl Apu {
         self.ch4_enabled = false;
 
         self.glob_panning = 0x0;
+        self.glob_volume_left = 0x0;
+        self.glob_volume_right = 0x0;
+        self.glob_vin_left = false;
+        self.glob_vin_right = false;
 
         self.left_enabled = true;
         self.right_enabled = true;
@@ -259,12 +353,92 @@ impl Apu {
 
         self.sequencer = 0;
         self.sequencer_step = 0;
-        self.output_timer = 0;
+        self.blip_left.reset();
+        self.blip_right.reset();
+        self.high_pass_left.reset();
+        self.high_pass_right.reset();
+        self.last_left = 0;
+        self.last_right = 0;
 
         self.clear_audio_buffer()
     }
 
+    /// Powers off the APU (NR52), clearing all of the sound registers
+    /// and disabling the channels, the wave RAM is kept and on the
+    /// DMG the length timers are also not affected by the power.
+    fn power_off(&mut self) {
+        self.ch1_sweep_slope = 0x0;
+        self.ch1_sweep_increase = true;
+        self.ch1_sweep_pace = 0x0;
+        self.ch1_sweep_enabled = false;
+        self.ch1_sweep_negated = false;
+        self.ch1_wave_duty = 0x0;
+        self.ch1_pace = 0x0;
+        self.ch1_direction = 0x0;
+        self.ch1_start_volume = 0x0;
+        self.ch1_volume = 0x0;
+        self.ch1_wave_length = 0x0;
+        self.ch1_length_stop = false;
+        self.ch1_enabled = false;
+        self.ch1_output = 0;
+
+        self.ch2_wave_duty = 0x0;
+        self.ch2_pace = 0x0;
+        self.ch2_direction = 0x0;
+        self.ch2_start_volume = 0x0;
+        self.ch2_volume = 0x0;
+        self.ch2_wave_length = 0x0;
+        self.ch2_length_stop = false;
+        self.ch2_enabled = false;
+        self.ch2_output = 0;
+
+        self.ch3_dac = false;
+        self.ch3_output_level = 0x0;
+        self.ch3_wave_length = 0x0;
+        self.ch3_length_stop = false;
+        self.ch3_enabled = false;
+        self.ch3_output = 0;
+
+        self.ch4_pace = 0x0;
+        self.ch4_direction = 0x0;
+        self.ch4_start_volume = 0x0;
+        self.ch4_volume = 0x0;
+        self.ch4_divisor = 0x0;
+        self.ch4_width_mode = false;
+        self.ch4_clock_shift = 0x0;
+        self.ch4_length_stop = false;
+        self.ch4_enabled = false;
+        self.ch4_output = 0;
+
+        self.glob_panning = 0x0;
+        self.glob_volume_left = 0x0;
+        self.glob_volume_right = 0x0;
+        self.glob_vin_left = false;
+        self.glob_vin_right = false;
+
+        if self.gb_mode == GameBoyMode::Cgb {
+            self.ch1_length_timer = 0x0;
+            self.ch2_length_timer = 0x0;
+            self.ch3_length_timer = 0x0;
+            self.ch4_length_timer = 0x0;
+        }
+    }
+
+    /// Powers on the APU (NR52), the frame sequencer is reset so
+    /// that its next step is 0 as are the duty positions of the
+    /// square channels and the sample buffer of the wave channel.
+    fn power_on(&mut self) {
+        self.sequencer_step = 0;
+        self.ch1_sequence = 0;
+        self.ch2_sequence = 0;
+        self.ch3_sample = 0;
+    }
+
     pub fn clock(&mut self, cycles: u16) {
+        if let Some(vgm) = self.vgm.as_mut() {
+            vgm.clock(cycles);
+        }
+
         if !self.sound_enabled {
             return;
         }
@@ -303,28 +477,55 @@ impl Apu {
 
         self.tick_ch_all(cycles);
 
-        self.output_timer = self.output_timer.saturating_sub(cycles as i16);
-        if self.output_timer <= 0 {
+        // advances the band-limited buffers and registers any change
+        // in the output levels as a step at the current position
+        self.blip_left.advance(cycles as u32);
+        self.blip_right.advance(cycles as u32);
+        let left = self.output_left();
+        if left != self.last_left {
+            self.blip_left
+                .add_delta(left as f32 - self.last_left as f32);
+            self.last_left = left;
+        }
+        let right = self.output_right();
+        if right != self.last_right {
+            self.blip_right
+                .add_delta(right as f32 - self.last_right as f32);
+            self.last_right = right;
+        }
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.clock(
+                cycles,
+                [self.last_left, self.last_right],
+                [
+                    self.ch1_output,
+                    self.ch2_output,
+                    self.ch3_output,
+                    self.ch4_output,
+                ],
+            );
+        }
+
+        while let Some(left) = self.blip_left.read_sample() {
+            let right = self.blip_right.read_sample().unwrap_or(0.0);
+            let left = Self::to_sample(self.high_pass_left.filter(left));
+            let right = Self::to_sample(self.high_pass_right.filter(right));
+
             // verifies if we've reached the maximum allowed size for the
             // audio buffer and if that's the case an item is removed from
             // the buffer (avoiding overflow) and then then the new audio
-            // volume item is added to the queue
+            // sample is added to the queue
             if self.audio_buffer.len() >= self.audio_buffer_max {
                 self.audio_buffer.pop_front();
                 self.audio_buffer.pop_front();
             }
             if self.left_enabled {
-                self.audio_buffer.push_back(self.output());
+                self.audio_buffer.push_back(left);
             }
             if self.right_enabled {
-                self.audio_buffer.push_back(self.output());
+                self.audio_buffer.push_back(right);
             }
-
-            // calculates the rate at which a new audio sample should be
-            // created based on the (base/CPU) clock frequency and the
-            // sampling rate, this is basically the amount of APU clock
-            // calls that should be performed until an audio sample is created
-            self.output_timer += (self.clock_freq as f32 / self.sampling_rate as f32) as i16;
         }
     }
 
@@ -333,53 +534,75 @@ impl Apu {
             // 0xFF10 — NR10: Channel 1 sweep
             0xff10 => {
                 (self.ch1_sweep_slope & 0x07)
-                    | (if self.ch1_sweep_increase { 0x08 } else { 0x00 })
+                    | (if self.ch1_sweep_increase { 0x00 } else { 0x08 })
                     | ((self.ch1_sweep_pace & 0x07) << 4)
+                    | 0x80
             }
             // 0xFF11 — NR11: Channel 1 length timer & duty cycle
-            0xff11 => (self.ch1_wave_duty & 0x03) << 6,
+            0xff11 => ((self.ch1_wave_duty & 0x03) << 6) | 0x3f,
             // 0xFF12 — NR12: Channel 1 volume & envelope
             0xff12 => {
                 (self.ch1_pace & 0x07)
                     | ((self.ch1_direction & 0x01) << 3)
-                    | ((self.ch1_volume & 0x0f) << 4)
+                    | ((self.ch1_start_volume & 0x0f) << 4)
             }
+            // 0xFF13 — NR13: Channel 1 wavelength low [write-only]
+            0xff13 => 0xff,
+            // 0xFF14 — NR14: Channel 1 wavelength high & control
+            0xff14 => (if self.ch1_length_stop { 0x40 } else { 0x00 }) | 0xbf,
 
             // 0xFF15 — Not used
             0xff15 => 0xff,
             // 0xFF16 — NR21: Channel 2 length timer & duty cycle
-            0xff16 => (self.ch2_wave_duty & 0x03) << 6,
+            0xff16 => ((self.ch2_wave_duty & 0x03) << 6) | 0x3f,
             // 0xFF17 — NR22: Channel 2 volume & envelope
             0xff17 => {
                 (self.ch2_pace & 0x07)
                     | ((self.ch2_direction & 0x01) << 3)
-                    | ((self.ch2_volume & 0x0f) << 4)
+                    | ((self.ch2_start_volume & 0x0f) << 4)
             }
+            // 0xFF18 — NR23: Channel 2 wavelength low [write-only]
+            0xff18 => 0xff,
+            // 0xFF19 — NR24: Channel 2 wavelength high & control
+            0xff19 => (if self.ch2_length_stop { 0x40 } else { 0x00 }) | 0xbf,
 
             // 0xFF1A — NR30: Channel 3 DAC enable
-            0xff1a => {
-                if self.ch3_dac {
-                    0x80
-                } else {
-                    0x00
-                }
-            }
-            // 0xFF1B — NR31: Channel 3 length timer
-            0xff1b => 0x00,
+            0xff1a => (if self.ch3_dac { 0x80 } else { 0x00 }) | 0x7f,
+            // 0xFF1B — NR31: Channel 3 length timer [write-only]
+            0xff1b => 0xff,
             // 0xFF1C — NR32: Channel 3 output level
-            0xff1c => (self.ch3_output_level & 0x03) << 5,
+            0xff1c => ((self.ch3_output_level & 0x03) << 5) | 0x9f,
+            // 0xFF1D — NR33: Channel 3 wavelength low [write-only]
+            0xff1d => 0xff,
+            // 0xFF1E — NR34: Channel 3 wavelength high & control
+            0xff1e => (if self.ch3_length_stop { 0x40 } else { 0x00 }) | 0xbf,
 
             // 0xFF1F — Not used
             0xff1f => 0xff,
-            // 0xFF20 — NR41: Channel 4 length timer
-            0xff20 => 0x00,
+            // 0xFF20 — NR41: Channel 4 length timer [write-only]
+            0xff20 => 0xff,
             // 0xFF21 — NR42: Channel 4 volume & envelope
             0xff21 => {
                 (self.ch4_pace & 0x07)
                     | ((self.ch4_direction & 0x01) << 3)
-                    | ((self.ch4_volume & 0x0f) << 4)
+                    | ((self.ch4_start_volume & 0x0f) << 4)
             }
+            // 0xFF22 — NR43: Channel 4 frequency & randomness
+            0xff22 => {
+                (self.ch4_divisor & 0x07)
+                    | (if self.ch4_width_mode { 0x08 } else { 0x00 })
+                    | ((self.ch4_clock_shift & 0x0f) << 4)
+            }
+            // 0xFF23 — NR44: Channel 4 control
+            0xff23 => (if self.ch4_length_stop { 0x40 } else { 0x00 }) | 0xbf,
 
+            // 0xFF24 — NR50: Master volume & VIN panning
+            0xff24 => {
+                (self.glob_volume_right & 0x07)
+                    | if self.glob_vin_right { 0x08 } else { 0x00 }
+                    | ((self.glob_volume_left & 0x07) << 4)
+                    | if self.glob_vin_left { 0x80 } else { 0x00 }
+            }
             // 0xFF25 — NR51: Sound panning
             0xff25 => self.glob_panning,
             // 0xFF26 — NR52: Sound on/off
@@ -388,17 +611,31 @@ impl Apu {
                 #[allow(clippy::bool_to_int_with_if)]
                 (if self.ch1_enabled { 0x01 } else { 0x00 }
                     | if self.ch2_enabled { 0x02 } else { 0x00 }
-                    | if self.ch3_enabled && self.ch3_dac {
-                        0x04
-                    } else {
-                        0x00
-                    }
+                    | if self.ch3_enabled { 0x04 } else { 0x00 }
                     | if self.ch4_enabled { 0x08 } else { 0x00 }
-                    | if self.sound_enabled { 0x80 } else { 0x00 })
+                    | if self.sound_enabled { 0x80 } else { 0x00 }
+                    | 0x70)
             }
 
+            // 0xFF27-0xFF2F — Not used
+            0xff27..=0xff2f => 0xff,
+
             // 0xFF30-0xFF3F — Wave pattern RAM
-            0xff30..=0xff3f => self.wave_ram[addr as usize & 0x000f],
+            0xff30..=0xff3f => match self.wave_ram_index(addr) {
+                Some(index) => self.wave_ram[index],
+                None => 0xff,
+            },
+
+            // 0xFF76 — PCM12: Channel 1 & 2 output (CGB only)
+            0xff76 => match self.gb_mode {
+                GameBoyMode::Cgb => self.pcm12(),
+                _ => 0xff,
+            },
+            // 0xFF77 — PCM34: Channel 3 & 4 output (CGB only)
+            0xff77 => match self.gb_mode {
+                GameBoyMode::Cgb => self.pcm34(),
+                _ => 0xff,
+            },
 
             _ => {
                 warnln!("Reading from unknown APU location 0x{:04x}", addr);
@@ -408,26 +645,60 @@ impl Apu {
     }
 
     pub fn write(&mut self, addr: u16, value: u8) {
+        if let Some(vgm) = self.vgm.as_mut() {
+            vgm.log(addr, value);
+        }
+
+        // while the APU is powered off the registers are read-only,
+        // with the exception of the length timers on the DMG
+        if !self.sound_enabled && (0xff10..=0xff25).contains(&addr) {
+            if self.gb_mode != GameBoyMode::Cgb {
+                match addr {
+                    0xff11 => self.ch1_length_timer = 64 - (value & 0x3f) as u16,
+                    0xff16 => self.ch2_length_timer = 64 - (value & 0x3f) as u16,
+                    0xff1b => self.ch3_length_timer = 256 - value as u16,
+                    0xff20 => self.ch4_length_timer = 64 - (value & 0x3f) as u16,
+                    _ => (),
+                }
+            }
+            return;
+        }
+
         match addr {
             // 0xFF10 — NR10: Channel 1 sweep
             0xff10 => {
                 self.ch1_sweep_slope = value & 0x07;
                 self.ch1_sweep_increase = value & 0x08 == 0x00;
                 self.ch1_sweep_pace = (value & 0x70) >> 4;
-                self.ch1_sweep_sequence = 0;
+
+                // leaving the negate mode after a sweep calculation
+                // has been done in it disables the channel
+                if self.ch1_sweep_negated && self.ch1_sweep_increase {
+                    self.ch1_enabled = false;
+                }
             }
             // 0xFF11 — NR11: Channel 1 length timer & duty cycle
             0xff11 => {
-                self.ch1_length_timer = value & 0x3f;
+                self.ch1_length_timer = 64 - (value & 0x3f) as u16;
                 self.ch1_wave_duty = (value & 0xc0) >> 6;
             }
             // 0xFF12 — NR12: Channel 1 volume & envelope
             0xff12 => {
+                if self.ch1_enabled {
+                    self.ch1_volume = Self::zombie_volume(
+                        self.ch1_volume,
+                        self.ch1_pace,
+                        self.ch1_direction,
+                        self.ch1_envelope_enabled,
+                        value,
+                    );
+                }
                 self.ch1_pace = value & 0x07;
                 self.ch1_direction = (value & 0x08) >> 3;
-                self.ch1_volume = (value & 0xf0) >> 4;
-                self.ch1_envelope_enabled = self.ch1_pace > 0;
-                self.ch1_envelope_sequence = 0;
+                self.ch1_start_volume = (value & 0xf0) >> 4;
+                if value & 0xf8 == 0x00 {
+                    self.ch1_enabled = false;
+                }
             }
             // 0xFF13 — NR13: Channel 1 wavelength low
             0xff13 => {
@@ -437,32 +708,43 @@ impl Apu {
             0xff14 => {
                 let length_trigger = value & 0x40 == 0x40;
                 let trigger = value & 0x80 == 0x80;
+                let extra_clock = self.length_extra_clock();
                 self.ch1_wave_length =
                     (self.ch1_wave_length & 0x00ff) | (((value & 0x07) as u16) << 8);
-                self.ch1_length_stop = value & 0x40 == 0x40;
-                self.ch1_enabled |= value & 0x80 == 0x80;
+                if extra_clock && length_trigger && !self.ch1_length_stop {
+                    self.ch1_length_stop = true;
+                    self.tick_length(Channel::Ch1);
+                }
+                self.ch1_length_stop = length_trigger;
                 if trigger {
                     self.trigger_ch1();
                 }
-                if (length_trigger || trigger) && self.ch1_length_timer == 0 {
-                    self.ch1_length_timer = 0;
-                }
             }
 
             // 0xFF15 — Not used
             0xff15 => (),
             // 0xFF16 — NR21: Channel 2 length timer & duty cycle
             0xff16 => {
-                self.ch2_length_timer = value & 0x3f;
+                self.ch2_length_timer = 64 - (value & 0x3f) as u16;
                 self.ch2_wave_duty = (value & 0xc0) >> 6;
             }
             // 0xFF17 — NR22: Channel 2 volume & envelope
             0xff17 => {
+                if self.ch2_enabled {
+                    self.ch2_volume = Self::zombie_volume(
+                        self.ch2_volume,
+                        self.ch2_pace,
+                        self.ch2_direction,
+                        self.ch2_envelope_enabled,
+                        value,
+                    );
+                }
                 self.ch2_pace = value & 0x07;
                 self.ch2_direction = (value & 0x08) >> 3;
-                self.ch2_volume = (value & 0xf0) >> 4;
-                self.ch2_envelope_enabled = self.ch2_pace > 0;
-                self.ch2_envelope_sequence = 0;
+                self.ch2_start_volume = (value & 0xf0) >> 4;
+                if value & 0xf8 == 0x00 {
+                    self.ch2_enabled = false;
+                }
             }
             // 0xFF18 — NR23: Channel 2 wavelength low
             0xff18 => {
@@ -472,25 +754,29 @@ impl Apu {
             0xff19 => {
                 let length_trigger = value & 0x40 == 0x40;
                 let trigger = value & 0x80 == 0x80;
+                let extra_clock = self.length_extra_clock();
                 self.ch2_wave_length =
                     (self.ch2_wave_length & 0x00ff) | (((value & 0x07) as u16) << 8);
+                if extra_clock && length_trigger && !self.ch2_length_stop {
+                    self.ch2_length_stop = true;
+                    self.tick_length(Channel::Ch2);
+                }
                 self.ch2_length_stop = length_trigger;
-                self.ch2_enabled |= trigger;
                 if trigger {
                     self.trigger_ch2();
                 }
-                if (length_trigger || trigger) && self.ch2_length_timer == 0 {
-                    self.ch2_length_timer = 0;
-                }
             }
 
             // 0xFF1A — NR30: Channel 3 DAC enable
             0xff1a => {
                 self.ch3_dac = value & 0x80 == 0x80;
+                if !self.ch3_dac {
+                    self.ch3_enabled = false;
+                }
             }
             // 0xFF1B — NR31: Channel 3 length timer
             0xff1b => {
-                self.ch3_length_timer = value;
+                self.ch3_length_timer = 256 - value as u16;
             }
             // 0xFF1C — NR32: Channel 3 output level
             0xff1c => {
@@ -504,31 +790,47 @@ impl Apu {
             0xff1e => {
                 let length_trigger = value & 0x40 == 0x40;
                 let trigger = value & 0x80 == 0x80;
+                let extra_clock = self.length_extra_clock();
                 self.ch3_wave_length =
                     (self.ch3_wave_length & 0x00ff) | (((value & 0x07) as u16) << 8);
+                if extra_clock && length_trigger && !self.ch3_length_stop {
+                    self.ch3_length_stop = true;
+                    self.tick_length(Channel::Ch3);
+                }
                 self.ch3_length_stop = length_trigger;
-                self.ch3_enabled |= trigger;
                 if trigger {
+                    // re-triggering the channel on the DMG while it's
+                    // reading a sample corrupts the wave RAM
+                    if self.ch3_enabled && self.gb_mode != GameBoyMode::Cgb && self.ch3_timer <= 2 {
+                        self.corrupt_wave_ram();
+                    }
                     self.trigger_ch3();
                 }
-                if (length_trigger || trigger) && self.ch3_length_timer == 0 {
-                    self.ch3_length_timer = 0;
-                }
             }
 
             // 0xFF1F — Not used
             0xff1f => (),
             // 0xFF20 — NR41: Channel 4 length timer
             0xff20 => {
-                self.ch4_length_timer = value & 0x3f;
+                self.ch4_length_timer = 64 - (value & 0x3f) as u16;
             }
             // 0xFF21 — NR42: Channel 4 volume & envelope
             0xff21 => {
+                if self.ch4_enabled {
+                    self.ch4_volume = Self::zombie_volume(
+                        self.ch4_volume,
+                        self.ch4_pace,
+                        self.ch4_direction,
+                        self.ch4_envelope_enabled,
+                        value,
+                    );
+                }
                 self.ch4_pace = value & 0x07;
                 self.ch4_direction = (value & 0x08) >> 3;
-                self.ch4_volume = (value & 0xf0) >> 4;
-                self.ch4_envelope_enabled = self.ch4_pace > 0;
-                self.ch4_envelope_sequence = 0;
+                self.ch4_start_volume = (value & 0xf0) >> 4;
+                if value & 0xf8 == 0x00 {
+                    self.ch4_enabled = false;
+                }
             }
             // 0xFF22 — NR43: Channel 4 frequency & randomness
             0xff22 => {
@@ -540,19 +842,23 @@ impl Apu {
             0xff23 => {
                 let length_trigger = value & 0x40 == 0x40;
                 let trigger = value & 0x80 == 0x80;
+                let extra_clock = self.length_extra_clock();
+                if extra_clock && length_trigger && !self.ch4_length_stop {
+                    self.ch4_length_stop = true;
+                    self.tick_length(Channel::Ch4);
+                }
                 self.ch4_length_stop = length_trigger;
-                self.ch4_enabled |= trigger;
                 if trigger {
                     self.trigger_ch4();
                 }
-                if (length_trigger || trigger) && self.ch4_length_timer == 0 {
-                    self.ch4_length_timer = 0;
-                }
             }
 
             // 0xFF24 — NR50: Master volume & VIN panning
             0xff24 => {
-                //@TODO: Implement master volume & VIN panning
+                self.glob_volume_right = value & 0x07;
+                self.glob_vin_right = value & 0x08 == 0x08;
+                self.glob_volume_left = (value & 0x70) >> 4;
+                self.glob_vin_left = value & 0x80 == 0x80;
             }
             // 0xFF25 — NR51: Sound panning
             0xff25 => {
@@ -560,27 +866,73 @@ impl Apu {
             }
             // 0xFF26 — NR52: Sound on/off
             0xff26 => {
-                self.sound_enabled = value & 0x80 == 0x80;
-                if !self.sound_enabled {
-                    self.reset();
-                    self.sound_enabled = false;
+                let sound_enabled = value & 0x80 == 0x80;
+                if !sound_enabled && self.sound_enabled {
+                    self.power_off();
                 }
+                if sound_enabled && !self.sound_enabled {
+                    self.power_on();
+                }
+                self.sound_enabled = sound_enabled;
             }
 
+            // 0xFF27-0xFF2F — Not used
+            0xff27..=0xff2f => (),
+
             // 0xFF30-0xFF3F — Wave pattern RAM
             0xff30..=0xff3f => {
-                self.wave_ram[addr as usize & 0x000f] = value;
+                if let Some(index) = self.wave_ram_index(addr) {
+                    self.wave_ram[index] = value;
+                }
             }
 
             _ => warnln!("Writing in unknown APU location 0x{:04x}", addr),
         }
     }
 
+    /// Obtains the (mono) output of the APU as the sum of the
+    /// output of the channels, ignoring both panning and volume.
     #[inline(always)]
     pub fn output(&self) -> u8 {
         self.ch1_output() + self.ch2_output() + self.ch3_output() + self.ch4_output()
     }
 
+    /// Obtains the output of the left terminal, mixing the channels
+    /// routed to it (NR51) and applying the master volume (NR50).
+    #[inline(always)]
+    pub fn output_left(&self) -> u8 {
+        self.mix(self.glob_panning >> 4, self.glob_volume_left)
+    }
+
+    /// Obtains the output of the right terminal, mixing the channels
+    /// routed to it (NR51) and applying the master volume (NR50).
+    #[inline(always)]
+    pub fn output_right(&self) -> u8 {
+        self.mix(self.glob_panning & 0x0f, self.glob_volume_right)
+    }
+
+    /// Mixes the output of the channels selected in the provided
+    /// panning nibble (bit 0 for channel 1 up to bit 3 for channel 4),
+    /// scaling the result by the master volume (from 0 to 7), so
+    /// that the maximum volume keeps the range of the mono output.
+    #[inline(always)]
+    fn mix(&self, panning: u8, volume: u8) -> u8 {
+        let mut output = 0u16;
+        if panning & 0x01 == 0x01 {
+            output += self.ch1_output() as u16;
+        }
+        if panning & 0x02 == 0x02 {
+            output += self.ch2_output() as u16;
+        }
+        if panning & 0x04 == 0x04 {
+            output += self.ch3_output() as u16;
+        }
+        if panning & 0x08 == 0x08 {
+            output += self.ch4_output() as u16;
+        }
+        (output * (volume as u16 + 1) / 8) as u8
+    }
+
     #[inline(always)]
     pub fn ch1_output(&self) -> u8 {
         if self.ch1_out_enabled {
@@ -617,6 +969,18 @@ impl Apu {
         }
     }
 
+    /// Obtains the value of the PCM12 register (CGB only), with the
+    /// current digital output of channels 1 (low) and 2 (high).
+    pub fn pcm12(&self) -> u8 {
+        (self.ch2_output << 4) | self.ch1_output
+    }
+
+    /// Obtains the value of the PCM34 register (CGB only), with the
+    /// current digital output of channels 3 (low) and 4 (high).
+    pub fn pcm34(&self) -> u8 {
+        (self.ch4_output << 4) | self.ch3_output
+    }
+
     pub fn ch1_enabled(&mut self) -> bool {
         self.ch1_out_enabled
     }
@@ -649,14 +1013,75 @@ impl Apu {
         self.ch4_out_enabled = enabled;
     }
 
-    pub fn audio_buffer(&self) -> &VecDeque<u8> {
+    pub fn audio_buffer(&self) -> &VecDeque<i16> {
         &self.audio_buffer
     }
 
-    pub fn audio_buffer_mut(&mut self) -> &mut VecDeque<u8> {
+    pub fn audio_buffer_mut(&mut self) -> &mut VecDeque<i16> {
         &mut self.audio_buffer
     }
 
+    pub fn recorder(&self) -> Option<&WavRecorder> {
+        self.recorder.as_ref()
+    }
+
+    pub fn recorder_enabled(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Starts recording the mixed audio output (and optionally the
+    /// output of each channel as stems) into a WAV recorder, in case
+    /// there's already a recording in progress it's kept.
+    pub fn enable_recorder(&mut self, stems: bool) {
+        if self.recorder.is_none() {
+            self.recorder = Some(WavRecorder::new(
+                self.sampling_rate as u32,
+                self.clock_freq,
+                self.high_pass_left.factor(),
+                SAMPLE_SCALE,
+                stems,
+            ));
+            self.update_rates();
+        }
+    }
+
+    /// Stops the current recording returning the recorder with
+    /// the captured audio, if there was one in progress.
+    pub fn disable_recorder(&mut self) -> Option<WavRecorder> {
+        self.recorder.take()
+    }
+
+    pub fn vgm(&self) -> Option<&VgmLogger> {
+        self.vgm.as_ref()
+    }
+
+    pub fn vgm_enabled(&self) -> bool {
+        self.vgm.is_some()
+    }
+
+    /// Starts logging the writes to the APU registers for VGM
+    /// export, the current power, volume, panning and wave RAM
+    /// state is logged first so that playback starts from it.
+    pub fn enable_vgm(&mut self) {
+        if self.vgm.is_some() {
+            return;
+        }
+        let mut vgm = VgmLogger::new();
+        vgm.log(0xff26, if self.sound_enabled { 0x80 } else { 0x00 });
+        vgm.log(0xff24, self.read(0xff24));
+        vgm.log(0xff25, self.read(0xff25));
+        for (index, value) in self.wave_ram.iter().enumerate() {
+            vgm.log(0xff30 + index as u16, *value);
+        }
+        self.vgm = Some(vgm);
+    }
+
+    /// Stops the logging of the APU register writes, returning
+    /// the logger with the captured writes.
+    pub fn disable_vgm(&mut self) -> Option<VgmLogger> {
+        self.vgm.take()
+    }
+
     pub fn clear_audio_buffer(&mut self) {
         self.audio_buffer.clear();
     }
@@ -667,6 +1092,146 @@ impl Apu {
 
     pub fn set_clock_freq(&mut self, value: u32) {
         self.clock_freq = value;
+        self.update_rates();
+    }
+
+    pub fn sampling_rate(&self) -> u16 {
+        self.sampling_rate
+    }
+
+    pub fn rate_adjust(&self) -> f32 {
+        self.rate_adjust
+    }
+
+    /// Sets the multiplier applied to the sampling rate when
+    /// generating samples, limited to the allowed deviation.
+    pub fn set_rate_adjust(&mut self, value: f32) {
+        self.rate_adjust = value.clamp(1.0 - RATE_CONTROL_DELTA, 1.0 + RATE_CONTROL_DELTA);
+        self.update_rates();
+    }
+
+    /// Dynamic rate control, adjusts the effective sampling rate
+    /// according to the fill level of the host audio queue so that
+    /// the emulated audio stays in sync with the video (and the host
+    /// display refresh rate), without crackling or drifting.
+    ///
+    /// The `queued` value is the number of sample frames (one per
+    /// channel) pending in the host queue and `target` the number
+    /// of frames that the queue should ideally hold, an empty queue
+    /// slightly increases the rate and a full one decreases it.
+    pub fn rate_control(&mut self, queued: usize, target: usize) -> f32 {
+        let fill = queued as f32 / target.max(1) as f32;
+        self.set_rate_adjust(1.0 + RATE_CONTROL_DELTA * (1.0 - fill));
+        self.rate_adjust
+    }
+
+    pub fn gb_mode(&self) -> GameBoyMode {
+        self.gb_mode
+    }
+
+    pub fn set_gb_mode(&mut self, value: GameBoyMode) {
+        self.gb_mode = value;
+        self.update_rates();
+    }
+
+    /// Updates the band-limited buffers and the high-pass filters
+    /// so that they match the current clock frequency, sampling
+    /// rate and model (the charge of the capacitor is different).
+    fn update_rates(&mut self) {
+        let sampling_rate = self.sampling_rate as u32;
+        let factor =
+            HighPass::from_charge(Self::charge(self.gb_mode), self.clock_freq, sampling_rate)
+                .factor();
+        let ratio = sampling_rate as f64 / self.clock_freq as f64;
+        self.blip_left.set_ratio(ratio * self.rate_adjust as f64);
+        self.blip_right.set_ratio(ratio * self.rate_adjust as f64);
+        self.high_pass_left.set_factor(factor);
+        self.high_pass_right.set_factor(factor);
+
+        // the recording is kept at the nominal rate, not being
+        // affected by the rate control of the host audio output
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.set_rates(ratio, factor);
+        }
+    }
+
+    fn charge(mode: GameBoyMode) -> f64 {
+        match mode {
+            GameBoyMode::Cgb => CGB_CHARGE,
+            _ => DMG_CHARGE,
+        }
+    }
+
+    #[inline(always)]
+    fn to_sample(value: f32) -> i16 {
+        (value * SAMPLE_SCALE).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }
+
+    /// Whether the next step of the frame sequencer is one that does
+    /// not clock the length timers, in which case enabling the length
+    /// of a channel (NRx4) clocks its timer once right away.
+    #[inline(always)]
+    fn length_extra_clock(&self) -> bool {
+        self.sequencer_step & 0x01 == 0x01
+    }
+
+    /// Obtains the length timer value of a channel on trigger, an
+    /// expired timer is reloaded with the maximum length (minus one
+    /// in case the extra length clock applies).
+    #[inline(always)]
+    fn length_reload(&self, timer: u16, length_stop: bool, max: u16) -> u16 {
+        if timer > 0 {
+            timer
+        } else if length_stop && self.length_extra_clock() {
+            max - 1
+        } else {
+            max
+        }
+    }
+
+    /// Emulates the "zombie mode" of the envelope, in which writing
+    /// the volume & envelope register (NRx2) while the channel is
+    /// playing changes the current volume in an unusual way.
+    fn zombie_volume(volume: u8, pace: u8, direction: u8, running: bool, value: u8) -> u8 {
+        let mut volume = volume;
+        if pace == 0x0 && running {
+            volume += 1;
+        } else if direction == 0x00 {
+            volume += 2;
+        }
+        if direction != (value & 0x08) >> 3 {
+            volume = 16u8.wrapping_sub(volume);
+        }
+        volume & 0x0f
+    }
+
+    /// Obtains the index of the wave RAM byte accessed by the CPU at
+    /// the given address, while channel 3 is playing the access goes
+    /// to the byte being read by the channel, which on the DMG is only
+    /// possible at the moment of the read (otherwise `None`).
+    #[inline(always)]
+    fn wave_ram_index(&self, addr: u16) -> Option<usize> {
+        if !self.ch3_enabled {
+            return Some(addr as usize & 0x000f);
+        }
+        if self.gb_mode == GameBoyMode::Cgb || self.ch3_fetch_age < 2 {
+            Some((self.ch3_position >> 1) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Corrupts the first bytes of the wave RAM, as happens on the DMG
+    /// when channel 3 is re-triggered while reading a sample, with them
+    /// being overwritten by the (aligned) bytes being read.
+    fn corrupt_wave_ram(&mut self) {
+        let index = (((self.ch3_position + 1) & 31) >> 1) as usize;
+        if index < 4 {
+            self.wave_ram[0] = self.wave_ram[index];
+        } else {
+            let base = index & 0x0c;
+            self.wave_ram.copy_within(base..base + 4, 0);
+        }
     }
 
     #[inline(always)]
@@ -681,34 +1246,39 @@ impl Apu {
     fn tick_length(&mut self, channel: Channel) {
         match channel {
             Channel::Ch1 => {
-                if !self.ch1_enabled {
+                if !self.ch1_length_stop || self.ch1_length_timer == 0 {
                     return;
                 }
-                self.ch1_length_timer = self.ch1_length_timer.saturating_add(1);
-                if self.ch1_length_timer >= 64 {
-                    self.ch1_enabled = !self.ch1_length_stop;
-                    self.ch1_length_timer = 0;
+                self.ch1_length_timer -= 1;
+                if self.ch1_length_timer == 0 {
+                    self.ch1_enabled = false;
                 }
             }
             Channel::Ch2 => {
-                self.ch2_length_timer = self.ch2_length_timer.saturating_add(1);
-                if self.ch2_length_timer >= 64 {
-                    self.ch2_enabled = !self.ch2_length_stop;
-                    self.ch2_length_timer = 0;
+                if !self.ch2_length_stop || self.ch2_length_timer == 0 {
+                    return;
+                }
+                self.ch2_length_timer -= 1;
+                if self.ch2_length_timer == 0 {
+                    self.ch2_enabled = false;
                 }
             }
             Channel::Ch3 => {
-                self.ch3_length_timer = self.ch3_length_timer.saturating_add(1);
-                if self.ch3_length_timer >= 64 {
-                    self.ch3_enabled = !self.ch3_length_stop;
-                    self.ch3_length_timer = 0;
+                if !self.ch3_length_stop || self.ch3_length_timer == 0 {
+                    return;
+                }
+                self.ch3_length_timer -= 1;
+                if self.ch3_length_timer == 0 {
+                    self.ch3_enabled = false;
                 }
             }
             Channel::Ch4 => {
-                self.ch4_length_timer = self.ch4_length_timer.saturating_add(1);
-                if self.ch4_length_timer >= 64 {
-                    self.ch4_enabled = !self.ch4_length_stop;
-                    self.ch4_length_timer = 0;
+                if !self.ch4_length_stop || self.ch4_length_timer == 0 {
+                    return;
+                }
+                self.ch4_length_timer -= 1;
+                if self.ch4_length_timer == 0 {
+                    self.ch4_enabled = false;
                 }
             }
         }
@@ -725,53 +1295,56 @@ impl Apu {
     fn tick_envelope(&mut self, channel: Channel) {
         match channel {
             Channel::Ch1 => {
-                if !self.ch1_enabled || !self.ch1_envelope_enabled {
+                if self.ch1_pace == 0x0 {
                     return;
                 }
                 self.ch1_envelope_sequence += 1;
                 if self.ch1_envelope_sequence >= self.ch1_pace {
-                    if self.ch1_direction == 0x01 {
-                        self.ch1_volume = self.ch1_volume.saturating_add(1);
-                    } else {
-                        self.ch1_volume = self.ch1_volume.saturating_sub(1);
-                    }
-                    if self.ch1_volume == 0 || self.ch1_volume == 15 {
-                        self.ch1_envelope_enabled = false;
+                    if self.ch1_envelope_enabled {
+                        if self.ch1_direction == 0x01 && self.ch1_volume < 15 {
+                            self.ch1_volume += 1;
+                        } else if self.ch1_direction == 0x00 && self.ch1_volume > 0 {
+                            self.ch1_volume -= 1;
+                        } else {
+                            self.ch1_envelope_enabled = false;
+                        }
                     }
                     self.ch1_envelope_sequence = 0;
                 }
             }
             Channel::Ch2 => {
-                if !self.ch2_enabled || !self.ch2_envelope_enabled {
+                if self.ch2_pace == 0x0 {
                     return;
                 }
                 self.ch2_envelope_sequence += 1;
                 if self.ch2_envelope_sequence >= self.ch2_pace {
-                    if self.ch2_direction == 0x01 {
-                        self.ch2_volume = self.ch2_volume.saturating_add(1);
-                    } else {
-                        self.ch2_volume = self.ch2_volume.saturating_sub(1);
-                    }
-                    if self.ch2_volume == 0 || self.ch2_volume == 15 {
-                        self.ch2_envelope_enabled = false;
+                    if self.ch2_envelope_enabled {
+                        if self.ch2_direction == 0x01 && self.ch2_volume < 15 {
+                            self.ch2_volume += 1;
+                        } else if self.ch2_direction == 0x00 && self.ch2_volume > 0 {
+                            self.ch2_volume -= 1;
+                        } else {
+                            self.ch2_envelope_enabled = false;
+                        }
                     }
                     self.ch2_envelope_sequence = 0;
                 }
             }
             Channel::Ch3 => (),
             Channel::Ch4 => {
-                if !self.ch4_enabled || !self.ch4_envelope_enabled {
+                if self.ch4_pace == 0x0 {
                     return;
                 }
                 self.ch4_envelope_sequence += 1;
                 if self.ch4_envelope_sequence >= self.ch4_pace {
-                    if self.ch4_direction == 0x01 {
-                        self.ch4_volume = self.ch4_volume.saturating_add(1);
-                    } else {
-                        self.ch4_volume = self.ch4_volume.saturating_sub(1);
-                    }
-                    if self.ch4_volume == 0 || self.ch4_volume == 15 {
-                        self.ch4_envelope_enabled = false;
+                    if self.ch4_envelope_enabled {
+                        if self.ch4_direction == 0x01 && self.ch4_volume < 15 {
+                            self.ch4_volume += 1;
+                        } else if self.ch4_direction == 0x00 && self.ch4_volume > 0 {
+                            self.ch4_volume -= 1;
+                        } else {
+                            self.ch4_envelope_enabled = false;
+                        }
                     }
                     self.ch4_envelope_sequence = 0;
                 }
@@ -781,26 +1354,54 @@ impl Apu {
 
     #[inline(always)]
     fn tick_ch1_sweep(&mut self) {
-        if self.ch1_sweep_pace == 0x0 {
+        self.ch1_sweep_sequence = self.ch1_sweep_sequence.saturating_sub(1);
+        if self.ch1_sweep_sequence > 0 {
             return;
         }
-        self.ch1_sweep_sequence += 1;
-        if self.ch1_sweep_sequence >= self.ch1_sweep_pace {
-            let divisor = 1u16 << self.ch1_sweep_slope as u16;
-            let delta = (self.ch1_wave_length as f32 / divisor as f32) as u16;
-            if self.ch1_sweep_increase {
-                self.ch1_wave_length = self.ch1_wave_length.saturating_add(delta);
-            } else {
-                self.ch1_wave_length = self.ch1_wave_length.saturating_sub(delta);
-            }
-            if self.ch1_wave_length > 0x07ff {
-                self.ch1_enabled = false;
-                self.ch1_wave_length = 0x07ff;
-            }
-            self.ch1_sweep_sequence = 0;
+        self.ch1_sweep_sequence = self.ch1_sweep_period();
+
+        if !self.ch1_sweep_enabled || self.ch1_sweep_pace == 0x0 {
+            return;
+        }
+        let wave_length = self.ch1_sweep_calculate();
+        if wave_length <= 0x07ff && self.ch1_sweep_slope > 0 {
+            self.ch1_sweep_shadow = wave_length;
+            self.ch1_wave_length = wave_length;
+
+            // runs the overflow check once more with the new
+            // value, without writing it back to the registers
+            self.ch1_sweep_calculate();
+        }
+    }
+
+    /// The number of sweep ticks (128Hz) between each of the sweep
+    /// iterations, with a pace of zero being handled as eight.
+    #[inline(always)]
+    fn ch1_sweep_period(&self) -> u8 {
+        if self.ch1_sweep_pace == 0x0 {
+            8
+        } else {
+            self.ch1_sweep_pace
         }
     }
 
+    /// Calculates the next wavelength of channel 1 using the shadow
+    /// value of the sweep, disabling the channel on overflow.
+    #[inline(always)]
+    fn ch1_sweep_calculate(&mut self) -> u16 {
+        let delta = self.ch1_sweep_shadow >> self.ch1_sweep_slope;
+        let wave_length = if self.ch1_sweep_increase {
+            self.ch1_sweep_shadow + delta
+        } else {
+            self.ch1_sweep_negated = true;
+            self.ch1_sweep_shadow.wrapping_sub(delta)
+        };
+        if wave_length > 0x07ff {
+            self.ch1_enabled = false;
+        }
+        wave_length
+    }
+
     #[inline(always)]
     fn tick_ch_all(&mut self, cycles: u16) {
         self.tick_ch1(cycles);
@@ -812,84 +1413,67 @@ impl Apu {
     #[inline(always)]
     fn tick_ch1(&mut self, cycles: u16) {
         self.ch1_timer = self.ch1_timer.saturating_sub(cycles as i16);
-        if self.ch1_timer > 0 {
-            return;
+        while self.ch1_timer <= 0 {
+            self.ch1_timer += ((2048 - self.ch1_wave_length) << 2) as i16;
+            self.ch1_sequence = (self.ch1_sequence + 1) & 7;
         }
 
-        if self.ch1_enabled {
-            self.ch1_output =
-                if DUTY_TABLE[self.ch1_wave_duty as usize][self.ch1_sequence as usize] == 1 {
-                    self.ch1_volume
-                } else {
-                    0
-                };
+        self.ch1_output = if self.ch1_enabled
+            && DUTY_TABLE[self.ch1_wave_duty as usize][self.ch1_sequence as usize] == 1
+        {
+            self.ch1_volume
         } else {
-            self.ch1_output = 0;
-        }
-
-        self.ch1_timer += ((2048 - self.ch1_wave_length) << 2) as i16;
-        self.ch1_sequence = (self.ch1_sequence + 1) & 7;
+            0
+        };
     }
 
     #[inline(always)]
     fn tick_ch2(&mut self, cycles: u16) {
         self.ch2_timer = self.ch2_timer.saturating_sub(cycles as i16);
-        if self.ch2_timer > 0 {
-            return;
+        while self.ch2_timer <= 0 {
+            self.ch2_timer += ((2048 - self.ch2_wave_length) << 2) as i16;
+            self.ch2_sequence = (self.ch2_sequence + 1) & 7;
         }
 
-        if self.ch2_enabled {
-            self.ch2_output =
-                if DUTY_TABLE[self.ch2_wave_duty as usize][self.ch2_sequence as usize] == 1 {
-                    self.ch2_volume
-                } else {
-                    0
-                };
+        self.ch2_output = if self.ch2_enabled
+            && DUTY_TABLE[self.ch2_wave_duty as usize][self.ch2_sequence as usize] == 1
+        {
+            self.ch2_volume
         } else {
-            self.ch2_output = 0;
-        }
-
-        self.ch2_timer += ((2048 - self.ch2_wave_length) << 2) as i16;
-        self.ch2_sequence = (self.ch2_sequence + 1) & 7;
+            0
+        };
     }
 
     #[inline(always)]
     fn tick_ch3(&mut self, cycles: u16) {
+        self.ch3_fetch_age = self.ch3_fetch_age.saturating_add(cycles);
         self.ch3_timer = self.ch3_timer.saturating_sub(cycles as i16);
-        if self.ch3_timer > 0 {
-            return;
-        }
-
-        if self.ch3_enabled && self.ch3_dac {
-            let wave_index = self.ch3_position >> 1;
-            let mut output = self.wave_ram[wave_index as usize];
-            output = if (self.ch3_position & 0x01) == 0x01 {
-                output & 0x0f
+        while self.ch3_timer <= 0 {
+            self.ch3_position = (self.ch3_position + 1) & 31;
+            let sample = self.wave_ram[(self.ch3_position >> 1) as usize];
+            self.ch3_sample = if (self.ch3_position & 0x01) == 0x01 {
+                sample & 0x0f
             } else {
-                (output & 0xf0) >> 4
+                (sample & 0xf0) >> 4
             };
-            if self.ch3_output_level > 0 {
-                output >>= self.ch3_output_level - 1;
-            } else {
-                output = 0;
-            }
-            self.ch3_output = output;
-        } else {
-            self.ch3_output = 0;
+
+            // keeps track of the number of cycles since the
+            // last read of the wave RAM (DMG access window)
+            self.ch3_fetch_age = (-self.ch3_timer) as u16;
+            self.ch3_timer += ((2048 - self.ch3_wave_length) << 1) as i16;
         }
 
-        self.ch3_timer += ((2048 - self.ch3_wave_length) << 1) as i16;
-        self.ch3_position = (self.ch3_position + 1) & 31;
+        self.ch3_output = if self.ch3_enabled && self.ch3_output_level > 0 {
+            self.ch3_sample >> (self.ch3_output_level - 1)
+        } else {
+            0
+        };
     }
 
     #[inline(always)]
     fn tick_ch4(&mut self, cycles: u16) {
         self.ch4_timer = self.ch4_timer.saturating_sub(cycles as i32);
-        if self.ch4_timer > 0 {
-            return;
-        }
-
-        if self.ch4_enabled {
+        while self.ch4_timer <= 0 {
             // obtains the current value of the LFSR based as
             // the XOR of the 1st and 2nd bit of the LFSR
             let result = ((self.ch4_lfsr & 0x0001) ^ ((self.ch4_lfsr >> 1) & 0x0001)) == 0x0001;
@@ -902,50 +1486,158 @@ impl Apu {
             // in case the short width mode (7 bits) is set then
             // the 6th bit will be set to value of the 15th bit
             if self.ch4_width_mode {
-                self.ch4_lfsr &= 0xbf;
+                self.ch4_lfsr &= !0x40;
                 self.ch4_lfsr |= if result { 0x40 } else { 0x00 };
             }
 
-            self.ch4_output = if result { self.ch4_volume } else { 0 };
-        } else {
-            self.ch4_output = 0;
+            self.ch4_timer +=
+                (CH4_DIVISORS[self.ch4_divisor as usize] as i32) << self.ch4_clock_shift;
         }
 
-        self.ch4_timer +=
-            ((CH4_DIVISORS[self.ch4_divisor as usize] as u16) << self.ch4_clock_shift) as i32;
+        self.ch4_output = if self.ch4_enabled && self.ch4_lfsr & 0x0001 == 0x0000 {
+            self.ch4_volume
+        } else {
+            0
+        };
     }
 
     #[inline(always)]
     fn trigger_ch1(&mut self) {
+        self.ch1_enabled = self.ch1_start_volume > 0 || self.ch1_direction > 0;
+        self.ch1_length_timer = self.length_reload(self.ch1_length_timer, self.ch1_length_stop, 64);
         self.ch1_timer = ((2048 - self.ch1_wave_length) << 2) as i16;
+        self.ch1_volume = self.ch1_start_volume;
+        self.ch1_envelope_enabled = true;
         self.ch1_envelope_sequence = 0;
-        self.ch1_sweep_sequence = 0;
+
+        // reloads the sweep unit from the current wavelength running
+        // the overflow check right away in case there's a slope
+        self.ch1_sweep_sequence = self.ch1_sweep_period();
+        self.ch1_sweep_shadow = self.ch1_wave_length;
+        self.ch1_sweep_enabled = self.ch1_sweep_pace > 0 || self.ch1_sweep_slope > 0;
+        self.ch1_sweep_negated = false;
+        if self.ch1_sweep_slope > 0 {
+            self.ch1_sweep_calculate();
+        }
     }
 
     #[inline(always)]
     fn trigger_ch2(&mut self) {
+        self.ch2_enabled = self.ch2_start_volume > 0 || self.ch2_direction > 0;
+        self.ch2_length_timer = self.length_reload(self.ch2_length_timer, self.ch2_length_stop, 64);
         self.ch2_timer = ((2048 - self.ch2_wave_length) << 2) as i16;
+        self.ch2_volume = self.ch2_start_volume;
+        self.ch2_envelope_enabled = true;
         self.ch2_envelope_sequence = 0;
     }
 
     #[inline(always)]
     fn trigger_ch3(&mut self) {
-        self.ch3_timer = 3;
+        self.ch3_enabled = self.ch3_dac;
+        self.ch3_length_timer =
+            self.length_reload(self.ch3_length_timer, self.ch3_length_stop, 256);
+        self.ch3_timer = ((2048 - self.ch3_wave_length) << 1) as i16 + CH3_DELAY;
         self.ch3_position = 0;
     }
 
     #[inline(always)]
     fn trigger_ch4(&mut self) {
-        self.ch4_timer =
-            ((CH4_DIVISORS[self.ch4_divisor as usize] as u16) << self.ch4_clock_shift) as i32;
+        self.ch4_enabled = self.ch4_start_volume > 0 || self.ch4_direction > 0;
+        self.ch4_length_timer = self.length_reload(self.ch4_length_timer, self.ch4_length_stop, 64);
+        self.ch4_timer = (CH4_DIVISORS[self.ch4_divisor as usize] as i32) << self.ch4_clock_shift;
         self.ch4_lfsr = 0x7ff1;
+        self.ch4_volume = self.ch4_start_volume;
+        self.ch4_envelope_enabled = true;
         self.ch4_envelope_sequence = 0;
     }
 }
 
+/// Only the state of the sound chip is serialized, the output
+/// (resampling and filtering) state and buffers are kept.
+impl StateComponent for Apu {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.i16(&mut self.ch1_timer);
+        s.u8(&mut self.ch1_sequence);
+        s.u8(&mut self.ch1_envelope_sequence);
+        s.bool(&mut self.ch1_envelope_enabled);
+        s.u8(&mut self.ch1_sweep_sequence);
+        s.u8(&mut self.ch1_output);
+        s.u8(&mut self.ch1_sweep_slope);
+        s.bool(&mut self.ch1_sweep_increase);
+        s.u8(&mut self.ch1_sweep_pace);
+        s.bool(&mut self.ch1_sweep_enabled);
+        s.u16(&mut self.ch1_sweep_shadow);
+        s.bool(&mut self.ch1_sweep_negated);
+        s.u16(&mut self.ch1_length_timer);
+        s.u8(&mut self.ch1_wave_duty);
+        s.u8(&mut self.ch1_pace);
+        s.u8(&mut self.ch1_direction);
+        s.u8(&mut self.ch1_start_volume);
+        s.u8(&mut self.ch1_volume);
+        s.u16(&mut self.ch1_wave_length);
+        s.bool(&mut self.ch1_length_stop);
+        s.bool(&mut self.ch1_enabled);
+        s.i16(&mut self.ch2_timer);
+        s.u8(&mut self.ch2_sequence);
+        s.u8(&mut self.ch2_envelope_sequence);
+        s.bool(&mut self.ch2_envelope_enabled);
+        s.u8(&mut self.ch2_output);
+        s.u16(&mut self.ch2_length_timer);
+        s.u8(&mut self.ch2_wave_duty);
+        s.u8(&mut self.ch2_pace);
+        s.u8(&mut self.ch2_direction);
+        s.u8(&mut self.ch2_start_volume);
+        s.u8(&mut self.ch2_volume);
+        s.u16(&mut self.ch2_wave_length);
+        s.bool(&mut self.ch2_length_stop);
+        s.bool(&mut self.ch2_enabled);
+        s.i16(&mut self.ch3_timer);
+        s.u8(&mut self.ch3_position);
+        s.u8(&mut self.ch3_output);
+        s.u8(&mut self.ch3_sample);
+        s.u16(&mut self.ch3_fetch_age);
+        s.bool(&mut self.ch3_dac);
+        s.u16(&mut self.ch3_length_timer);
+        s.u8(&mut self.ch3_output_level);
+        s.u16(&mut self.ch3_wave_length);
+        s.bool(&mut self.ch3_length_stop);
+        s.bool(&mut self.ch3_enabled);
+        s.i32(&mut self.ch4_timer);
+        s.u8(&mut self.ch4_envelope_sequence);
+        s.bool(&mut self.ch4_envelope_enabled);
+        s.u8(&mut self.ch4_output);
+        s.u16(&mut self.ch4_length_timer);
+        s.u8(&mut self.ch4_pace);
+        s.u8(&mut self.ch4_direction);
+        s.u8(&mut self.ch4_start_volume);
+        s.u8(&mut self.ch4_volume);
+        s.u8(&mut self.ch4_divisor);
+        s.bool(&mut self.ch4_width_mode);
+        s.u8(&mut self.ch4_clock_shift);
+        s.u16(&mut self.ch4_lfsr);
+        s.bool(&mut self.ch4_length_stop);
+        s.bool(&mut self.ch4_enabled);
+        s.u8(&mut self.glob_panning);
+        s.u8(&mut self.glob_volume_left);
+        s.u8(&mut self.glob_volume_right);
+        s.bool(&mut self.glob_vin_left);
+        s.bool(&mut self.glob_vin_right);
+        s.bool(&mut self.right_enabled);
+        s.bool(&mut self.left_enabled);
+        s.bool(&mut self.sound_enabled);
+        s.bool(&mut self.ch1_out_enabled);
+        s.bool(&mut self.ch2_out_enabled);
+        s.bool(&mut self.ch3_out_enabled);
+        s.bool(&mut self.ch4_out_enabled);
+        s.bytes(&mut self.wave_ram);
+        s.u16(&mut self.sequencer);
+        s.u8(&mut self.sequencer_step);
+    }
+}
+
 impl Default for Apu {
     fn default() -> Self {
-        Self::new(44100, 1.0, GameBoy::CPU_FREQ)
+        Self::new(GameBoyMode::Dmg, 44100, 1.0, GameBoy::CPU_FREQ)
     }
 }
 
@@ -963,7 +1655,7 @@ mod tests {
 
         assert_eq!(apu.ch1_timer, 4096);
         assert_eq!(apu.ch1_envelope_sequence, 0);
-        assert_eq!(apu.ch1_sweep_sequence, 0);
+        assert_eq!(apu.ch1_sweep_sequence, 8);
     }
 
     #[test]
@@ -986,7 +1678,7 @@ mod tests {
         };
         apu.trigger_ch3();
 
-        assert_eq!(apu.ch3_timer, 3);
+        assert_eq!(apu.ch3_timer, 2054);
         assert_eq!(apu.ch3_position, 0);
     }
 
@@ -1003,4 +1695,222 @@ mod tests {
         assert_eq!(apu.ch4_lfsr, 0x7ff1);
         assert_eq!(apu.ch4_envelope_sequence, 0);
     }
+
+    #[test]
+    fn test_registers_power() {
+        let mut apu = Apu::default();
+        assert_eq!(apu.read(0xff11), 0x3f);
+        assert_eq!(apu.read(0xff13), 0xff);
+        assert_eq!(apu.read(0xff1c), 0x9f);
+        assert_eq!(apu.read(0xff27), 0xff);
+        assert_eq!(apu.read(0xff76), 0xff);
+
+        apu.write(0xff12, 0xf3);
+        apu.write(0xff14, 0xc0);
+        apu.write(0xff30, 0x12);
+        assert_eq!(apu.read(0xff12), 0xf3);
+        assert_eq!(apu.read(0xff14), 0xff);
+        assert_eq!(apu.read(0xff26), 0xf1);
+
+        // powering off clears the registers (but not the wave RAM)
+        // and ignores the writes, except for the DMG length timers
+        apu.write(0xff26, 0x00);
+        assert_eq!(apu.read(0xff26), 0x70);
+        assert_eq!(apu.read(0xff12), 0x00);
+        assert_eq!(apu.read(0xff30), 0x12);
+        apu.write(0xff12, 0xf0);
+        apu.write(0xff11, 0x3e);
+        assert_eq!(apu.read(0xff12), 0x00);
+        assert_eq!(apu.ch1_length_timer, 2);
+
+        // disabling the DAC of a playing channel disables it
+        apu.write(0xff26, 0x80);
+        apu.write(0xff12, 0xf0);
+        apu.write(0xff14, 0x80);
+        assert_eq!(apu.read(0xff26), 0xf1);
+        apu.write(0xff12, 0x00);
+        assert_eq!(apu.read(0xff26), 0xf0);
+    }
+
+    #[test]
+    fn test_zombie_volume() {
+        assert_eq!(Apu::zombie_volume(5, 0, 1, true, 0x08), 6);
+        assert_eq!(Apu::zombie_volume(5, 3, 0, true, 0x00), 7);
+        assert_eq!(Apu::zombie_volume(5, 3, 1, true, 0x00), 11);
+        assert_eq!(Apu::zombie_volume(15, 3, 0, true, 0x00), 1);
+    }
+
+    #[test]
+    fn test_pcm() {
+        let mut apu = Apu {
+            ch1_output: 0x03,
+            ch2_output: 0x0a,
+            ch3_output: 0x0f,
+            ch4_output: 0x01,
+            ..Default::default()
+        };
+        assert_eq!(apu.read(0xff76), 0xff);
+        apu.set_gb_mode(GameBoyMode::Cgb);
+        assert_eq!(apu.read(0xff76), 0xa3);
+        assert_eq!(apu.read(0xff77), 0x1f);
+    }
+
+    #[test]
+    fn test_stereo_output() {
+        let mut apu = Apu {
+            ch1_output: 15,
+            ch2_output: 10,
+            ..Default::default()
+        };
+        apu.write(0xff24, 0x73);
+        apu.write(0xff25, 0x12);
+        assert_eq!(apu.read(0xff24), 0x73);
+        assert_eq!(apu.read(0xff25), 0x12);
+
+        // channel 1 is routed to the left terminal at full volume
+        // and channel 2 to the right terminal at half volume
+        assert_eq!(apu.output_left(), 15);
+        assert_eq!(apu.output_right(), 5);
+        assert_eq!(apu.output(), 25);
+
+        apu.write(0xff25, 0x33);
+        assert_eq!(apu.output_left(), 25);
+        assert_eq!(apu.output_right(), 12);
+    }
+
+    #[test]
+    fn test_boot_skipped_output() {
+        // triggers channel 2 (LD A, n ; LDH (n), A) never writing the
+        // NR50 and NR51 registers, relying on their post boot values
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100..0x010e].copy_from_slice(&[
+            0x3e, 0x80, 0xe0, 0x16, // NR21 (50% duty)
+            0x3e, 0xf0, 0xe0, 0x17, // NR22 (max volume)
+            0x3e, 0x87, 0xe0, 0x19, // NR24 (trigger)
+            0x18, 0xfe, // JR -2
+        ]);
+        let mut game_boy = GameBoy::new(Some(GameBoyMode::Dmg));
+        game_boy.load(false);
+        game_boy.load_rom(&rom);
+        game_boy.boot();
+        assert_eq!(game_boy.mmu().read(0xff24), 0x77);
+        assert_eq!(game_boy.mmu().read(0xff25), 0xf3);
+
+        game_boy.run_frame();
+        assert!(game_boy
+            .apu_i()
+            .audio_buffer()
+            .iter()
+            .any(|sample| *sample != 0));
+    }
+
+    #[test]
+    fn test_band_limited_output() {
+        let mut apu = Apu::default();
+        apu.write(0xff24, 0x77);
+        apu.write(0xff25, 0x11);
+        apu.write(0xff11, 0x80);
+        apu.write(0xff12, 0xf0);
+        apu.write(0xff13, 0x06);
+        apu.write(0xff14, 0x87);
+        for _ in 0..GameBoy::CPU_FREQ / 16 {
+            apu.clock(16);
+        }
+
+        // the square wave swings around zero once the high-pass
+        // filter has removed the DC offset of the channel output
+        let buffer = apu.audio_buffer();
+        let tail = buffer.iter().skip(buffer.len() / 2).map(|v| *v as i64);
+        let average = tail.clone().sum::<i64>() / tail.clone().count() as i64;
+        assert_eq!(buffer.len(), 44100 * 2);
+        assert!(tail.clone().any(|sample| sample > 1000));
+        assert!(tail.clone().any(|sample| sample < -1000));
+        assert!(average.abs() < 100);
+
+        // the CGB capacitor charges faster than the DMG one
+        let mut apu_cgb = Apu::default();
+        apu_cgb.set_gb_mode(GameBoyMode::Cgb);
+        assert!(apu_cgb.high_pass_left.factor() < apu.high_pass_left.factor());
+    }
+
+    #[test]
+    fn test_rate_control() {
+        let mut apu = Apu::default();
+        assert_eq!(apu.rate_control(0, 4096), 1.005);
+        assert_eq!(apu.rate_control(4096, 4096), 1.0);
+        assert_eq!(apu.rate_control(8192, 4096), 0.995);
+        assert_eq!(apu.rate_control(65536, 4096), 0.995);
+
+        // a starving host queue generates more samples for the
+        // same amount of emulated time
+        apu.write(0xff25, 0x11);
+        apu.set_rate_adjust(1.005);
+        for _ in 0..GameBoy::CPU_FREQ / 32 {
+            apu.clock(16);
+        }
+        assert_eq!(apu.audio_buffer().len(), 22160 * 2);
+    }
+
+    #[test]
+    fn test_recorder() {
+        let mut apu = Apu::default();
+        apu.enable_recorder(true);
+        apu.write(0xff24, 0x77);
+        apu.write(0xff25, 0x11);
+        apu.write(0xff11, 0x80);
+        apu.write(0xff12, 0xf0);
+        apu.write(0xff13, 0x06);
+        apu.write(0xff14, 0x87);
+        for _ in 0..GameBoy::CPU_FREQ / 64 {
+            apu.clock(16);
+        }
+
+        let recorder = apu.disable_recorder().unwrap();
+        assert!(!apu.recorder_enabled());
+        assert_eq!(recorder.mixed().len(), 11025 * 2);
+        assert_eq!(
+            &recorder.mixed()[..],
+            &apu.audio_buffer().iter().copied().collect::<Vec<i16>>()[..]
+        );
+        assert_eq!(recorder.stem(0).unwrap().len(), 11025);
+        assert!(recorder.stem(0).unwrap().iter().any(|v| *v > 1000));
+        assert!(recorder.stem(2).unwrap().iter().all(|v| *v == 0));
+    }
+
+    #[test]
+    fn test_recorder_rate_adjust() {
+        let mut apu = Apu::default();
+        apu.enable_recorder(true);
+        apu.write(0xff25, 0x11);
+        apu.set_rate_adjust(1.005);
+        for _ in 0..GameBoy::CPU_FREQ / 32 {
+            apu.clock(16);
+        }
+
+        // the output follows the rate control while the recording
+        // keeps the nominal sampling rate
+        let recorder = apu.disable_recorder().unwrap();
+        assert_eq!(apu.audio_buffer().len(), 22160 * 2);
+        assert_eq!(recorder.mixed().len(), 22050 * 2);
+        assert_eq!(recorder.stem(0).unwrap().len(), 22050);
+    }
+
+    #[test]
+    fn test_vgm() {
+        let mut apu = Apu::default();
+        apu.write(0xff24, 0x77);
+        apu.enable_vgm();
+        apu.clock(16);
+        apu.write(0xff12, 0xf0);
+        apu.write(0xff14, 0x87);
+
+        let vgm = apu.disable_vgm().unwrap();
+        assert!(!apu.vgm_enabled());
+        assert_eq!(vgm.cycles(), 16);
+        assert_eq!(vgm.writes().len(), 21);
+        assert_eq!(vgm.writes()[0], (0, 0x16, 0x80));
+        assert_eq!(vgm.writes()[1], (0, 0x14, 0x77));
+        assert_eq!(vgm.writes()[19], (16, 0x02, 0xf0));
+        assert_eq!(vgm.writes()[20], (16, 0x04, 0x87));
+    }
 }
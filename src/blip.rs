@@ -0,0 +1,253 @@
+use std::{collections::VecDeque, f64::consts::PI};
+
+/// The number of sub-sample phases of the band-limited step,
+/// a delta is placed with a precision of 1/32 of an output sample.
+pub const BLIP_PHASES: usize = 32;
+
+/// The width (in output samples) of the band-limited impulse,
+/// larger values increase the quality of the low-pass filter
+/// at the cost of more work per amplitude change.
+pub const BLIP_WIDTH: usize = 16;
+
+/// The cutoff frequency of the low-pass filter as a fraction
+/// of the output sampling rate (0.5 being Nyquist).
+const BLIP_CUTOFF: f64 = 0.45;
+
+/// Band-limited synthesis buffer (in the style of blip_buf) that
+/// converts the amplitude changes of a signal running at a high
+/// clock rate (eg: the APU) into samples at the output rate.
+///
+/// Instead of taking the nearest sample, each amplitude change is
+/// added as a band-limited step (a windowed sinc impulse that is
+/// later integrated) which removes the aliasing that otherwise
+/// shows up as harsh high-pitched noise in the square channels.
+#[derive(Clone)]
+pub struct BlipBuffer {
+    /// The number of output samples generated per input clock.
+    ratio: f64,
+
+    /// The current position (in output samples, relative to the
+    /// first pending sample) of the input signal.
+    position: f64,
+
+    /// The pending (not yet integrated) deltas, one entry per
+    /// output sample, including the tail of the last impulse.
+    deltas: VecDeque<f32>,
+
+    /// The running sum of the deltas, meaning the current
+    /// amplitude of the output signal.
+    integrator: f32,
+
+    /// The band-limited impulse for each of the sub-sample
+    /// phases, each of them normalized to a unit sum.
+    kernel: Vec<[f32; BLIP_WIDTH]>,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: u32, sample_rate: u32) -> Self {
+        Self {
+            ratio: sample_rate as f64 / clock_rate as f64,
+            position: 0.0,
+            deltas: VecDeque::with_capacity(BLIP_WIDTH * 2),
+            integrator: 0.0,
+            kernel: Self::build_kernel(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.position = 0.0;
+        self.deltas.clear();
+        self.integrator = 0.0;
+    }
+
+    /// Changes the input clock and output sampling rates, the
+    /// pending deltas are kept so that no sample is lost.
+    pub fn set_rates(&mut self, clock_rate: u32, sample_rate: u32) {
+        self.ratio = sample_rate as f64 / clock_rate as f64;
+    }
+
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    pub fn set_ratio(&mut self, value: f64) {
+        self.ratio = value;
+    }
+
+    /// Adds an amplitude change at the current position of the
+    /// input signal, spreading it as a band-limited step.
+    pub fn add_delta(&mut self, delta: f32) {
+        let mut index = self.position as usize;
+        let mut phase = ((self.position - index as f64) * BLIP_PHASES as f64 + 0.5) as usize;
+        if phase >= BLIP_PHASES {
+            index += 1;
+            phase -= BLIP_PHASES;
+        }
+
+        let length = index + BLIP_WIDTH;
+        if self.deltas.len() < length {
+            self.deltas.resize(length, 0.0);
+        }
+
+        for (offset, weight) in self.kernel[phase].iter().enumerate() {
+            self.deltas[index + offset] += delta * weight;
+        }
+    }
+
+    /// Advances the input signal by the given number of clocks.
+    pub fn advance(&mut self, clocks: u32) {
+        self.position += clocks as f64 * self.ratio;
+    }
+
+    /// The number of output samples that are complete, meaning
+    /// that no further delta is able to change them.
+    pub fn samples_avail(&self) -> usize {
+        self.position as usize
+    }
+
+    /// Reads (and removes) the next complete output sample, the
+    /// value is in the same unit as the provided deltas.
+    pub fn read_sample(&mut self) -> Option<f32> {
+        if self.position < 1.0 {
+            return None;
+        }
+        self.integrator += self.deltas.pop_front().unwrap_or(0.0);
+        self.position -= 1.0;
+        Some(self.integrator)
+    }
+
+    /// Builds the Blackman windowed sinc impulse for each of the
+    /// sub-sample phases, the impulse is centered in the middle
+    /// of the kernel which delays the output by half its width.
+    fn build_kernel() -> Vec<[f32; BLIP_WIDTH]> {
+        let center = (BLIP_WIDTH / 2) as f64 - 1.0;
+        (0..BLIP_PHASES)
+            .map(|phase| {
+                let fraction = phase as f64 / BLIP_PHASES as f64;
+                let mut impulse = [0.0f64; BLIP_WIDTH];
+                for (index, value) in impulse.iter_mut().enumerate() {
+                    // distance (in output samples) to the step and the
+                    // matching position inside of the window (0.0 to 1.0)
+                    let x = index as f64 - center - fraction;
+                    let w = (x + BLIP_WIDTH as f64 / 2.0) / BLIP_WIDTH as f64;
+                    let sinc = if x == 0.0 {
+                        1.0
+                    } else {
+                        (2.0 * PI * BLIP_CUTOFF * x).sin() / (2.0 * PI * BLIP_CUTOFF * x)
+                    };
+                    let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+                    *value = sinc * window.max(0.0);
+                }
+                let sum: f64 = impulse.iter().sum();
+                let mut kernel = [0.0f32; BLIP_WIDTH];
+                for (target, value) in kernel.iter_mut().zip(impulse.iter()) {
+                    *target = (value / sum) as f32;
+                }
+                kernel
+            })
+            .collect()
+    }
+}
+
+/// Simple first order high-pass filter that removes the DC offset
+/// of the output, emulating the capacitor found at the output of
+/// the Game Boy's audio amplifier.
+#[derive(Clone)]
+pub struct HighPass {
+    /// The per sample charge factor of the capacitor.
+    factor: f32,
+
+    /// The current charge of the capacitor.
+    capacitor: f32,
+}
+
+impl HighPass {
+    pub fn new(factor: f32) -> Self {
+        Self {
+            factor,
+            capacitor: 0.0,
+        }
+    }
+
+    /// Builds the filter from the capacitor charge factor of the
+    /// hardware, which is defined per (input) clock, converting
+    /// it into the equivalent factor per output sample.
+    pub fn from_charge(charge: f64, clock_rate: u32, sample_rate: u32) -> Self {
+        Self::new(charge.powf(clock_rate as f64 / sample_rate as f64) as f32)
+    }
+
+    pub fn reset(&mut self) {
+        self.capacitor = 0.0;
+    }
+
+    pub fn factor(&self) -> f32 {
+        self.factor
+    }
+
+    pub fn set_factor(&mut self, value: f32) {
+        self.factor = value;
+    }
+
+    pub fn filter(&mut self, input: f32) -> f32 {
+        let output = input - self.capacitor;
+        self.capacitor = input - output * self.factor;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlipBuffer, HighPass, BLIP_WIDTH};
+
+    #[test]
+    fn test_blip_step() {
+        let mut blip = BlipBuffer::new(4194304, 44100);
+        blip.add_delta(10.0);
+        blip.advance(4194304 / 1000);
+
+        let mut samples = vec![];
+        while let Some(sample) = blip.read_sample() {
+            samples.push(sample);
+        }
+
+        // the step is delayed by half of the impulse width and
+        // settles (after the ringing) at the delta amplitude
+        assert_eq!(samples.len(), 44);
+        assert!(samples[0].abs() < 0.5);
+        assert!((samples[BLIP_WIDTH] - 10.0).abs() < 0.01);
+        assert!((samples[43] - 10.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_blip_square() {
+        let mut blip = BlipBuffer::new(4194304, 44100);
+        let mut max = 0.0f32;
+        let mut level = 0.0f32;
+        for _ in 0..2000 {
+            let delta = if level > 0.0 { -15.0 } else { 15.0 };
+            level += delta;
+            blip.add_delta(delta);
+            blip.advance(32);
+            while let Some(sample) = blip.read_sample() {
+                max = max.max(sample.abs());
+            }
+        }
+
+        // a square wave way above the cutoff frequency is filtered
+        // down to (roughly) its average amplitude
+        assert!(max < 10.0);
+        assert_eq!(blip.samples_avail(), 0);
+    }
+
+    #[test]
+    fn test_high_pass() {
+        let mut high_pass = HighPass::from_charge(0.999958, 4194304, 44100);
+        let first = high_pass.filter(1.0);
+        let mut last = first;
+        for _ in 0..44100 {
+            last = high_pass.filter(1.0);
+        }
+        assert_eq!(first, 1.0);
+        assert!(last.abs() < 0.01);
+    }
+}
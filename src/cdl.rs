@@ -0,0 +1,259 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+use crate::util::write_file;
+
+/// Flag set for the ROM bytes that have been fetched and
+/// executed by the CPU as the first byte of an instruction.
+pub const CDL_OPCODE: u8 = 0x01;
+
+/// Flag set for the ROM bytes that have been fetched and
+/// executed by the CPU as an instruction operand (this includes
+/// the second byte of the 0xCB prefixed instructions).
+pub const CDL_OPERAND: u8 = 0x02;
+
+/// Flag set for the ROM bytes that have been read as data
+/// by the CPU (eg: `LD A, (HL)` over a ROM address).
+pub const CDL_DATA: u8 = 0x04;
+
+/// Flag set for the ROM bytes that have been used as the
+/// source of an OAM DMA or CGB HDMA/GDMA transfer, usually
+/// meaning that they contain graphics data.
+pub const CDL_DMA: u8 = 0x08;
+
+/// Code/Data Logger (CDL) that keeps track of how each of
+/// the bytes of the cartridge ROM has been accessed during
+/// emulation, useful for ROM coverage analysis and to drive
+/// disassemblers in the separation between code and data.
+///
+/// The exported file is a raw dump of the ROM domain, with one
+/// flag byte per ROM byte (same size and order as the ROM file),
+/// using the flag layout of the BizHawk (Gambatte core) CDL:
+///
+/// | Bit | Value  | Meaning                                  |
+/// |-----|--------|------------------------------------------|
+/// | 0   | `0x01` | Executed as the first byte (opcode)      |
+/// | 1   | `0x02` | Executed as an operand byte              |
+/// | 2   | `0x04` | Read as data by the CPU                  |
+/// | 3   | `0x08` | Read by DMA (extension, ignored by tools |
+/// |     |        | that only understand the first 3 bits)   |
+///
+/// The remaining bits (4 to 7) are always unset, the file contains
+/// no header so it can be read as a plain byte array by scripts
+/// and disassemblers.
+#[derive(Clone)]
+pub struct CodeDataLogger {
+    /// The flags buffer with one entry per byte of the
+    /// cartridge ROM, each entry is a bitwise OR of the
+    /// multiple `CDL_*` access flags.
+    flags: Vec<u8>,
+}
+
+impl CodeDataLogger {
+    pub fn new(size: usize) -> Self {
+        Self {
+            flags: vec![0x00; size],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.flags.iter_mut().for_each(|flag| *flag = 0x00);
+    }
+
+    /// Re-allocates the flags buffer so that it's able to
+    /// hold one entry per byte of a ROM with the given size,
+    /// clearing any previously logged information.
+    pub fn allocate(&mut self, size: usize) {
+        self.flags = vec![0x00; size];
+    }
+
+    /// Marks the byte at the provided ROM address (absolute
+    /// offset in the ROM data) with the given flag, addresses
+    /// outside the ROM are silently ignored.
+    #[inline(always)]
+    pub fn log(&mut self, addr: usize, flag: u8) {
+        if let Some(value) = self.flags.get_mut(addr) {
+            *value |= flag;
+        }
+    }
+
+    pub fn flag(&self, addr: usize) -> u8 {
+        self.flags.get(addr).copied().unwrap_or(0x00)
+    }
+
+    pub fn flags(&self) -> &Vec<u8> {
+        &self.flags
+    }
+
+    pub fn size(&self) -> usize {
+        self.flags.len()
+    }
+
+    /// Counts the number of ROM bytes that have at least one
+    /// of the provided flags set.
+    pub fn count(&self, flag: u8) -> usize {
+        self.flags
+            .iter()
+            .filter(|value| *value & flag != 0x00)
+            .count()
+    }
+
+    /// Computes the percentage of the ROM (0.0 to 100.0) that has
+    /// been accessed (in any possible way) since the logger start.
+    pub fn coverage(&self) -> f32 {
+        if self.flags.is_empty() {
+            return 0.0;
+        }
+        self.count(0xff) as f32 * 100.0 / self.flags.len() as f32
+    }
+
+    /// Exports the current CDL information as a sequence of
+    /// bytes ready to be written to a `.cdl` file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.flags.clone()
+    }
+
+    pub fn save_file(&self, path: &str) -> io::Result<()> {
+        write_file(path, &self.to_bytes())
+    }
+
+    pub fn description(&self, column_length: usize) -> String {
+        let size_l = format!("{:width$}", "Size", width = column_length);
+        let code_l = format!("{:width$}", "Code", width = column_length);
+        let data_l = format!("{:width$}", "Data", width = column_length);
+        let dma_l = format!("{:width$}", "DMA", width = column_length);
+        let coverage_l = format!("{:width$}", "Coverage", width = column_length);
+        format!(
+            "{}  {} bytes\n{}  {} bytes\n{}  {} bytes\n{}  {} bytes\n{}  {:.2}%",
+            size_l,
+            self.size(),
+            code_l,
+            self.count(CDL_OPCODE | CDL_OPERAND),
+            data_l,
+            self.count(CDL_DATA),
+            dma_l,
+            self.count(CDL_DMA),
+            coverage_l,
+            self.coverage()
+        )
+    }
+}
+
+impl Default for CodeDataLogger {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Display for CodeDataLogger {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.description(9))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cdl::{CodeDataLogger, CDL_DATA, CDL_DMA, CDL_OPCODE, CDL_OPERAND},
+        gb::{GameBoy, GameBoyMode},
+    };
+
+    #[test]
+    fn test_cdl_log() {
+        let mut cdl = CodeDataLogger::new(4);
+        cdl.log(0, CDL_OPCODE);
+        cdl.log(1, CDL_OPERAND);
+        cdl.log(1, CDL_DATA);
+        cdl.log(3, CDL_DMA);
+        cdl.log(8, CDL_DATA);
+
+        assert_eq!(cdl.flag(0), CDL_OPCODE);
+        assert_eq!(cdl.flag(1), CDL_OPERAND | CDL_DATA);
+        assert_eq!(cdl.flag(2), 0x00);
+        assert_eq!(cdl.flag(3), CDL_DMA);
+        assert_eq!(cdl.count(CDL_DATA), 1);
+        assert_eq!(cdl.coverage(), 75.0);
+        assert_eq!(cdl.to_bytes(), vec![0x01, 0x06, 0x00, 0x08]);
+    }
+
+    #[test]
+    fn test_cdl_reset() {
+        let mut cdl = CodeDataLogger::new(2);
+        cdl.log(0, CDL_OPCODE);
+        cdl.reset();
+        assert_eq!(cdl.size(), 2);
+        assert_eq!(cdl.coverage(), 0.0);
+    }
+
+    #[test]
+    fn test_cdl_cpu() {
+        // LD A, (0x0150) ; LD A, 0x01 ; JR -2 (loops over itself)
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100..0x0107].copy_from_slice(&[0xfa, 0x50, 0x01, 0x3e, 0x01, 0x18, 0xfe]);
+
+        let mut game_boy = GameBoy::new(Some(GameBoyMode::Dmg));
+        game_boy.load(false);
+        game_boy.load_rom(&rom);
+        game_boy.boot();
+        game_boy.enable_cdl();
+        for _ in 0..4 {
+            game_boy.clock();
+        }
+
+        let cdl = game_boy.cdl().unwrap();
+        assert_eq!(cdl.size(), 0x8000);
+        assert_eq!(cdl.flag(0x0100), CDL_OPCODE);
+        assert_eq!(cdl.flag(0x0101), CDL_OPERAND);
+        assert_eq!(cdl.flag(0x0102), CDL_OPERAND);
+        assert_eq!(cdl.flag(0x0103), CDL_OPCODE);
+        assert_eq!(cdl.flag(0x0104), CDL_OPERAND);
+        assert_eq!(cdl.flag(0x0105), CDL_OPCODE);
+        assert_eq!(cdl.flag(0x0106), CDL_OPERAND);
+        assert_eq!(cdl.flag(0x0107), 0x00);
+        assert_eq!(cdl.flag(0x0150), CDL_DATA);
+        assert_eq!(cdl.count(0xff), 8);
+    }
+
+    #[test]
+    fn test_cdl_dma_conflict() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0150] = 0x12;
+
+        let mut values = vec![];
+        for enabled in [false, true] {
+            let mut game_boy = GameBoy::new(Some(GameBoyMode::Dmg));
+            game_boy.load(false);
+            game_boy.load_rom(&rom);
+            game_boy.boot();
+            if enabled {
+                game_boy.enable_cdl();
+            }
+            for index in 0..160 {
+                game_boy.mmu().write(0xc000 + index, 0x34);
+            }
+
+            // fetching from ROM while the OAM DMA transfer from WRAM is
+            // running reads the byte being transferred (same bus)
+            game_boy.mmu().write(0xff46, 0xc0);
+            game_boy.mmu().clock_dma(8);
+            values.push(game_boy.mmu().read_cdl(0x0150, CDL_OPCODE));
+            if enabled {
+                assert_eq!(game_boy.cdl().unwrap().flag(0x0150), 0x00);
+            }
+        }
+        assert_eq!(values, vec![0x34, 0x34]);
+    }
+
+    #[test]
+    fn test_cdl_save_error() {
+        let mut game_boy = GameBoy::new(Some(GameBoyMode::Dmg));
+        assert!(game_boy.save_cdl_file("test.cdl").is_err());
+
+        game_boy.load(false);
+        game_boy.load_rom(&vec![0x00; 0x8000]);
+        game_boy.enable_cdl();
+        assert!(game_boy.save_cdl_file("missing/test.cdl").is_err());
+    }
+}
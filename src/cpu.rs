@@ -1,7 +1,8 @@
-use std::{cell::RefCell, rc::Rc};
+use std::sync::{Arc, Mutex};
 
 use crate::{
     apu::Apu,
+    cdl::{CDL_OPCODE, CDL_OPERAND},
     debugln,
     dma::Dma,
     gb::GameBoyConfig,
@@ -10,11 +11,14 @@ use crate::{
     pad::Pad,
     ppu::Ppu,
     serial::Serial,
+    state::{Serializer, StateComponent},
     timer::Timer,
+    util::SharedThread,
 };
 
 pub const PREFIX: u8 = 0xcb;
 
+#[derive(Clone)]
 pub struct Cpu {
     pub pc: u16,
     pub sp: u16,
@@ -44,11 +48,11 @@ pub struct Cpu {
     /// The pointer to the parent configuration of the running
     /// Game Boy emulator, that can be used to control the behaviour
     /// of Game Boy emulation.
-    gbc: Rc<RefCell<GameBoyConfig>>,
+    gbc: SharedThread<GameBoyConfig>,
 }
 
 impl Cpu {
-    pub fn new(mmu: Mmu, gbc: Rc<RefCell<GameBoyConfig>>) -> Self {
+    pub fn new(mmu: Mmu, gbc: SharedThread<GameBoyConfig>) -> Self {
         Self {
             pc: 0x0,
             sp: 0x0,
@@ -113,6 +117,13 @@ impl Cpu {
         // register to enabled (required by some ROMs)
         self.mmu.set_boot_active(false);
         self.mmu.write(0xff40, 0x91);
+
+        // sets the sound registers to the values left by the boot
+        // ROM (sound on, mixed into both terminals at full volume)
+        // as many ROMs rely on them and never write to NR50/NR51
+        self.mmu.write(0xff26, 0xf1);
+        self.mmu.write(0xff25, 0xf3);
+        self.mmu.write(0xff24, 0x77);
     }
 
     pub fn clock(&mut self) -> u8 {
@@ -125,6 +136,16 @@ impl Cpu {
             panic!("Invalid PC area at 0x{:04x}", pc);
         }
 
+        // in case the CPU is stalled by a running HDMA transfer no
+        // instruction is executed and the stall cycles are consumed
+        // (in small steps so that other components are clocked)
+        let stall_cycles = self.mmu.dma_i().stall_cycles();
+        if stall_cycles > 0 {
+            let cycles = stall_cycles.min(4);
+            self.mmu.dma().set_stall_cycles(stall_cycles - cycles);
+            return cycles as u8;
+        }
+
         // @TODO this is so bad, need to improve this by an order
         // of magnitude, to be able to have better performance
         // in case the CPU execution halted and there's an interrupt
@@ -252,14 +273,14 @@ impl Cpu {
 
         // fetches the current instruction and increments
         // the PC (program counter) accordingly
-        let mut opcode = self.mmu.read(self.pc);
+        let mut opcode = self.mmu.read_cdl(self.pc, CDL_OPCODE);
         self.pc = self.pc.wrapping_add(1);
 
         let is_prefix = opcode == PREFIX;
         let inst: &(fn(&mut Cpu), u8, &str);
 
         if is_prefix {
-            opcode = self.mmu.read(self.pc);
+            opcode = self.mmu.read_cdl(self.pc, CDL_OPERAND);
             self.pc = self.pc.wrapping_add(1);
             inst = &EXTENDED[opcode as usize];
         } else {
@@ -474,7 +495,7 @@ impl Cpu {
 
     #[inline(always)]
     pub fn read_u8(&mut self) -> u8 {
-        let byte = self.mmu.read(self.pc);
+        let byte = self.mmu.read_cdl(self.pc, CDL_OPERAND);
         self.pc = self.pc.wrapping_add(1);
         byte
     }
@@ -574,14 +595,36 @@ impl Cpu {
         self.ime = false;
     }
 
-    pub fn set_gbc(&mut self, value: Rc<RefCell<GameBoyConfig>>) {
+    pub fn set_gbc(&mut self, value: SharedThread<GameBoyConfig>) {
         self.gbc = value;
     }
 }
 
+impl StateComponent for Cpu {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.u16(&mut self.pc);
+        s.u16(&mut self.sp);
+        s.u8(&mut self.a);
+        s.u8(&mut self.b);
+        s.u8(&mut self.c);
+        s.u8(&mut self.d);
+        s.u8(&mut self.e);
+        s.u8(&mut self.h);
+        s.u8(&mut self.l);
+        s.bool(&mut self.ime);
+        s.bool(&mut self.zero);
+        s.bool(&mut self.sub);
+        s.bool(&mut self.half_carry);
+        s.bool(&mut self.carry);
+        s.bool(&mut self.halted);
+        s.u8(&mut self.cycles);
+        self.mmu.serialize(s);
+    }
+}
+
 impl Default for Cpu {
     fn default() -> Self {
-        let gbc: Rc<RefCell<GameBoyConfig>> = Rc::new(RefCell::new(GameBoyConfig::default()));
+        let gbc: SharedThread<GameBoyConfig> = Arc::new(Mutex::new(GameBoyConfig::default()));
         Cpu::new(Mmu::default(), gbc)
     }
 }
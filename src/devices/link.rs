@@ -0,0 +1,249 @@
+use crate::{gb::GameBoy, serial::SerialDevice};
+
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::{Arc, Mutex},
+};
+
+/// The state shared by both ends of a link cable, each of
+/// the arrays is indexed by the end (0 or 1) of the cable.
+#[derive(Default)]
+struct LinkState {
+    /// The byte that each end is waiting to transfer in slave
+    /// mode (external clock), if any.
+    slave: [Option<u8>; 2],
+
+    /// Whether each end is currently in a transfer that is
+    /// being clocked by the other end (master).
+    clocked: [bool; 2],
+
+    /// The byte received by each end at the end of a transfer
+    /// clocked by the other end, waiting to be polled.
+    inbox: [Option<u8>; 2],
+}
+
+/// One of the ends of a link cable that connects two Game Boy
+/// instances running in the same process.
+///
+/// The end running the clock (master) obtains the byte of the other
+/// end when the transfer starts and delivers its own byte once the
+/// eight bits have been shifted, completing the transfer of the
+/// other end (slave) at that same moment.
+pub struct LinkDevice {
+    index: usize,
+    state: Arc<Mutex<LinkState>>,
+    received: Vec<u8>,
+}
+
+impl LinkDevice {
+    /// Creates a pair of link devices connected to each other,
+    /// each of them to be attached to a different Game Boy.
+    pub fn pair() -> (Self, Self) {
+        let state = Arc::new(Mutex::new(LinkState::default()));
+        (
+            Self {
+                index: 0,
+                state: state.clone(),
+                received: vec![],
+            },
+            Self {
+                index: 1,
+                state,
+                received: vec![],
+            },
+        )
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn received(&self) -> &Vec<u8> {
+        &self.received
+    }
+
+    fn peer(&self) -> usize {
+        1 - self.index
+    }
+}
+
+impl SerialDevice for LinkDevice {
+    fn send(&mut self) -> u8 {
+        let peer = self.peer();
+        let mut state = self.state.lock().unwrap();
+        match state.slave[peer].take() {
+            Some(byte) => {
+                state.clocked[peer] = true;
+                self.received.push(byte);
+                byte
+            }
+            None => 0xff,
+        }
+    }
+
+    fn receive(&mut self, byte: u8) {
+        let peer = self.peer();
+        let mut state = self.state.lock().unwrap();
+        if state.clocked[peer] {
+            state.clocked[peer] = false;
+            state.inbox[peer] = Some(byte);
+        }
+    }
+
+    fn allow_slave(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> String {
+        format!("Link [{}]", self.index + 1)
+    }
+
+    fn state(&self) -> String {
+        self.received
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn set_slave(&mut self, byte: Option<u8>) {
+        let mut state = self.state.lock().unwrap();
+        if !state.clocked[self.index] {
+            state.slave[self.index] = byte;
+        }
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        let byte = self.state.lock().unwrap().inbox[self.index].take();
+        if let Some(byte) = byte {
+            self.received.push(byte);
+        }
+        byte
+    }
+}
+
+impl Display for LinkDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Link")
+    }
+}
+
+/// Link cable that connects two Game Boy instances running in the
+/// same process, keeping both of them in sync (by cycles) so that
+/// the serial transfers happen at the correct time on both ends.
+pub struct LinkCable {
+    /// The number of cycles (at normal speed) that the first
+    /// Game Boy is ahead of the second one.
+    balance: i64,
+}
+
+impl LinkCable {
+    pub fn new() -> Self {
+        Self { balance: 0 }
+    }
+
+    /// Connects both Game Boy instances, attaching one of the
+    /// ends of the cable to the serial port of each of them.
+    pub fn connect(&mut self, first: &mut GameBoy, second: &mut GameBoy) {
+        let (first_device, second_device) = LinkDevice::pair();
+        first.attach_serial(Box::new(first_device));
+        second.attach_serial(Box::new(second_device));
+        self.balance = 0;
+    }
+
+    /// Runs one instruction of the first Game Boy and then runs the
+    /// second one until it catches up, so that both never drift more
+    /// than one instruction apart, returning the cycles of the first.
+    pub fn clock(&mut self, first: &mut GameBoy, second: &mut GameBoy) -> u16 {
+        let cycles = first.clock();
+        self.balance += (cycles / first.multiplier() as u16) as i64;
+        while self.balance > 0 {
+            let cycles = second.clock();
+            self.balance -= (cycles / second.multiplier() as u16) as i64;
+        }
+        cycles
+    }
+
+    pub fn balance(&self) -> i64 {
+        self.balance
+    }
+}
+
+impl Default for LinkCable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LinkCable, LinkDevice};
+    use crate::{
+        gb::{GameBoy, GameBoyMode},
+        serial::Serial,
+    };
+
+    #[test]
+    fn test_link_transfer() {
+        let (first, second) = LinkDevice::pair();
+        let mut master = Serial::new();
+        let mut slave = Serial::new();
+        master.set_device(Box::new(first));
+        slave.set_device(Box::new(second));
+
+        slave.write(0xff01, 0x42);
+        slave.write(0xff02, 0x80);
+        master.write(0xff01, 0x24);
+        master.write(0xff02, 0x81);
+
+        // the slave only completes its transfer once the master
+        // has shifted all of the eight bits
+        for _ in 0..8 * 512 / 4 - 1 {
+            master.clock(4);
+            slave.clock(4);
+        }
+        assert!(!slave.int_serial());
+        master.clock(4);
+        slave.clock(4);
+
+        assert!(master.int_serial());
+        assert!(slave.int_serial());
+        assert_eq!(master.read(0xff01), 0x42);
+        assert_eq!(slave.read(0xff01), 0x24);
+        assert_eq!(master.device().state(), "42");
+        assert_eq!(slave.device().state(), "24");
+    }
+
+    #[test]
+    fn test_link_no_slave() {
+        let (first, _second) = LinkDevice::pair();
+        let mut master = Serial::new();
+        master.set_device(Box::new(first));
+        master.write(0xff01, 0x24);
+        master.write(0xff02, 0x81);
+        for _ in 0..8 * 512 / 4 {
+            master.clock(4);
+        }
+        assert!(master.int_serial());
+        assert_eq!(master.read(0xff01), 0xff);
+    }
+
+    #[test]
+    fn test_link_cable_sync() {
+        let mut first = GameBoy::new(Some(GameBoyMode::Dmg));
+        let mut second = GameBoy::new(Some(GameBoyMode::Dmg));
+        let mut cable = LinkCable::new();
+        cable.connect(&mut first, &mut second);
+        for game_boy in [&mut first, &mut second] {
+            game_boy.load(false);
+            game_boy.load_rom(&[0x00; 0x8000]);
+        }
+
+        for _ in 0..10000 {
+            cable.clock(&mut first, &mut second);
+            assert!(cable.balance() <= 0 && cable.balance() > -24);
+        }
+        assert_eq!(first.serial().device().description(), "Link [1]");
+        assert_eq!(second.serial().device().description(), "Link [2]");
+    }
+}
@@ -1,3 +1,5 @@
 pub mod buffer;
+pub mod link;
+pub mod network;
 pub mod printer;
 pub mod stdout;
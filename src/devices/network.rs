@@ -0,0 +1,516 @@
+use crate::{serial::SerialDevice, warnln};
+
+use std::{
+    collections::VecDeque,
+    convert::TryInto,
+    fmt::{self, Display, Formatter},
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Message sent periodically with the emulated time (in cycles)
+/// reached by the sending end.
+const MSG_TIME: u8 = 0x01;
+
+/// Message sent whenever the sending end starts (or stops) waiting
+/// for a transfer in slave mode (external clock), with its byte.
+const MSG_ARM: u8 = 0x02;
+
+/// Message sent by the end running the clock (master) when a transfer
+/// starts, with its byte and the duration of the transfer.
+const MSG_CLOCK: u8 = 0x03;
+
+/// Size of each message, as kind (1 byte), value (1 byte), extra
+/// (2 bytes) and emulated time (8 bytes), in little endian.
+const MESSAGE_SIZE: usize = 12;
+
+/// The maximum number of (normal speed) cycles one end is allowed
+/// to run ahead of the other one before blocking.
+pub const LINK_WINDOW: u64 = 2048;
+
+/// The number of cycles between each time message.
+const LINK_SYNC: u64 = 512;
+
+/// Extra cycles on top of the window before the slave end completes
+/// a transfer, covering the length of the instruction being clocked.
+const LINK_MARGIN: u64 = 64;
+
+/// The default amount of (wall clock) time to wait for the remote
+/// end before giving up on it.
+const LINK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Changes of the byte waiting in slave mode, with the emulated time
+/// of each of them, `None` meaning that no transfer is pending.
+type ArmLog = VecDeque<(u64, Option<u8>)>;
+
+/// The state of the remote end, as updated by the thread reading
+/// its messages.
+#[derive(Default)]
+struct NetworkState {
+    /// The emulated time reached by the remote end.
+    time: u64,
+
+    /// The changes of the slave byte of the remote end.
+    arms: ArmLog,
+
+    /// The transfers started by the remote end as master, with
+    /// time, byte and duration (in cycles).
+    clocks: VecDeque<(u64, u8, u16)>,
+
+    /// The time of the last transfer started by the remote end, as
+    /// it's the last thing that end does at that time.
+    clocked: Option<u64>,
+
+    /// Whether the connection has been lost (or given up on).
+    disconnected: bool,
+}
+
+/// Link cable that tunnels the serial transfers to another emulator
+/// over a TCP connection, using a lockstep protocol.
+///
+/// Both ends exchange the emulated time (in cycles) they've reached
+/// and neither is allowed to run more than `LINK_WINDOW` cycles ahead
+/// of the other. Every change of the slave byte and every transfer
+/// start is stamped with the emulated time, so both ends agree on
+/// the outcome of a transfer regardless of network latency: the
+/// master waits until the remote end has reached the start of the
+/// transfer and the remote end is considered the slave only if it
+/// was waiting in slave mode at that (emulated) time. In case both
+/// ends start a transfer at the same time each of them receives 0xFF.
+///
+/// The slave completes the transfer once the duration of it has
+/// elapsed, but never sooner than the window, meaning that transfers
+/// using the CGB high speed clock complete late on the slave end.
+///
+/// In case the remote end doesn't respond for longer than the timeout
+/// the connection is dropped, with transfers then receiving 0xFF.
+pub struct NetworkDevice {
+    stream: TcpStream,
+    state: Arc<(Mutex<NetworkState>, Condvar)>,
+    peer: String,
+    timeout: Duration,
+    time: u64,
+    synced: u64,
+    arms: ArmLog,
+    master: (u8, u16),
+    last_clock: Option<u64>,
+    last_peer_clock: Option<u64>,
+    inbox: Option<(u64, u8)>,
+    received: Vec<u8>,
+}
+
+impl NetworkDevice {
+    /// Listens for the connection of the remote end at the provided
+    /// address (eg: `0.0.0.0:5555`), giving up after the timeout.
+    pub fn listen(addr: &str, timeout: Duration) -> Result<Self, String> {
+        let listener = TcpListener::bind(addr)
+            .map_err(|error| format!("Failed to listen on {}: {}", addr, error))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|error| error.to_string())?;
+        let start = Instant::now();
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream
+                        .set_nonblocking(false)
+                        .map_err(|error| error.to_string())?;
+                    return Self::from_stream(stream);
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                    if start.elapsed() >= timeout {
+                        return Err(format!("No connection on {} after {:?}", addr, timeout));
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(error) => return Err(format!("Failed to accept on {}: {}", addr, error)),
+            }
+        }
+    }
+
+    /// Connects to the remote end listening at the provided address
+    /// (eg: `127.0.0.1:5555`).
+    pub fn connect(addr: &str) -> Result<Self, String> {
+        let stream = TcpStream::connect(addr)
+            .map_err(|error| format!("Failed to connect to {}: {}", addr, error))?;
+        Self::from_stream(stream)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> Result<Self, String> {
+        stream
+            .set_nodelay(true)
+            .map_err(|error| error.to_string())?;
+        let peer = stream
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        let reader = stream.try_clone().map_err(|error| error.to_string())?;
+        let state = Arc::new((Mutex::new(NetworkState::default()), Condvar::new()));
+
+        let state_t = state.clone();
+        thread::spawn(move || Self::read_loop(reader, state_t));
+
+        Ok(Self {
+            stream,
+            state,
+            peer,
+            timeout: LINK_TIMEOUT,
+            time: 0,
+            synced: 0,
+            arms: VecDeque::new(),
+            master: (0xff, 0),
+            last_clock: None,
+            last_peer_clock: None,
+            inbox: None,
+            received: vec![],
+        })
+    }
+
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    pub fn received(&self) -> &Vec<u8> {
+        &self.received
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, value: Duration) {
+        self.timeout = value;
+    }
+
+    /// Reads the messages of the remote end until the connection is
+    /// closed, updating the shared state and waking up any waiter.
+    fn read_loop(mut reader: TcpStream, state: Arc<(Mutex<NetworkState>, Condvar)>) {
+        let (lock, condvar) = &*state;
+        let mut message = [0u8; MESSAGE_SIZE];
+        while reader.read_exact(&mut message).is_ok() {
+            let time = u64::from_le_bytes(message[4..].try_into().unwrap());
+            let extra = u16::from_le_bytes([message[2], message[3]]);
+            let mut state = lock.lock().unwrap();
+            match message[0] {
+                MSG_TIME => (),
+                MSG_ARM => state
+                    .arms
+                    .push_back((time, if extra == 1 { Some(message[1]) } else { None })),
+                MSG_CLOCK => {
+                    state.clocks.push_back((time, message[1], extra));
+                    state.clocked = Some(time);
+                }
+                _ => {
+                    warnln!("Invalid link message 0x{:02x}", message[0]);
+                    break;
+                }
+            }
+            state.time = state.time.max(time);
+            condvar.notify_all();
+        }
+        lock.lock().unwrap().disconnected = true;
+        condvar.notify_all();
+    }
+
+    fn write_message(&self, kind: u8, value: u8, extra: u16) {
+        let mut message = [0u8; MESSAGE_SIZE];
+        message[0] = kind;
+        message[1] = value;
+        message[2..4].copy_from_slice(&extra.to_le_bytes());
+        message[4..].copy_from_slice(&self.time.to_le_bytes());
+        if (&self.stream).write_all(&message).is_err() {
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn sync(&mut self) {
+        self.write_message(MSG_TIME, 0x00, 0x0000);
+        self.synced = self.time;
+    }
+
+    /// Blocks until the state of the remote end is ready, dropping
+    /// the connection in case it takes longer than the timeout.
+    fn wait_peer<F: Fn(&NetworkState) -> bool>(&self, ready: F) {
+        let (lock, condvar) = &*self.state;
+        let state = lock.lock().unwrap();
+        let (mut state, result) = condvar
+            .wait_timeout_while(state, self.timeout, |state| {
+                !state.disconnected && !ready(state)
+            })
+            .unwrap();
+        if result.timed_out() {
+            warnln!("Link peer {} timed out, disconnecting", self.peer);
+            state.disconnected = true;
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Returns the byte of the end with the provided log of slave bytes
+    /// in case it's waiting in slave mode at the given time, any change
+    /// made until the previous transfer (`last`) having been consumed by it.
+    fn armed(arms: &ArmLog, time: u64, last: Option<u64>) -> Option<u8> {
+        let (armed_at, byte) = arms.iter().rev().find(|(at, _)| *at <= time)?;
+        match last {
+            Some(last) if *armed_at <= last => None,
+            _ => *byte,
+        }
+    }
+
+    /// Discards the changes of the log made before the given time,
+    /// keeping the one in effect at it.
+    fn prune(arms: &mut ArmLog, time: u64) {
+        while arms.len() > 1 && arms[1].0 <= time {
+            arms.pop_front();
+        }
+    }
+}
+
+impl SerialDevice for NetworkDevice {
+    fn send(&mut self) -> u8 {
+        let (byte, cycles) = self.master;
+        self.write_message(MSG_CLOCK, byte, cycles);
+        self.synced = self.time;
+        let last = self.last_clock.replace(self.time);
+
+        // blocks until the remote end is done with the time at which
+        // the transfer starts, so that its slave byte at it is known
+        let time = self.time;
+        let done = |state: &NetworkState| state.time > time || state.clocked == Some(time);
+        self.wait_peer(done);
+
+        let (lock, _) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        let byte = if done(&state) {
+            Self::armed(&state.arms, self.time, last).unwrap_or(0xff)
+        } else {
+            0xff
+        };
+        Self::prune(&mut state.arms, self.time);
+        self.received.push(byte);
+        byte
+    }
+
+    fn receive(&mut self, _: u8) {}
+
+    fn allow_slave(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> String {
+        format!("Network [{}]", self.peer)
+    }
+
+    fn state(&self) -> String {
+        self.received
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn set_slave(&mut self, byte: Option<u8>) {
+        if byte.is_none() {
+            self.inbox = None;
+            if matches!(self.arms.back(), None | Some((_, None))) {
+                return;
+            }
+        }
+        self.arms.push_back((self.time, byte));
+        self.write_message(MSG_ARM, byte.unwrap_or(0xff), byte.is_some() as u16);
+        self.synced = self.time;
+    }
+
+    fn set_master(&mut self, byte: u8, cycles: u16) {
+        self.master = (byte, cycles);
+    }
+
+    fn clock(&mut self, cycles: u16) {
+        self.time += cycles as u64;
+        if self.time >= self.synced + LINK_SYNC {
+            self.sync();
+        }
+
+        // blocks while too far ahead of the remote end
+        let ahead = {
+            let state = self.state.0.lock().unwrap();
+            !state.disconnected && self.time > state.time + LINK_WINDOW
+        };
+        if ahead {
+            self.sync();
+            let time = self.time - LINK_WINDOW;
+            self.wait_peer(|state| state.time >= time);
+        }
+
+        // handles the transfers started by the remote end before now, the
+        // ones for which this end was waiting in slave mode (and didn't
+        // cancel afterwards) are completed once their duration elapses
+        let (lock, _) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        while let Some(&(at, byte, cycles)) = state.clocks.front() {
+            if at >= self.time {
+                break;
+            }
+            state.clocks.pop_front();
+            let armed = Self::armed(&self.arms, at, self.last_peer_clock).is_some();
+            let cancelled = self
+                .arms
+                .iter()
+                .any(|(time, byte)| *time > at && byte.is_none());
+            if armed && !cancelled {
+                let delay = (cycles as u64).max(LINK_WINDOW + LINK_MARGIN);
+                self.inbox = Some((at + delay, byte));
+            }
+            self.last_peer_clock = Some(at);
+        }
+
+        // the transfers still to be handled start at or after the
+        // time of the first pending one (or the remote time)
+        let horizon = state.clocks.front().map_or(state.time, |clock| clock.0);
+        Self::prune(&mut self.arms, horizon.min(self.time));
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        match self.inbox {
+            Some((done, byte)) if self.time >= done => {
+                self.inbox = None;
+                self.received.push(byte);
+                Some(byte)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Drop for NetworkDevice {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl Display for NetworkDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Network")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NetworkDevice;
+    use crate::serial::Serial;
+
+    use std::{
+        net::TcpListener,
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+
+    fn connect_pair() -> (NetworkDevice, NetworkDevice) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle =
+            thread::spawn(move || NetworkDevice::from_stream(listener.accept().unwrap().0));
+        let first = NetworkDevice::connect(&addr).unwrap();
+        (first, handle.join().unwrap().unwrap())
+    }
+
+    /// Clocks a serial attached to the device in a separate thread for
+    /// the given number of cycles, performing the writes at the given
+    /// cycles, returning the serial and the cycle of the interrupt.
+    fn run(
+        device: NetworkDevice,
+        cycles: u32,
+        writes: Vec<(u32, u16, u8)>,
+    ) -> JoinHandle<(Serial, Option<u32>)> {
+        thread::spawn(move || {
+            let mut serial = Serial::new();
+            serial.set_device(Box::new(device));
+            let mut interrupt = None;
+            for cycle in (0..cycles).step_by(4) {
+                for (_, addr, value) in writes.iter().filter(|write| write.0 == cycle) {
+                    serial.write(*addr, *value);
+                }
+                serial.clock(4);
+                if interrupt.is_none() && serial.int_serial() {
+                    interrupt = Some(cycle + 4);
+                }
+            }
+            (serial, interrupt)
+        })
+    }
+
+    #[test]
+    fn test_network_transfer() {
+        let (first, second) = connect_pair();
+        let slave = run(second, 16384, vec![(0, 0xff01, 0x42), (0, 0xff02, 0x80)]);
+        let master = run(first, 16384, vec![(0, 0xff01, 0x24), (0, 0xff02, 0x81)]);
+        let (mut master, master_done) = master.join().unwrap();
+        let (mut slave, slave_done) = slave.join().unwrap();
+
+        assert_eq!(master_done, Some(8 * 512));
+        assert_eq!(slave_done, Some(8 * 512));
+        assert_eq!(master.read(0xff01), 0x42);
+        assert_eq!(slave.read(0xff01), 0x24);
+        assert_eq!(master.device().state(), "42");
+        assert_eq!(slave.device().state(), "24");
+    }
+
+    #[test]
+    fn test_network_masters() {
+        let (first, second) = connect_pair();
+
+        // both ends start a transfer as master at the same time
+        // and none of them is able to receive the other's byte
+        let second = run(second, 8192, vec![(0, 0xff01, 0x42), (0, 0xff02, 0x81)]);
+        let first = run(first, 8192, vec![(0, 0xff01, 0x24), (0, 0xff02, 0x81)]);
+        let (mut first, first_done) = first.join().unwrap();
+        let (mut second, second_done) = second.join().unwrap();
+
+        assert_eq!(first_done, Some(8 * 512));
+        assert_eq!(second_done, Some(8 * 512));
+        assert_eq!(first.read(0xff01), 0xff);
+        assert_eq!(second.read(0xff01), 0xff);
+    }
+
+    #[test]
+    fn test_network_timing() {
+        // the slave is started (in wall clock) after the master, still
+        // as it's waiting at an earlier emulated time the transfer succeeds
+        let (first, second) = connect_pair();
+        let master = run(first, 16384, vec![(0, 0xff01, 0x24), (2000, 0xff02, 0x81)]);
+        thread::sleep(Duration::from_millis(50));
+        let slave = run(second, 16384, vec![(0, 0xff01, 0x42), (1000, 0xff02, 0x80)]);
+        let (mut master, _) = master.join().unwrap();
+        let (mut slave, slave_done) = slave.join().unwrap();
+        assert_eq!(master.read(0xff01), 0x42);
+        assert_eq!(slave.read(0xff01), 0x24);
+        assert_eq!(slave_done, Some(2000 + 8 * 512));
+
+        // the slave is started (in wall clock) before the master, still
+        // as it's waiting at a later emulated time the transfer fails
+        let (first, second) = connect_pair();
+        let slave = run(second, 16384, vec![(0, 0xff01, 0x42), (3000, 0xff02, 0x80)]);
+        thread::sleep(Duration::from_millis(50));
+        let master = run(first, 16384, vec![(0, 0xff01, 0x24), (2000, 0xff02, 0x81)]);
+        let (mut master, _) = master.join().unwrap();
+        let (mut slave, slave_done) = slave.join().unwrap();
+        assert_eq!(master.read(0xff01), 0xff);
+        assert_eq!(slave.read(0xff01), 0x42);
+        assert_eq!(slave_done, None);
+    }
+
+    #[test]
+    fn test_network_timeout() {
+        // the remote end never runs, so the master gives up on it
+        let (mut first, _second) = connect_pair();
+        first.set_timeout(Duration::from_millis(100));
+        let (mut master, master_done) =
+            run(first, 8192, vec![(0, 0xff01, 0x24), (0, 0xff02, 0x81)])
+                .join()
+                .unwrap();
+        assert_eq!(master_done, Some(8 * 512));
+        assert_eq!(master.read(0xff01), 0xff);
+    }
+}
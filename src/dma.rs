@@ -1,4 +1,7 @@
-use crate::warnln;
+use crate::{
+    state::{Serializer, StateComponent},
+    warnln,
+};
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum DmaMode {
@@ -6,12 +9,36 @@ pub enum DmaMode {
     HBlank = 0x01,
 }
 
+/// The number of CPU cycles taken by a complete OAM DMA
+/// transfer, 160 bytes at one byte per M-cycle (4 cycles).
+pub const OAM_DMA_CYCLES: u16 = 640;
+
+#[derive(Clone)]
 pub struct Dma {
     source: u16,
     destination: u16,
     length: u16,
     mode: DmaMode,
     active: bool,
+
+    /// If the 16 bytes block of the H-Blank DMA transfer has
+    /// already been transferred in the current H-Blank.
+    hblank_done: bool,
+
+    /// The number of CPU cycles during which the CPU is stalled
+    /// because of a running HDMA transfer (CGB only).
+    stall_cycles: u16,
+
+    /// The value written to the OAM DMA register (0xFF46), this
+    /// is the high byte of the source address of the transfer.
+    value_dma: u8,
+
+    /// The number of CPU cycles remaining until the end of
+    /// the current OAM DMA transfer.
+    cycles_dma: u16,
+
+    /// If an OAM DMA transfer is currently in progress.
+    active_dma: bool,
 }
 
 impl Dma {
@@ -22,6 +49,11 @@ impl Dma {
             length: 0x0,
             mode: DmaMode::General,
             active: false,
+            hblank_done: false,
+            stall_cycles: 0,
+            value_dma: 0x0,
+            cycles_dma: 0x0,
+            active_dma: false,
         }
     }
 
@@ -31,14 +63,27 @@ impl Dma {
         self.length = 0x0;
         self.mode = DmaMode::General;
         self.active = false;
+        self.hblank_done = false;
+        self.stall_cycles = 0;
+        self.value_dma = 0x0;
+        self.cycles_dma = 0x0;
+        self.active_dma = false;
     }
 
     pub fn clock(&mut self, _cycles: u16) {}
 
     pub fn read(&mut self, addr: u16) -> u8 {
         match addr {
+            // 0xFF46 — DMA: OAM DMA source address & start
+            0xff46 => self.value_dma,
             // 0xFF55 — HDMA5: VRAM DMA length/mode/start (CGB only)
-            0xff45 => ((self.length >> 4) - 1) as u8 | ((self.active as u8) << 7),
+            0xff55 => {
+                // the lower 7 bits contain the remaining length (in blocks
+                // of 16 bytes minus one) and bit 7 is unset while active,
+                // reading 0xFF after the transfer is completed
+                ((self.length >> 4) as u8).wrapping_sub(1) & 0x7f
+                    | if self.active { 0x00 } else { 0x80 }
+            }
             _ => {
                 warnln!("Reading from unknown DMA location 0x{:04x}", addr);
                 0xff
@@ -48,18 +93,34 @@ impl Dma {
 
     pub fn write(&mut self, addr: u16, value: u8) {
         match addr {
+            // 0xFF46 — DMA: OAM DMA source address & start
+            0xff46 => {
+                self.value_dma = value;
+                self.cycles_dma = OAM_DMA_CYCLES;
+                self.active_dma = true;
+            }
             // 0xFF51 — HDMA1: VRAM DMA source high (CGB only)
             0xff51 => self.source = (self.source & 0x00ff) | ((value as u16) << 8),
             // 0xFF52 — HDMA2: VRAM DMA source low (CGB only)
             0xff52 => self.source = (self.source & 0xff00) | ((value & 0xf0) as u16),
             // 0xFF53 — HDMA3: VRAM DMA destination high (CGB only)
-            0xff53 => self.destination = (self.destination & 0x00ff) | ((value as u16) << 8),
+            0xff53 => {
+                self.destination =
+                    (self.destination & 0x00ff) | 0x8000 | (((value & 0x1f) as u16) << 8)
+            }
             // 0xFF54 — HDMA4: VRAM DMA destination low (CGB only)
             0xff54 => self.destination = (self.destination & 0xff00) | ((value & 0xf0) as u16),
             // 0xFF55 — HDMA5: VRAM DMA length/mode/start (CGB only)
             0xff55 => {
+                // writing bit 7 as zero while an H-Blank transfer is
+                // running cancels it, keeping the remaining length
+                if self.active && self.mode == DmaMode::HBlank && value & 0x80 == 0x00 {
+                    self.active = false;
+                    return;
+                }
                 self.length = (((value & 0x7f) + 0x1) as u16) << 4;
-                self.mode = match (value & 80) >> 7 {
+                self.hblank_done = false;
+                self.mode = match (value & 0x80) >> 7 {
                     0 => DmaMode::General,
                     1 => DmaMode::HBlank,
                     _ => DmaMode::General,
@@ -109,6 +170,77 @@ impl Dma {
     pub fn set_active(&mut self, value: bool) {
         self.active = value;
     }
+
+    pub fn hblank_done(&self) -> bool {
+        self.hblank_done
+    }
+
+    pub fn set_hblank_done(&mut self, value: bool) {
+        self.hblank_done = value;
+    }
+
+    pub fn stall_cycles(&self) -> u16 {
+        self.stall_cycles
+    }
+
+    pub fn set_stall_cycles(&mut self, value: u16) {
+        self.stall_cycles = value;
+    }
+
+    pub fn value_dma(&self) -> u8 {
+        self.value_dma
+    }
+
+    pub fn set_value_dma(&mut self, value: u8) {
+        self.value_dma = value;
+    }
+
+    /// Obtains the source address of the OAM DMA transfer, notice
+    /// that the sources above 0xDFFF are mapped into the WRAM.
+    pub fn source_dma(&self) -> u16 {
+        let value = if self.value_dma >= 0xe0 {
+            self.value_dma - 0x20
+        } else {
+            self.value_dma
+        };
+        (value as u16) << 8
+    }
+
+    pub fn cycles_dma(&self) -> u16 {
+        self.cycles_dma
+    }
+
+    pub fn set_cycles_dma(&mut self, value: u16) {
+        self.cycles_dma = value;
+    }
+
+    pub fn active_dma(&self) -> bool {
+        self.active_dma
+    }
+
+    pub fn set_active_dma(&mut self, value: bool) {
+        self.active_dma = value;
+    }
+}
+
+impl StateComponent for Dma {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.u16(&mut self.source);
+        s.u16(&mut self.destination);
+        s.u16(&mut self.length);
+        let mut mode = self.mode as u8;
+        s.u8(&mut mode);
+        self.mode = match mode {
+            0x01 => DmaMode::HBlank,
+            _ => DmaMode::General,
+        };
+        s.bool(&mut self.active);
+        s.bool(&mut self.hblank_done);
+        s.u16(&mut self.stall_cycles);
+        s.u8(&mut self.value_dma);
+        s.u16(&mut self.cycles_dma);
+        s.bool(&mut self.active_dma);
+    }
 }
 
 impl Default for Dma {
@@ -119,7 +251,7 @@ impl Default for Dma {
 
 #[cfg(test)]
 mod tests {
-    use crate::dma::{Dma, DmaMode};
+    use crate::dma::{Dma, DmaMode, OAM_DMA_CYCLES};
 
     #[test]
     fn test_dma_default() {
@@ -135,6 +267,9 @@ mod tests {
         dma.length = 0x9abc;
         dma.mode = DmaMode::HBlank;
         dma.active = true;
+        dma.value_dma = 0xc0;
+        dma.cycles_dma = 0x0100;
+        dma.active_dma = true;
 
         dma.reset();
 
@@ -143,6 +278,9 @@ mod tests {
         assert_eq!(dma.length, 0x0);
         assert_eq!(dma.mode, DmaMode::General);
         assert!(!dma.active);
+        assert_eq!(dma.value_dma, 0x0);
+        assert_eq!(dma.cycles_dma, 0x0);
+        assert!(!dma.active_dma);
     }
 
     #[test]
@@ -151,4 +289,34 @@ mod tests {
         dma.set_active(true);
         assert!(dma.active);
     }
+
+    #[test]
+    fn test_dma_oam_write() {
+        let mut dma = Dma::new();
+        dma.write(0xff46, 0xfe);
+        assert!(dma.active_dma);
+        assert_eq!(dma.cycles_dma, OAM_DMA_CYCLES);
+        assert_eq!(dma.read(0xff46), 0xfe);
+        assert_eq!(dma.source_dma(), 0xde00);
+    }
+
+    #[test]
+    fn test_hdma_registers() {
+        let mut dma = Dma::new();
+        dma.write(0xff53, 0x12);
+        dma.write(0xff54, 0x34);
+        assert_eq!(dma.destination, 0x9230);
+
+        dma.write(0xff55, 0x83);
+        assert_eq!(dma.mode, DmaMode::HBlank);
+        assert_eq!(dma.length, 0x40);
+        assert_eq!(dma.read(0xff55), 0x03);
+
+        dma.write(0xff55, 0x00);
+        assert!(!dma.active);
+        assert_eq!(dma.read(0xff55), 0x83);
+
+        dma.length = 0;
+        assert_eq!(dma.read(0xff55), 0xff);
+    }
 }
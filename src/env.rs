@@ -0,0 +1,313 @@
+use crate::{
+    gb::{GameBoy, GameBoyMode},
+    movie::Movie,
+    ppu::{DISPLAY_HEIGHT, DISPLAY_WIDTH},
+};
+
+/// Configuration of a learning environment, controlling how
+/// the emulator is set up and how observations are built.
+#[derive(Clone)]
+pub struct EnvironmentConfig {
+    /// The Game Boy mode (DMG, CGB, etc.) to be used.
+    pub mode: GameBoyMode,
+
+    /// If the boot ROM should be executed at reset, otherwise
+    /// the post boot state is set directly.
+    pub boot: bool,
+
+    /// The default number of frames that each step runs with
+    /// the same action (frame skip), must be at least one.
+    pub frame_skip: u32,
+
+    /// If the observed frame should be converted into a single
+    /// channel grayscale (luminance) image.
+    pub grayscale: bool,
+
+    /// The integer factor by which the observed frame is
+    /// downscaled (using box averaging), 1 means no scaling.
+    pub downscale: usize,
+
+    /// The maximum number of steps of an episode, after which
+    /// the episode is considered done.
+    pub max_steps: Option<u32>,
+
+    /// Input movie (recorded from power-on) that is played back
+    /// once at creation, taking the system into the state from
+    /// which every episode starts.
+    pub start: Option<Movie>,
+}
+
+impl Default for EnvironmentConfig {
+    fn default() -> Self {
+        Self {
+            mode: GameBoyMode::Dmg,
+            boot: true,
+            frame_skip: 4,
+            grayscale: false,
+            downscale: 1,
+            max_steps: None,
+            start: None,
+        }
+    }
+}
+
+/// Information about the current episode of the environment.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct EpisodeInfo {
+    /// The number of steps run in the episode.
+    pub steps: u32,
+
+    /// The number of frames run in the episode.
+    pub frames: u32,
+
+    /// The number of frames in which the game did not poll
+    /// the input (lag frames) during the episode.
+    pub lag_frames: u32,
+
+    /// The number of CPU cycles run in the episode.
+    pub cycles: u64,
+
+    /// If the episode has reached its end.
+    pub done: bool,
+}
+
+/// Observation of the environment after a step, to be consumed
+/// by the agent (reward computation is game specific and should
+/// be done by the caller using the RAM view).
+pub struct Observation {
+    /// The (possibly downscaled and grayscale) frame buffer,
+    /// with either three (RGB) or one (grayscale) channel.
+    pub frame: Vec<u8>,
+
+    /// The width of the observed frame in pixels.
+    pub width: usize,
+
+    /// The height of the observed frame in pixels.
+    pub height: usize,
+
+    /// View over the work RAM (0xC000-0xDFFF) followed by
+    /// the high RAM (0xFF80-0xFFFE) of the system.
+    pub ram: Vec<u8>,
+
+    /// The information about the current episode.
+    pub info: EpisodeInfo,
+}
+
+/// Gym-style environment wrapper over the Game Boy emulator,
+/// meant to be used in the training of reinforcement learning
+/// agents. Audio emulation is disabled for performance.
+pub struct Environment {
+    game_boy: GameBoy,
+
+    /// Snapshot of the system in the start state of every
+    /// episode (after the start movie), restored on reset.
+    start: GameBoy,
+
+    config: EnvironmentConfig,
+    info: EpisodeInfo,
+}
+
+impl Environment {
+    /// Creates the environment, loading the ROM and playing back
+    /// the start movie (if any) into the start state of the
+    /// episodes, an error is returned in case the start movie
+    /// can't be played back with the ROM.
+    pub fn new(rom_data: &[u8], config: EnvironmentConfig) -> Result<Self, String> {
+        let mut start = GameBoy::new(Some(config.mode));
+        start.set_apu_enabled(false);
+        start.load(config.boot);
+        start.load_rom(rom_data);
+        if !config.boot {
+            start.boot();
+        }
+
+        if let Some(movie) = config.start.clone() {
+            let frames = movie.frames();
+            start.play_movie(movie)?;
+            while let Some(session) = start.movie() {
+                if session.frame() >= frames {
+                    break;
+                }
+                start.run_frame();
+            }
+            start.stop_movie();
+        }
+
+        Ok(Self {
+            game_boy: start.clone(),
+            start,
+            config,
+            info: EpisodeInfo::default(),
+        })
+    }
+
+    /// Resets the environment into the start state, restoring
+    /// the snapshot taken at creation, returning the initial
+    /// observation of the new episode.
+    pub fn reset(&mut self) -> Observation {
+        self.game_boy = self.start.clone();
+        self.info = EpisodeInfo::default();
+        self.observation()
+    }
+
+    /// Runs a step of the environment holding the provided action
+    /// (input state bit mask, see `GameBoy::set_input_state()`)
+    /// for the given number of frames, or the configured frame
+    /// skip if none is provided.
+    pub fn step(&mut self, action_mask: u8, frames: Option<u32>) -> Observation {
+        let frames = frames.unwrap_or(self.config.frame_skip).max(1);
+        self.game_boy.set_input_state(action_mask);
+        for _ in 0..frames {
+            let result = self.game_boy.run_frame();
+            self.info.cycles += result.cycles as u64;
+            self.info.frames += 1;
+            if !result.polled {
+                self.info.lag_frames += 1;
+            }
+        }
+        self.info.steps += 1;
+        if let Some(max_steps) = self.config.max_steps {
+            self.info.done = self.info.steps >= max_steps;
+        }
+        self.observation()
+    }
+
+    pub fn observation(&mut self) -> Observation {
+        let (frame, width, height) = self.frame();
+        Observation {
+            frame,
+            width,
+            height,
+            ram: self.ram(),
+            info: self.info,
+        }
+    }
+
+    /// Builds the observed frame from the current frame buffer,
+    /// applying the downscale and grayscale conversions.
+    pub fn frame(&mut self) -> (Vec<u8>, usize, usize) {
+        let factor = self.config.downscale.max(1);
+        let channels = if self.config.grayscale { 1 } else { 3 };
+        let width = DISPLAY_WIDTH / factor;
+        let height = DISPLAY_HEIGHT / factor;
+        let frame_buffer = self.game_boy.frame_buffer();
+
+        if factor == 1 && channels == 3 {
+            return (frame_buffer.to_vec(), width, height);
+        }
+
+        let mut frame = vec![0u8; width * height * channels];
+        let area = (factor * factor) as u32;
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0u32; 3];
+                for dy in 0..factor {
+                    for dx in 0..factor {
+                        let index = ((y * factor + dy) * DISPLAY_WIDTH + x * factor + dx) * 3;
+                        sum[0] += frame_buffer[index] as u32;
+                        sum[1] += frame_buffer[index + 1] as u32;
+                        sum[2] += frame_buffer[index + 2] as u32;
+                    }
+                }
+                let index = (y * width + x) * channels;
+                if channels == 1 {
+                    // uses the ITU-R BT.601 luma coefficients (in fixed point)
+                    // to convert the averaged RGB value into grayscale
+                    frame[index] =
+                        ((sum[0] * 299 + sum[1] * 587 + sum[2] * 114) / (area * 1000)) as u8;
+                } else {
+                    frame[index] = (sum[0] / area) as u8;
+                    frame[index + 1] = (sum[1] / area) as u8;
+                    frame[index + 2] = (sum[2] / area) as u8;
+                }
+            }
+        }
+
+        (frame, width, height)
+    }
+
+    /// Obtains the RAM view of the system, composed by the work
+    /// RAM (0xC000-0xDFFF) followed by the high RAM (0xFF80-0xFFFE).
+    pub fn ram(&mut self) -> Vec<u8> {
+        let mut ram = self.game_boy.mmu().read_many(0xc000, 0x2000);
+        ram.extend(self.game_boy.mmu().read_many(0xff80, 0x007f));
+        ram
+    }
+
+    pub fn game_boy(&mut self) -> &mut GameBoy {
+        &mut self.game_boy
+    }
+
+    pub fn game_boy_i(&self) -> &GameBoy {
+        &self.game_boy
+    }
+
+    pub fn config(&self) -> &EnvironmentConfig {
+        &self.config
+    }
+
+    pub fn info(&self) -> EpisodeInfo {
+        self.info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        env::{Environment, EnvironmentConfig},
+        gb::GameBoyMode,
+        movie::{Movie, MovieStart},
+        util::read_file,
+    };
+
+    #[test]
+    fn test_environment_step() {
+        let rom_data = read_file("res/roms/test/dmg_acid2.gb");
+        let config = EnvironmentConfig {
+            grayscale: true,
+            downscale: 2,
+            max_steps: Some(2),
+            ..EnvironmentConfig::default()
+        };
+        let mut environment = Environment::new(&rom_data, config).unwrap();
+
+        let observation = environment.step(0x00, None);
+        assert_eq!(observation.width, 80);
+        assert_eq!(observation.height, 72);
+        assert_eq!(observation.frame.len(), 80 * 72);
+        assert_eq!(observation.ram.len(), 0x2000 + 0x007f);
+        assert_eq!(observation.info.frames, 4);
+        assert!(!observation.info.done);
+
+        let observation = environment.step(0x00, Some(1));
+        assert_eq!(observation.info.frames, 5);
+        assert!(observation.info.done);
+
+        let observation = environment.reset();
+        assert_eq!(observation.info.steps, 0);
+    }
+
+    #[test]
+    fn test_environment_reset() {
+        let rom_data = read_file("res/roms/test/dmg_acid2.gb");
+        let mut environment = Environment::new(&rom_data, EnvironmentConfig::default()).unwrap();
+        let start = environment.observation();
+        let first = environment.step(0x00, Some(60));
+        assert_ne!(first.frame, start.frame);
+
+        // each episode restarts from the exact same state
+        let observation = environment.reset();
+        assert_eq!(observation.frame, start.frame);
+        assert_eq!(observation.ram, start.ram);
+        let second = environment.step(0x00, Some(60));
+        assert_eq!(second.frame, first.frame);
+        assert_eq!(second.ram, first.ram);
+
+        let movie = Movie::new(GameBoyMode::Dmg, MovieStart::PowerOn, 0x0);
+        let config = EnvironmentConfig {
+            start: Some(movie),
+            ..EnvironmentConfig::default()
+        };
+        assert!(Environment::new(&rom_data, config).is_err());
+    }
+}
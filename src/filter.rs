@@ -0,0 +1,480 @@
+use core::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::ppu::{Pixel, RGBA_SIZE, RGB_SIZE};
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// The CPU side filters that can be used to upscale the
+/// RGB frame buffer produced by the PPU.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScaleFilter {
+    /// Nearest neighbour (integer) scaling, each pixel is
+    /// repeated as a block of the requested scale.
+    Nearest = 0,
+
+    /// The Scale2x (AdvMAME2x) pixel art scaler (2x).
+    Scale2x = 1,
+
+    /// The Scale3x (AdvMAME3x) pixel art scaler (3x).
+    Scale3x = 2,
+
+    /// The original EPX (Eric's Pixel Expansion) scaler (2x),
+    /// Scale2x is derived from it and produces the same results.
+    Epx = 3,
+
+    /// Simplified (level 1) version of the xBR scaler (2x) that
+    /// uses edge detection to smooth diagonal lines.
+    Xbr = 4,
+
+    /// Nearest neighbour scaling with a dark grid between the
+    /// pixels, emulating the dot matrix of the LCD.
+    LcdGrid = 5,
+}
+
+impl ScaleFilter {
+    pub fn description(&self) -> &'static str {
+        match self {
+            ScaleFilter::Nearest => "Nearest",
+            ScaleFilter::Scale2x => "Scale2x",
+            ScaleFilter::Scale3x => "Scale3x",
+            ScaleFilter::Epx => "EPX",
+            ScaleFilter::Xbr => "xBR-lite",
+            ScaleFilter::LcdGrid => "LCD Grid",
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0 => ScaleFilter::Nearest,
+            1 => ScaleFilter::Scale2x,
+            2 => ScaleFilter::Scale3x,
+            3 => ScaleFilter::Epx,
+            4 => ScaleFilter::Xbr,
+            5 => ScaleFilter::LcdGrid,
+            _ => panic!("Invalid scale filter value: {}", value),
+        }
+    }
+
+    pub fn from_string(value: &str) -> Self {
+        match value {
+            "nearest" => ScaleFilter::Nearest,
+            "scale2x" => ScaleFilter::Scale2x,
+            "scale3x" => ScaleFilter::Scale3x,
+            "epx" => ScaleFilter::Epx,
+            "xbr" => ScaleFilter::Xbr,
+            "lcd" => ScaleFilter::LcdGrid,
+            _ => panic!("Invalid scale filter value: {}", value),
+        }
+    }
+
+    /// Obtains the scale factor effectively applied by the filter
+    /// for the requested scale, the pixel art scalers have a fixed
+    /// factor and the LCD grid requires at least 2x.
+    pub fn factor(&self, scale: usize) -> usize {
+        match self {
+            ScaleFilter::Nearest => scale.max(1),
+            ScaleFilter::Scale2x | ScaleFilter::Epx | ScaleFilter::Xbr => 2,
+            ScaleFilter::Scale3x => 3,
+            ScaleFilter::LcdGrid => scale.max(2),
+        }
+    }
+}
+
+impl Display for ScaleFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+/// Scales the provided RGB buffer (with the given dimensions in
+/// pixels) using the selected filter, returning a new RGB buffer
+/// with the dimensions multiplied by `filter.factor(scale)`.
+pub fn scale_frame(
+    filter: ScaleFilter,
+    buffer: &[u8],
+    width: usize,
+    height: usize,
+    scale: usize,
+) -> Vec<u8> {
+    let source = Source {
+        buffer,
+        width,
+        height,
+    };
+    match filter {
+        ScaleFilter::Nearest => nearest(&source, filter.factor(scale), false),
+        ScaleFilter::Scale2x => scale2x(&source),
+        ScaleFilter::Scale3x => scale3x(&source),
+        ScaleFilter::Epx => epx(&source),
+        ScaleFilter::Xbr => xbr(&source),
+        ScaleFilter::LcdGrid => nearest(&source, filter.factor(scale), true),
+    }
+}
+
+/// Scales the provided RGB buffer using the selected filter,
+/// returning an RGBA buffer (fully opaque) ready to be used
+/// by targets that require an alpha channel.
+pub fn scale_frame_rgba(
+    filter: ScaleFilter,
+    buffer: &[u8],
+    width: usize,
+    height: usize,
+    scale: usize,
+) -> Vec<u8> {
+    rgb_to_rgba(&scale_frame(filter, buffer, width, height, scale))
+}
+
+/// Converts the provided RGB buffer into an RGBA one,
+/// with all the pixels fully opaque.
+pub fn rgb_to_rgba(buffer: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(buffer.len() / RGB_SIZE * RGBA_SIZE);
+    for pixel in buffer.chunks_exact(RGB_SIZE) {
+        result.extend_from_slice(pixel);
+        result.push(0xff);
+    }
+    result
+}
+
+/// The source RGB buffer of a scaling operation, providing
+/// access to the pixels with the coordinates clamped to the
+/// edges of the image.
+struct Source<'a> {
+    buffer: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl Source<'_> {
+    fn get(&self, x: isize, y: isize) -> Pixel {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        let offset = (y * self.width + x) * RGB_SIZE;
+        [
+            self.buffer[offset],
+            self.buffer[offset + 1],
+            self.buffer[offset + 2],
+        ]
+    }
+}
+
+/// The target RGB buffer of a scaling operation.
+struct Target {
+    buffer: Vec<u8>,
+    width: usize,
+}
+
+impl Target {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            buffer: vec![0u8; width * height * RGB_SIZE],
+            width,
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, pixel: Pixel) {
+        let offset = (y * self.width + x) * RGB_SIZE;
+        self.buffer[offset..offset + RGB_SIZE].copy_from_slice(&pixel);
+    }
+}
+
+fn nearest(source: &Source, factor: usize, grid: bool) -> Vec<u8> {
+    let mut target = Target::new(source.width * factor, source.height * factor);
+    for y in 0..source.height {
+        for x in 0..source.width {
+            let pixel = source.get(x as isize, y as isize);
+
+            // the last row and column of each block are darkened
+            // (to 3/4) in the LCD grid mode, creating the grid
+            let dark = pixel.map(|value| (value as u16 * 3 / 4) as u8);
+            for block_y in 0..factor {
+                for block_x in 0..factor {
+                    let is_grid = grid && (block_x == factor - 1 || block_y == factor - 1);
+                    target.set(
+                        x * factor + block_x,
+                        y * factor + block_y,
+                        if is_grid { dark } else { pixel },
+                    );
+                }
+            }
+        }
+    }
+    target.buffer
+}
+
+fn scale2x(source: &Source) -> Vec<u8> {
+    let mut target = Target::new(source.width * 2, source.height * 2);
+    for y in 0..source.height {
+        for x in 0..source.width {
+            let (xi, yi) = (x as isize, y as isize);
+            let b = source.get(xi, yi - 1);
+            let d = source.get(xi - 1, yi);
+            let e = source.get(xi, yi);
+            let f = source.get(xi + 1, yi);
+            let h = source.get(xi, yi + 1);
+
+            let (mut e0, mut e1, mut e2, mut e3) = (e, e, e, e);
+            if b != h && d != f {
+                if d == b {
+                    e0 = d;
+                }
+                if b == f {
+                    e1 = f;
+                }
+                if d == h {
+                    e2 = d;
+                }
+                if h == f {
+                    e3 = f;
+                }
+            }
+
+            target.set(x * 2, y * 2, e0);
+            target.set(x * 2 + 1, y * 2, e1);
+            target.set(x * 2, y * 2 + 1, e2);
+            target.set(x * 2 + 1, y * 2 + 1, e3);
+        }
+    }
+    target.buffer
+}
+
+fn scale3x(source: &Source) -> Vec<u8> {
+    let mut target = Target::new(source.width * 3, source.height * 3);
+    for y in 0..source.height {
+        for x in 0..source.width {
+            let (xi, yi) = (x as isize, y as isize);
+            let a = source.get(xi - 1, yi - 1);
+            let b = source.get(xi, yi - 1);
+            let c = source.get(xi + 1, yi - 1);
+            let d = source.get(xi - 1, yi);
+            let e = source.get(xi, yi);
+            let f = source.get(xi + 1, yi);
+            let g = source.get(xi - 1, yi + 1);
+            let h = source.get(xi, yi + 1);
+            let i = source.get(xi + 1, yi + 1);
+
+            let mut block = [e; 9];
+            if b != h && d != f {
+                if d == b {
+                    block[0] = d;
+                }
+                if (d == b && e != c) || (b == f && e != a) {
+                    block[1] = b;
+                }
+                if b == f {
+                    block[2] = f;
+                }
+                if (d == b && e != g) || (d == h && e != a) {
+                    block[3] = d;
+                }
+                if (b == f && e != i) || (h == f && e != c) {
+                    block[5] = f;
+                }
+                if d == h {
+                    block[6] = d;
+                }
+                if (d == h && e != i) || (h == f && e != g) {
+                    block[7] = h;
+                }
+                if h == f {
+                    block[8] = f;
+                }
+            }
+
+            for (index, pixel) in block.iter().enumerate() {
+                target.set(x * 3 + index % 3, y * 3 + index / 3, *pixel);
+            }
+        }
+    }
+    target.buffer
+}
+
+fn epx(source: &Source) -> Vec<u8> {
+    let mut target = Target::new(source.width * 2, source.height * 2);
+    for y in 0..source.height {
+        for x in 0..source.width {
+            let (xi, yi) = (x as isize, y as isize);
+            let p = source.get(xi, yi);
+            let a = source.get(xi, yi - 1);
+            let b = source.get(xi + 1, yi);
+            let c = source.get(xi - 1, yi);
+            let d = source.get(xi, yi + 1);
+
+            let (mut p1, mut p2, mut p3, mut p4) = (p, p, p, p);
+            if c == a {
+                p1 = a;
+            }
+            if a == b {
+                p2 = b;
+            }
+            if d == c {
+                p3 = c;
+            }
+            if b == d {
+                p4 = d;
+            }
+
+            // in case three or more of the neighbours are identical
+            // the original pixel is kept for the complete block
+            let equal = [a == b, a == c, a == d, b == c, b == d, c == d]
+                .iter()
+                .filter(|value| **value)
+                .count();
+            if equal >= 3 {
+                p1 = p;
+                p2 = p;
+                p3 = p;
+                p4 = p;
+            }
+
+            target.set(x * 2, y * 2, p1);
+            target.set(x * 2 + 1, y * 2, p2);
+            target.set(x * 2, y * 2 + 1, p3);
+            target.set(x * 2 + 1, y * 2 + 1, p4);
+        }
+    }
+    target.buffer
+}
+
+fn xbr(source: &Source) -> Vec<u8> {
+    let mut target = Target::new(source.width * 2, source.height * 2);
+    for y in 0..source.height {
+        for x in 0..source.width {
+            // runs the edge detection for each of the corners of
+            // the pixel, mirroring the neighbourhood accordingly
+            for (sx, sy) in [(1isize, 1isize), (-1, 1), (1, -1), (-1, -1)] {
+                let get =
+                    |dx: isize, dy: isize| source.get(x as isize + dx * sx, y as isize + dy * sy);
+                let e = get(0, 0);
+                let b = get(0, -1);
+                let c = get(1, -1);
+                let d = get(-1, 0);
+                let f = get(1, 0);
+                let g = get(-1, 1);
+                let h = get(0, 1);
+                let i = get(1, 1);
+                let f4 = get(2, 0);
+                let i4 = get(2, 1);
+                let h5 = get(0, 2);
+                let i5 = get(1, 2);
+
+                let edge = distance(e, c)
+                    + distance(e, g)
+                    + distance(i, h5)
+                    + distance(i, f4)
+                    + 4.0 * distance(h, f);
+                let cross = distance(h, d)
+                    + distance(h, i5)
+                    + distance(f, i4)
+                    + distance(f, b)
+                    + 4.0 * distance(e, i);
+
+                let pixel = if edge < cross {
+                    let color = if distance(e, f) <= distance(e, h) {
+                        f
+                    } else {
+                        h
+                    };
+                    blend(e, color)
+                } else {
+                    e
+                };
+
+                target.set(x * 2 + (sx > 0) as usize, y * 2 + (sy > 0) as usize, pixel);
+            }
+        }
+    }
+    target.buffer
+}
+
+/// Computes the perceptual distance between two colors, using
+/// the (weighted) difference of their YUV components.
+fn distance(first: Pixel, second: Pixel) -> f32 {
+    let r = first[0] as f32 - second[0] as f32;
+    let g = first[1] as f32 - second[1] as f32;
+    let b = first[2] as f32 - second[2] as f32;
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let u = -0.169 * r - 0.331 * g + 0.5 * b;
+    let v = 0.5 * r - 0.419 * g - 0.081 * b;
+    48.0 * y.abs() + 7.0 * u.abs() + 6.0 * v.abs()
+}
+
+fn blend(first: Pixel, second: Pixel) -> Pixel {
+    [
+        ((first[0] as u16 + second[0] as u16) / 2) as u8,
+        ((first[1] as u16 + second[1] as u16) / 2) as u8,
+        ((first[2] as u16 + second[2] as u16) / 2) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rgb_to_rgba, scale_frame, ScaleFilter};
+
+    const W: [u8; 3] = [0xff, 0xff, 0xff];
+    const K: [u8; 3] = [0x00, 0x00, 0x00];
+
+    fn image(pixels: &[[u8; 3]]) -> Vec<u8> {
+        pixels.concat()
+    }
+
+    fn pixel(buffer: &[u8], width: usize, x: usize, y: usize) -> [u8; 3] {
+        let offset = (y * width + x) * 3;
+        [buffer[offset], buffer[offset + 1], buffer[offset + 2]]
+    }
+
+    #[test]
+    fn test_nearest() {
+        let buffer = image(&[W, K]);
+        let result = scale_frame(ScaleFilter::Nearest, &buffer, 2, 1, 3);
+        assert_eq!(result.len(), 6 * 3 * 3);
+        assert_eq!(pixel(&result, 6, 2, 2), W);
+        assert_eq!(pixel(&result, 6, 3, 0), K);
+    }
+
+    #[test]
+    fn test_scale2x() {
+        // the diagonal line is smoothed by the scaler, with the
+        // corners of the pixels following the diagonal
+        let buffer = image(&[K, W, W, K]);
+        let result = scale_frame(ScaleFilter::Scale2x, &buffer, 2, 2, 1);
+        assert_eq!(result.len(), 4 * 4 * 3);
+        assert_eq!(pixel(&result, 4, 1, 0), K);
+        assert_eq!(pixel(&result, 4, 1, 1), W);
+        assert_eq!(pixel(&result, 4, 2, 1), K);
+    }
+
+    #[test]
+    fn test_epx() {
+        let buffer = image(&[K, W, W, W, K, K, W, K, W]);
+        let result = scale_frame(ScaleFilter::Epx, &buffer, 3, 3, 1);
+        let expected = scale_frame(ScaleFilter::Scale2x, &buffer, 3, 3, 1);
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_scale3x_xbr() {
+        let buffer = image(&[W; 4]);
+        let result = scale_frame(ScaleFilter::Scale3x, &buffer, 2, 2, 1);
+        assert_eq!(result, image(&[W; 36]));
+        let result = scale_frame(ScaleFilter::Xbr, &buffer, 2, 2, 1);
+        assert_eq!(result, image(&[W; 16]));
+    }
+
+    #[test]
+    fn test_lcd_grid() {
+        let buffer = image(&[W]);
+        let result = scale_frame(ScaleFilter::LcdGrid, &buffer, 1, 1, 1);
+        assert_eq!(result.len(), 2 * 2 * 3);
+        assert_eq!(pixel(&result, 2, 0, 0), W);
+        assert_eq!(pixel(&result, 2, 1, 1), [0xbf, 0xbf, 0xbf]);
+    }
+
+    #[test]
+    fn test_rgb_to_rgba() {
+        let result = rgb_to_rgba(&image(&[W, K]));
+        assert_eq!(result, vec![0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0xff]);
+    }
+}
@@ -1,24 +1,34 @@
 use std::{
-    cell::RefCell,
+    cmp::min,
     collections::VecDeque,
     fmt::{self, Display, Formatter},
-    rc::Rc,
+    sync::{Arc, Mutex},
 };
 
 use crate::{
     apu::Apu,
+    cdl::CodeDataLogger,
     cpu::Cpu,
     data::{BootRom, CGB_BOOT, DMG_BOOT, DMG_BOOTIX, MGB_BOOTIX, SGB_BOOT},
     devices::{printer::PrinterDevice, stdout::StdoutDevice},
     dma::Dma,
+    filter::{scale_frame_rgba, ScaleFilter},
+    gbs::GbsFile,
     gen::{COMPILATION_DATE, COMPILATION_TIME, COMPILER, COMPILER_VERSION, VERSION},
     mmu::Mmu,
+    movie::{Movie, MovieMode, MovieSession, MovieStart},
     pad::{Pad, PadKey},
-    ppu::{Ppu, PpuMode, Tile, DISPLAY_HEIGHT, DISPLAY_WIDTH, FRAME_BUFFER_SIZE},
+    ppu::{
+        ColorCorrection, ObjectData, Ppu, PpuMode, PpuRenderer, Tile, TileData, DISPLAY_HEIGHT,
+        DISPLAY_WIDTH, FRAME_BUFFER_SIZE, WIDE_FRAME_BUFFER_SIZE,
+    },
     rom::{Cartridge, RamSize},
     serial::{NullDevice, Serial, SerialDevice},
+    state::{read_header, write_header, Serializer, StateComponent, STATE_HEADER_SIZE},
     timer::Timer,
-    util::read_file,
+    util::{read_file, SharedThread},
+    vgm::VgmLogger,
+    wav::WavRecorder,
 };
 
 #[cfg(feature = "wasm")]
@@ -42,7 +52,7 @@ use std::{
 // CGB = Game Boy Color
 // SGB = Super Game Boy
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum GameBoyMode {
     Dmg = 1,
     Cgb = 2,
@@ -287,9 +297,26 @@ pub struct Registers {
     pub lyc: u8,
 }
 
+/// Result of a stepping operation (eg: frame advance) that
+/// describes the amount of emulation that has been run.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ClockFrame {
+    /// The number of CPU cycles that have been clocked.
+    pub cycles: u32,
+
+    /// The index of the PPU frame at the end of the step.
+    pub frame: u16,
+
+    /// If the input (P1 register) has been polled by the
+    /// running game during the step, a frame without input
+    /// polling is usually known as a lag frame.
+    pub polled: bool,
+}
+
 pub trait AudioProvider {
     fn audio_output(&self) -> u8;
-    fn audio_buffer(&self) -> &VecDeque<u8>;
+    fn audio_buffer(&self) -> &VecDeque<i16>;
     fn clear_audio_buffer(&mut self);
 }
 
@@ -346,13 +373,24 @@ pub struct GameBoy {
     /// used as reference or the rest of the components.
     cpu: Cpu,
 
-    /// The reference counted and mutable reference to
+    /// The thread safe shared and mutable reference to
     /// Game Boy configuration structure that can be
     /// used by the GB components to access global
     /// configuration values on the current emulator.
     /// If performance is required (may value access)
     /// the values should be cloned and stored locally.
-    gbc: Rc<RefCell<GameBoyConfig>>,
+    gbc: SharedThread<GameBoyConfig>,
+
+    /// The currently running input movie session (either
+    /// recording or playing back), if any.
+    movie: Option<MovieSession>,
+
+    /// The GBS file currently loaded (if any), used to rebuild
+    /// the synthetic cartridge whenever a new track is selected.
+    gbs: Option<GbsFile>,
+
+    /// The index (0 based) of the GBS track being played.
+    gbs_track: u8,
 }
 
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
@@ -360,7 +398,7 @@ impl GameBoy {
     #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
     pub fn new(mode: Option<GameBoyMode>) -> Self {
         let mode = mode.unwrap_or(GameBoyMode::Dmg);
-        let gbc = Rc::new(RefCell::new(GameBoyConfig {
+        let gbc = Arc::new(Mutex::new(GameBoyConfig {
             mode,
             ppu_enabled: true,
             apu_enabled: true,
@@ -372,11 +410,11 @@ impl GameBoy {
 
         let components = Components {
             ppu: Ppu::new(mode, gbc.clone()),
-            apu: Apu::default(),
+            apu: Apu::new(mode, 44100, 1.0, GameBoy::CPU_FREQ),
             dma: Dma::default(),
             pad: Pad::default(),
             timer: Timer::default(),
-            serial: Serial::default(),
+            serial: Serial::with_mode(mode),
         };
         let mmu = Mmu::new(components, mode, gbc.clone());
         let cpu = Cpu::new(mmu, gbc.clone());
@@ -391,6 +429,9 @@ impl GameBoy {
             clock_freq: GameBoy::CPU_FREQ,
             cpu,
             gbc,
+            movie: None,
+            gbs: None,
+            gbs_track: 0,
         }
     }
 
@@ -404,6 +445,9 @@ impl GameBoy {
     }
 
     pub fn clock(&mut self) -> u16 {
+        if self.movie.is_some() {
+            self.movie_play();
+        }
         let cycles = self.cpu_clock() as u16;
         let cycles_n = cycles / self.multiplier() as u16;
         if self.ppu_enabled {
@@ -421,6 +465,9 @@ impl GameBoy {
         if self.serial_enabled {
             self.serial_clock(cycles);
         }
+        if self.movie.is_some() {
+            self.movie_clock(cycles);
+        }
         cycles
     }
 
@@ -431,6 +478,9 @@ impl GameBoy {
     /// the PPU where only one mode switch operation is expected
     /// per each clock call.
     pub fn clock_m(&mut self, count: usize) -> u16 {
+        if self.movie.is_some() {
+            self.movie_play();
+        }
         let mut cycles = 0u16;
         for _ in 0..count {
             cycles += self.cpu_clock() as u16;
@@ -451,17 +501,97 @@ impl GameBoy {
         if self.serial_enabled {
             self.serial_clock(cycles);
         }
+        if self.movie.is_some() {
+            self.movie_clock(cycles);
+        }
         cycles
     }
 
     pub fn key_press(&mut self, key: PadKey) {
+        if let Some(movie) = self.movie.as_mut() {
+            if movie.mode() == MovieMode::Playing {
+                return;
+            }
+            movie.record(key, true);
+        }
         self.pad().key_press(key);
     }
 
     pub fn key_lift(&mut self, key: PadKey) {
+        if let Some(movie) = self.movie.as_mut() {
+            if movie.mode() == MovieMode::Playing {
+                return;
+            }
+            movie.record(key, false);
+        }
         self.pad().key_lift(key);
     }
 
+    /// Clocks the system until the PPU enters the V-Blank period,
+    /// meaning that a new frame is ready in the frame buffer.
+    /// In case the LCD is off (no V-Blank) the clocking stops
+    /// after the equivalent time of a complete frame.
+    pub fn run_frame(&mut self) -> ClockFrame {
+        let limit = GameBoy::LCD_CYCLES * self.multiplier() as u32;
+        let mut cycles = 0u32;
+        let mut last_mode = self.ppu_i().mode();
+        self.pad().set_polled(false);
+        loop {
+            cycles += self.clock() as u32;
+            let mode = self.ppu_i().mode();
+            if (mode == PpuMode::VBlank && last_mode != PpuMode::VBlank) || cycles >= limit {
+                break;
+            }
+            last_mode = mode;
+        }
+        ClockFrame {
+            cycles,
+            frame: self.ppu_i().frame_index(),
+            polled: self.pad_i().polled(),
+        }
+    }
+
+    /// Clocks the system until the running game reads the
+    /// joypad register (P1 at 0xFF00), the ideal moment to
+    /// change the input state in tool-assisted runs.
+    /// To avoid an endless loop for games that do not poll the
+    /// input, clocking stops after the time of a complete frame.
+    pub fn run_until_input_poll(&mut self) -> ClockFrame {
+        let limit = GameBoy::LCD_CYCLES * self.multiplier() as u32;
+        let mut cycles = 0u32;
+        self.pad().set_polled(false);
+        while !self.pad_i().polled() && cycles < limit {
+            cycles += self.clock() as u32;
+        }
+        ClockFrame {
+            cycles,
+            frame: self.ppu_i().frame_index(),
+            polled: self.pad_i().polled(),
+        }
+    }
+
+    /// Sets the complete input state at once from a bit mask
+    /// with one bit per key, in the order (from the least
+    /// significant bit): A, B, Select, Start, Right, Left, Up, Down.
+    pub fn set_input_state(&mut self, mask: u8) {
+        let state = self.pad_i().state();
+        for key in (0..8).filter_map(PadKey::from_u8) {
+            let key_mask = key.mask();
+            if state & key_mask == mask & key_mask {
+                continue;
+            }
+            if mask & key_mask == key_mask {
+                self.key_press(key);
+            } else {
+                self.key_lift(key);
+            }
+        }
+    }
+
+    pub fn input_state(&self) -> u8 {
+        self.pad_i().state()
+    }
+
     pub fn cpu_clock(&mut self) -> u8 {
         self.cpu.clock()
     }
@@ -498,10 +628,83 @@ impl GameBoy {
         self.ppu().frame_index()
     }
 
+    pub fn ppu_lcd(&mut self) -> bool {
+        self.ppu().switch_lcd()
+    }
+
+    pub fn ppu_renderer(&mut self) -> PpuRenderer {
+        self.ppu().renderer()
+    }
+
+    pub fn set_ppu_renderer(&mut self, value: PpuRenderer) {
+        self.ppu().set_renderer(value);
+    }
+
+    pub fn ppu_unlimited_objects(&mut self) -> bool {
+        self.ppu().unlimited_objects()
+    }
+
+    pub fn set_ppu_unlimited_objects(&mut self, value: bool) {
+        self.ppu().set_unlimited_objects(value);
+    }
+
+    pub fn ppu_widescreen(&mut self) -> bool {
+        self.ppu().widescreen()
+    }
+
+    pub fn set_ppu_widescreen(&mut self, value: bool) {
+        self.ppu().set_widescreen(value);
+    }
+
+    pub fn ppu_color_correction(&mut self) -> ColorCorrection {
+        self.ppu().color_correction()
+    }
+
+    pub fn set_ppu_color_correction(&mut self, value: ColorCorrection) {
+        self.ppu().set_color_correction(value);
+    }
+
+    pub fn ppu_frame_blending(&mut self) -> bool {
+        self.ppu().frame_blending()
+    }
+
+    pub fn set_ppu_frame_blending(&mut self, value: bool) {
+        self.ppu().set_frame_blending(value);
+    }
+
     pub fn boot(&mut self) {
         self.cpu.boot();
     }
 
+    pub fn gbs_loaded(&self) -> bool {
+        self.gbs.is_some()
+    }
+
+    pub fn gbs_track(&self) -> u8 {
+        self.gbs_track
+    }
+
+    pub fn gbs_track_count(&self) -> u8 {
+        self.gbs.as_ref().map(|gbs| gbs.song_count()).unwrap_or(0)
+    }
+
+    /// Selects the track (0 based) of the loaded GBS file to be
+    /// played, resetting the system and booting the synthetic
+    /// cartridge built for that track, in case no GBS file is
+    /// loaded nothing is done.
+    pub fn set_gbs_track(&mut self, track: u8) {
+        let track = min(track, self.gbs_track_count().saturating_sub(1));
+        let rom = match self.gbs.as_ref() {
+            Some(gbs) => gbs.cartridge(track),
+            None => return,
+        };
+        self.gbs_track = track;
+        self.reset();
+        self.load(false);
+        self.mmu().set_rom(rom);
+        self.boot();
+    }
+
     pub fn load(&mut self, boot: bool) {
         match self.mode() {
             GameBoyMode::Dmg => self.load_dmg(boot),
@@ -562,7 +765,19 @@ impl GameBoy {
         self.frame_buffer().to_vec()
     }
 
-    pub fn audio_buffer_eager(&mut self, clear: bool) -> Vec<u8> {
+    pub fn frame_buffer_wide_eager(&mut self) -> Vec<u8> {
+        self.frame_buffer_wide().to_vec()
+    }
+
+    /// Obtains the current frame buffer upscaled with the provided
+    /// filter as an RGBA buffer, the dimensions of the frame are
+    /// multiplied by `filter.factor(scale)`.
+    pub fn frame_buffer_scaled_eager(&mut self, filter: ScaleFilter, scale: usize) -> Vec<u8> {
+        let (width, height) = (self.display_width(), self.display_height());
+        scale_frame_rgba(filter, self.frame_buffer().as_ref(), width, height, scale)
+    }
+
+    pub fn audio_buffer_eager(&mut self, clear: bool) -> Vec<i16> {
         let buffer = Vec::from(self.audio_buffer().clone());
         if clear {
             self.clear_audio_buffer();
@@ -574,6 +789,14 @@ impl GameBoy {
         self.apu_i().output()
     }
 
+    pub fn audio_output_left(&self) -> u8 {
+        self.apu_i().output_left()
+    }
+
+    pub fn audio_output_right(&self) -> u8 {
+        self.apu_i().output_right()
+    }
+
     pub fn audio_all_output(&self) -> Vec<u8> {
         vec![
             self.audio_output(),
@@ -680,6 +903,47 @@ impl GameBoy {
         tile.palette_buffer(self.ppu().palette_bg())
     }
 
+    /// Obtains the RGB pixel buffer of the complete 256x256
+    /// tile map at 0x9800 (`false`) or 0x9C00 (`true`), with
+    /// the CGB tile attributes applied.
+    pub fn get_tile_map_buffer(&mut self, map: bool) -> Vec<u8> {
+        self.ppu().render_tile_map(map)
+    }
+
+    /// Obtains the CGB attributes of the tile at the given
+    /// index of the 0x9800 (`false`) or 0x9C00 (`true`) tile map,
+    /// `None` is returned for an index outside the tile map.
+    pub fn get_tile_attrs(&mut self, map: bool, index: usize) -> Option<TileData> {
+        self.ppu().bg_map_attrs(map).get(index).copied()
+    }
+
+    /// Obtains the decoded OAM data of the object at the
+    /// given index (from 0 to 39), `None` is returned for
+    /// an index outside of that range.
+    pub fn get_object(&mut self, index: usize) -> Option<ObjectData> {
+        self.ppu().obj_data().get(index).copied()
+    }
+
+    /// Obtains the RGBA pixel buffer of the object at the given
+    /// index, rendered with its own palette and flips, the
+    /// buffer is 8x8 or 8x16 pixels according to the object size.
+    /// An empty buffer is returned for an invalid object index.
+    pub fn get_object_buffer(&mut self, index: usize) -> Vec<u8> {
+        self.ppu().render_object(index)
+    }
+
+    /// Obtains the RGB colors of the 8 CGB background palettes
+    /// as a flat buffer of 8 palettes with 4 colors each.
+    pub fn get_palettes_bg_buffer(&mut self) -> Vec<u8> {
+        self.ppu().palettes_color_bg().concat().concat()
+    }
+
+    /// Obtains the RGB colors of the 8 CGB object palettes
+    /// as a flat buffer of 8 palettes with 4 colors each.
+    pub fn get_palettes_obj_buffer(&mut self) -> Vec<u8> {
+        self.ppu().palettes_color_obj().concat().concat()
+    }
+
     /// Obtains the name of the compiler that has been
     /// used in the compilation of the base Boytacean
     /// library. Can be used for diagnostics.
@@ -725,9 +989,11 @@ impl GameBoy {
 
     pub fn set_mode(&mut self, value: GameBoyMode) {
         self.mode = value;
-        (*self.gbc).borrow_mut().set_mode(value);
+        self.gbc.lock().unwrap().set_mode(value);
         self.mmu().set_mode(value);
         self.ppu().set_gb_mode(value);
+        self.apu().set_gb_mode(value);
+        self.serial().set_gb_mode(value);
     }
 
     pub fn ppu_enabled(&self) -> bool {
@@ -736,7 +1002,7 @@ impl GameBoy {
 
     pub fn set_ppu_enabled(&mut self, value: bool) {
         self.ppu_enabled = value;
-        (*self.gbc).borrow_mut().set_ppu_enabled(value);
+        self.gbc.lock().unwrap().set_ppu_enabled(value);
     }
 
     pub fn apu_enabled(&self) -> bool {
@@ -745,7 +1011,7 @@ impl GameBoy {
 
     pub fn set_apu_enabled(&mut self, value: bool) {
         self.apu_enabled = value;
-        (*self.gbc).borrow_mut().set_apu_enabled(value);
+        self.gbc.lock().unwrap().set_apu_enabled(value);
     }
 
     pub fn dma_enabled(&self) -> bool {
@@ -754,7 +1020,7 @@ impl GameBoy {
 
     pub fn set_dma_enabled(&mut self, value: bool) {
         self.dma_enabled = value;
-        (*self.gbc).borrow_mut().set_dma_enabled(value);
+        self.gbc.lock().unwrap().set_dma_enabled(value);
     }
 
     pub fn timer_enabled(&self) -> bool {
@@ -763,7 +1029,7 @@ impl GameBoy {
 
     pub fn set_timer_enabled(&mut self, value: bool) {
         self.timer_enabled = value;
-        (*self.gbc).borrow_mut().set_timer_enabled(value);
+        self.gbc.lock().unwrap().set_timer_enabled(value);
     }
 
     pub fn serial_enabled(&self) -> bool {
@@ -772,7 +1038,7 @@ impl GameBoy {
 
     pub fn set_serial_enabled(&mut self, value: bool) {
         self.serial_enabled = value;
-        (*self.gbc).borrow_mut().set_serial_enabled(value);
+        self.gbc.lock().unwrap().set_serial_enabled(value);
     }
 
     pub fn set_all_enabled(&mut self, value: bool) {
@@ -789,7 +1055,7 @@ impl GameBoy {
 
     pub fn set_clock_freq(&mut self, value: u32) {
         self.clock_freq = value;
-        (*self.gbc).borrow_mut().set_clock_freq(value);
+        self.gbc.lock().unwrap().set_clock_freq(value);
         self.apu().set_clock_freq(value);
     }
 
@@ -941,7 +1207,11 @@ impl GameBoy {
         &(self.ppu().frame_buffer)
     }
 
-    pub fn audio_buffer(&mut self) -> &VecDeque<u8> {
+    pub fn frame_buffer_wide(&mut self) -> &[u8; WIDE_FRAME_BUFFER_SIZE] {
+        &(self.ppu().frame_buffer_wide)
+    }
+
+    pub fn audio_buffer(&mut self) -> &VecDeque<i16> {
         self.apu().audio_buffer()
     }
 
@@ -974,6 +1244,7 @@ impl GameBoy {
 
     pub fn load_rom(&mut self, data: &[u8]) -> &mut Cartridge {
         let rom = Cartridge::from_data(data);
+        self.gbs = None;
         self.mmu().set_rom(rom);
         self.mmu().rom()
     }
@@ -983,6 +1254,26 @@ impl GameBoy {
         self.load_rom(&data)
     }
 
+    /// Loads a GBS (Game Boy Sound System) file, mapping it into
+    /// a synthetic cartridge and starting the playback of its
+    /// first track, the boot ROM is skipped in the process.
+    pub fn load_gbs(&mut self, data: &[u8]) -> &mut Cartridge {
+        let gbs = GbsFile::from_data(data);
+        let track = gbs.first_song();
+        self.gbs = Some(gbs);
+        self.set_gbs_track(track);
+        self.mmu().rom()
+    }
+
+    pub fn load_gbs_file(&mut self, path: &str) -> &mut Cartridge {
+        let data = read_file(path);
+        self.load_gbs(&data)
+    }
+
+    pub fn gbs(&self) -> Option<&GbsFile> {
+        self.gbs.as_ref()
+    }
+
     pub fn attach_serial(&mut self, device: Box<dyn SerialDevice>) {
         self.serial().set_device(device);
     }
@@ -990,6 +1281,196 @@ impl GameBoy {
     pub fn set_speed_callback(&mut self, callback: fn(speed: GameBoySpeed)) {
         self.mmu().set_speed_callback(callback);
     }
+
+    /// Saves the state of the emulated machine (CPU, memory and the
+    /// remaining components) so that it can be restored later with
+    /// `load_state()`, with the same ROM loaded and mode.
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let mut data = write_header(self.mode as u8, self.mmu_i().rom_i().checksum());
+        let mut s = Serializer::saver();
+        self.cpu.serialize(&mut s);
+        data.extend_from_slice(&s.into_data());
+        data
+    }
+
+    /// Restores the state of the emulated machine from a state saved
+    /// with `save_state()`, rejecting states saved for a different ROM
+    /// or mode. In case of error the current state is kept untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let (mode, rom_checksum) = read_header(data)?;
+        if mode != self.mode as u8 {
+            return Err(format!("State mode mismatch (got {})", mode));
+        }
+        if rom_checksum != self.mmu_i().rom_i().checksum() {
+            return Err(format!(
+                "State ROM checksum mismatch (got 0x{:08x})",
+                rom_checksum
+            ));
+        }
+
+        // validates the state against a copy of the CPU before applying
+        // it, so that a corrupted state never leaves it half loaded
+        let mut s = Serializer::loader(&data[STATE_HEADER_SIZE..]);
+        self.cpu.clone().serialize(&mut s);
+        s.finish()?;
+
+        let mut s = Serializer::loader(&data[STATE_HEADER_SIZE..]);
+        self.cpu.serialize(&mut s);
+        s.finish()
+    }
+
+    /// Starts the recording of an input movie from the current state,
+    /// for a power-on start this should be called right after loading
+    /// the boot ROM and the cartridge, otherwise the current state is
+    /// saved into the movie so that it can be deterministically played
+    /// back from it.
+    pub fn start_movie_recording(&mut self, start: MovieStart) {
+        let mut movie = Movie::new(self.mode, start, self.mmu_i().rom_i().checksum());
+        if start == MovieStart::SaveState {
+            movie.set_state(self.save_state());
+        }
+        let ppu_frame = self.ppu_i().frame_index();
+        self.movie = Some(MovieSession::new(movie, MovieMode::Recording, ppu_frame));
+    }
+
+    /// Starts the playback of the provided input movie, for power-on
+    /// movies it should be called at power-on (as the recording) while
+    /// for the other ones the embedded state is loaded. The playback is
+    /// rejected in case the movie does not match the current ROM
+    /// or running mode.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), String> {
+        let rom_checksum = self.mmu_i().rom_i().checksum();
+        if movie.rom_checksum() != rom_checksum {
+            return Err(format!(
+                "Movie ROM checksum mismatch (expected 0x{:08x}, got 0x{:08x})",
+                movie.rom_checksum(),
+                rom_checksum
+            ));
+        }
+        if movie.mode() != self.mode {
+            return Err(format!(
+                "Movie mode mismatch (expected {}, got {})",
+                movie.mode(),
+                self.mode
+            ));
+        }
+        if movie.start() == MovieStart::SaveState {
+            self.load_state(movie.state())?;
+        }
+        let ppu_frame = self.ppu_i().frame_index();
+        self.movie = Some(MovieSession::new(movie, MovieMode::Playing, ppu_frame));
+        Ok(())
+    }
+
+    /// Stops the current movie session (recording or playback)
+    /// returning the associated movie.
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.movie.take().map(|session| session.finish())
+    }
+
+    pub fn movie(&self) -> Option<&MovieSession> {
+        self.movie.as_ref()
+    }
+
+    pub fn movie_recording(&self) -> bool {
+        matches!(&self.movie, Some(session) if session.mode() == MovieMode::Recording)
+    }
+
+    pub fn movie_playing(&self) -> bool {
+        matches!(&self.movie, Some(session) if session.mode() == MovieMode::Playing)
+    }
+
+    /// Applies the input events of the movie in playback whose
+    /// position in time has already been reached.
+    fn movie_play(&mut self) {
+        while let Some(event) = self.movie.as_mut().and_then(|movie| movie.next_event()) {
+            if event.pressed {
+                self.pad().key_press(event.key);
+            } else {
+                self.pad().key_lift(event.key);
+            }
+        }
+    }
+
+    fn movie_clock(&mut self, cycles: u16) {
+        let ppu_frame = self.ppu_i().frame_index();
+        if let Some(movie) = self.movie.as_mut() {
+            movie.clock(cycles, ppu_frame);
+        }
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLogger> {
+        self.mmu_i().cdl()
+    }
+
+    pub fn enable_cdl(&mut self) {
+        self.mmu().enable_cdl();
+    }
+
+    pub fn disable_cdl(&mut self) -> Option<CodeDataLogger> {
+        self.mmu().disable_cdl()
+    }
+
+    /// Saves the information currently gathered by the Code/Data
+    /// Logger into a CDL file at the provided path, returning an
+    /// error in case the CDL is not enabled.
+    pub fn save_cdl_file(&self, path: &str) -> Result<(), String> {
+        match self.cdl() {
+            Some(cdl) => cdl
+                .save_file(path)
+                .map_err(|error| format!("Failed to write {}: {}", path, error)),
+            None => Err(String::from("Code/Data Logger is not enabled")),
+        }
+    }
+
+    pub fn wav_recorder(&self) -> Option<&WavRecorder> {
+        self.apu_i().recorder()
+    }
+
+    pub fn enable_wav_recorder(&mut self, stems: bool) {
+        self.apu().enable_recorder(stems);
+    }
+
+    pub fn disable_wav_recorder(&mut self) -> Option<WavRecorder> {
+        self.apu().disable_recorder()
+    }
+
+    /// Saves the audio captured by the WAV recorder into a WAV file
+    /// at the provided path (plus one file per channel if stems are
+    /// being recorded), returning an error in case the recorder is
+    /// not enabled.
+    pub fn save_wav_file(&self, path: &str) -> Result<(), String> {
+        match self.wav_recorder() {
+            Some(recorder) => recorder
+                .save_file(path)
+                .map_err(|error| format!("Failed to write {}: {}", path, error)),
+            None => Err(String::from("WAV recorder is not enabled")),
+        }
+    }
+
+    pub fn vgm_logger(&self) -> Option<&VgmLogger> {
+        self.apu_i().vgm()
+    }
+
+    pub fn enable_vgm_logger(&mut self) {
+        self.apu().enable_vgm();
+    }
+
+    pub fn disable_vgm_logger(&mut self) -> Option<VgmLogger> {
+        self.apu().disable_vgm()
+    }
+
+    /// Saves the APU register writes captured by the VGM logger
+    /// into a VGM file at the provided path, returning an error
+    /// in case the VGM logger is not enabled.
+    pub fn save_vgm_file(&self, path: &str) -> Result<(), String> {
+        match self.vgm_logger() {
+            Some(vgm) => vgm
+                .save_file(path)
+                .map_err(|error| format!("Failed to write {}: {}", path, error)),
+            None => Err(String::from("VGM logger is not enabled")),
+        }
+    }
 }
 
 #[cfg(feature = "wasm")]
@@ -1003,6 +1484,10 @@ impl GameBoy {
         }));
     }
 
+    pub fn load_gbs_ws(&mut self, data: &[u8]) -> Cartridge {
+        self.load_gbs(data).clone()
+    }
+
     pub fn load_rom_ws(&mut self, data: &[u8]) -> Cartridge {
         let rom = self.load_rom(data);
         rom.set_rumble_cb(|active| {
@@ -1111,7 +1596,7 @@ impl AudioProvider for GameBoy {
         self.apu_i().output()
     }
 
-    fn audio_buffer(&self) -> &VecDeque<u8> {
+    fn audio_buffer(&self) -> &VecDeque<i16> {
         self.apu_i().audio_buffer()
     }
 
@@ -1126,6 +1611,35 @@ impl Default for GameBoy {
     }
 }
 
+/// Clones the complete emulation state into an independent
+/// system (eg: to snapshot a state and restore it later), the
+/// configuration is copied into a new shared structure and the
+/// attached serial device is replaced by a null device.
+impl Clone for GameBoy {
+    fn clone(&self) -> Self {
+        let gbc = Arc::new(Mutex::new(*self.gbc.lock().unwrap()));
+        let mut cpu = self.cpu.clone();
+        cpu.set_gbc(gbc.clone());
+        cpu.mmu().set_gbc(gbc.clone());
+        cpu.mmu().ppu().set_gbc(gbc.clone());
+
+        Self {
+            mode: self.mode,
+            ppu_enabled: self.ppu_enabled,
+            apu_enabled: self.apu_enabled,
+            dma_enabled: self.dma_enabled,
+            timer_enabled: self.timer_enabled,
+            serial_enabled: self.serial_enabled,
+            clock_freq: self.clock_freq,
+            cpu,
+            gbc,
+            movie: self.movie.clone(),
+            gbs: self.gbs.clone(),
+            gbs_track: self.gbs_track,
+        }
+    }
+}
+
 impl Display for GameBoy {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.description(9))
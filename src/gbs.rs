@@ -0,0 +1,372 @@
+use core::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::{rom::Cartridge, util::read_file};
+
+/// The size (in bytes) of the header of a GBS file, the
+/// music data starts right after it.
+pub const GBS_HEADER_SIZE: usize = 0x70;
+
+/// The address of the small driver (in the synthetic cartridge)
+/// that initializes the hardware and calls the init routine,
+/// placed right after the cartridge header.
+const GBS_DRIVER_ADDR: u16 = 0x0150;
+
+/// The TAC (timer control) bit that selects the timer interrupt
+/// (instead of V-Blank) as the source of the play calls.
+const GBS_TAC_TIMER: u8 = 0x04;
+
+/// Game Boy Sound System (GBS) file, a music rip of a Game Boy
+/// game that contains only the sound driver and data, which is
+/// played by calling its init and play routines.
+///
+/// The file is played by mapping its data into a synthetic MBC5
+/// cartridge with a small driver that sets up the hardware, calls
+/// the init routine for the selected track and then calls the
+/// play routine either from V-Blank or the timer interrupt.
+#[derive(Clone)]
+pub struct GbsFile {
+    /// The complete contents of the GBS file, including
+    /// the header and the music data.
+    data: Vec<u8>,
+}
+
+impl GbsFile {
+    pub fn from_data(data: &[u8]) -> Self {
+        if data.len() < GBS_HEADER_SIZE || &data[0x00..0x03] != b"GBS" {
+            panic!("Invalid GBS file, missing GBS header");
+        }
+        let gbs = Self {
+            data: data.to_vec(),
+        };
+        if gbs.load_addr() < 0x0400 {
+            panic!("Invalid GBS file, load address 0x{:04x}", gbs.load_addr());
+        }
+        gbs
+    }
+
+    pub fn from_file(path: &str) -> Self {
+        let data = read_file(path);
+        Self::from_data(&data)
+    }
+
+    /// Checks if the provided data starts with the GBS
+    /// signature, to be used for file type detection.
+    pub fn is_gbs(data: &[u8]) -> bool {
+        data.len() >= GBS_HEADER_SIZE && &data[0x00..0x03] == b"GBS"
+    }
+
+    pub fn version(&self) -> u8 {
+        self.data[0x03]
+    }
+
+    pub fn song_count(&self) -> u8 {
+        self.data[0x04]
+    }
+
+    /// The index (0 based) of the first song to be played, note
+    /// that the header stores it as a 1 based value.
+    pub fn first_song(&self) -> u8 {
+        self.data[0x05].saturating_sub(1)
+    }
+
+    pub fn load_addr(&self) -> u16 {
+        self.read_u16(0x06)
+    }
+
+    pub fn init_addr(&self) -> u16 {
+        self.read_u16(0x08)
+    }
+
+    pub fn play_addr(&self) -> u16 {
+        self.read_u16(0x0a)
+    }
+
+    pub fn stack_pointer(&self) -> u16 {
+        self.read_u16(0x0c)
+    }
+
+    pub fn timer_modulo(&self) -> u8 {
+        self.data[0x0e]
+    }
+
+    pub fn timer_control(&self) -> u8 {
+        self.data[0x0f]
+    }
+
+    /// If the play routine is driven by the timer interrupt
+    /// (as defined by TAC), otherwise V-Blank is used.
+    pub fn timer_driven(&self) -> bool {
+        self.timer_control() & GBS_TAC_TIMER == GBS_TAC_TIMER
+    }
+
+    pub fn title(&self) -> String {
+        self.read_string(0x10)
+    }
+
+    pub fn author(&self) -> String {
+        self.read_string(0x30)
+    }
+
+    pub fn copyright(&self) -> String {
+        self.read_string(0x50)
+    }
+
+    pub fn music_data(&self) -> &[u8] {
+        &self.data[GBS_HEADER_SIZE..]
+    }
+
+    /// Builds the contents of the synthetic MBC5 cartridge that
+    /// plays the provided track (0 based), with the music data
+    /// placed at the load address and the driver in the space
+    /// before it (interrupt vectors and header area).
+    pub fn cartridge_data(&self, track: u8) -> Vec<u8> {
+        let load_addr = self.load_addr() as usize;
+        let end = load_addr + self.music_data().len();
+        let mut size = 0x8000;
+        let mut size_code = 0x00;
+        while size < end {
+            size *= 2;
+            size_code += 1;
+        }
+
+        let mut rom = vec![0x00; size];
+        rom[load_addr..end].copy_from_slice(self.music_data());
+
+        // the RST vectors are redirected to the load address
+        // plus the vector offset, as defined by the GBS format
+        for vector in (0x00..0x40).step_by(8) {
+            Self::write_jp(&mut rom, vector, self.load_addr() + vector as u16);
+        }
+
+        // the interrupt vectors return right away, except for
+        // the one driving the play routine (V-Blank or timer)
+        for vector in (0x40..=0x60).step_by(8) {
+            rom[vector] = 0xd9;
+        }
+        let play_vector = if self.timer_driven() { 0x50 } else { 0x40 };
+        rom[play_vector] = 0xcd;
+        rom[play_vector + 1..play_vector + 3].copy_from_slice(&self.play_addr().to_le_bytes());
+        rom[play_vector + 3] = 0xd9;
+
+        // the entry point jumps over the header into the driver
+        rom[0x0100] = 0x00;
+        Self::write_jp(&mut rom, 0x0101, GBS_DRIVER_ADDR);
+
+        // header with the title, MBC5 + RAM type and the sizes
+        for (index, byte) in self.title().bytes().take(15).enumerate() {
+            rom[0x0134 + index] = byte;
+        }
+        rom[0x0147] = 0x1a;
+        rom[0x0148] = size_code;
+        rom[0x0149] = 0x02;
+
+        let driver = self.driver(track);
+        let driver_addr = GBS_DRIVER_ADDR as usize;
+        rom[driver_addr..driver_addr + driver.len()].copy_from_slice(&driver);
+
+        rom
+    }
+
+    /// Builds the synthetic cartridge that plays the provided
+    /// track (0 based) of the GBS file.
+    pub fn cartridge(&self, track: u8) -> Cartridge {
+        Cartridge::from_data(&self.cartridge_data(track))
+    }
+
+    pub fn description(&self, column_length: usize) -> String {
+        let title_l = format!("{:width$}", "Title", width = column_length);
+        let author_l = format!("{:width$}", "Author", width = column_length);
+        let copyright_l = format!("{:width$}", "Copyright", width = column_length);
+        let songs_l = format!("{:width$}", "Songs", width = column_length);
+        let driver_l = format!("{:width$}", "Driver", width = column_length);
+        format!(
+            "{}  {}\n{}  {}\n{}  {}\n{}  {}\n{}  {}",
+            title_l,
+            self.title(),
+            author_l,
+            self.author(),
+            copyright_l,
+            self.copyright(),
+            songs_l,
+            self.song_count(),
+            driver_l,
+            if self.timer_driven() {
+                "Timer"
+            } else {
+                "V-Blank"
+            }
+        )
+    }
+
+    /// Builds the machine code of the driver that initializes the
+    /// hardware (RAM enable, sound and timer registers, interrupts),
+    /// calls the init routine with the track number and then waits
+    /// for the interrupts that call the play routine.
+    ///
+    /// The sound registers (NR50, NR51 and NR52) are set by the driver
+    /// routing all of the channels to both terminals, as expected by
+    /// GBS players, instead of the post boot values (NR51 at 0xF3).
+    fn driver(&self, track: u8) -> Vec<u8> {
+        let [sp_low, sp_high] = self.stack_pointer().to_le_bytes();
+        let [init_low, init_high] = self.init_addr().to_le_bytes();
+        let interrupts = if self.timer_driven() { 0x04 } else { 0x01 };
+        let mut code = vec![];
+
+        // disables interrupts and sets the stack pointer (LD SP, nn)
+        code.extend_from_slice(&[0xf3, 0x31, sp_low, sp_high]);
+
+        // enables the cartridge RAM and selects ROM bank 1
+        code.extend_from_slice(&[0x3e, 0x0a, 0xea, 0x00, 0x00]);
+        code.extend_from_slice(&[0x3e, 0x01, 0xea, 0x00, 0x20]);
+
+        // turns the sound on (NR52), routes all of the channels to
+        // both terminals (NR51) and sets the maximum volume (NR50)
+        code.extend_from_slice(&[0x3e, 0x80, 0xe0, 0x26]);
+        code.extend_from_slice(&[0x3e, 0xff, 0xe0, 0x25]);
+        code.extend_from_slice(&[0x3e, 0x77, 0xe0, 0x24]);
+
+        // sets the timer registers (TMA and TAC) from the header, with
+        // TIMA starting at the modulo so that the first period is exact
+        code.extend_from_slice(&[0x3e, self.timer_modulo(), 0xe0, 0x06, 0xe0, 0x05]);
+        code.extend_from_slice(&[0x3e, self.timer_control() & 0x07, 0xe0, 0x07]);
+
+        // enables the play interrupt (IE) and clears the pending
+        // ones (IF), then calls init with the track number in A
+        code.extend_from_slice(&[0x3e, interrupts, 0xe0, 0xff]);
+        code.extend_from_slice(&[0xaf, 0xe0, 0x0f]);
+        code.extend_from_slice(&[0x3e, track, 0xcd, init_low, init_high]);
+
+        // enables interrupts and waits for them in a HALT loop
+        let [loop_low, loop_high] = (GBS_DRIVER_ADDR + code.len() as u16 + 1).to_le_bytes();
+        code.extend_from_slice(&[0xfb, 0x76, 0xc3, loop_low, loop_high]);
+
+        code
+    }
+
+    fn write_jp(rom: &mut [u8], addr: usize, target: u16) {
+        rom[addr] = 0xc3;
+        rom[addr + 1..addr + 3].copy_from_slice(&target.to_le_bytes());
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+
+    fn read_string(&self, offset: usize) -> String {
+        let bytes = &self.data[offset..offset + 32];
+        let length = bytes.iter().position(|byte| *byte == 0x00).unwrap_or(32);
+        String::from_utf8_lossy(&bytes[..length]).to_string()
+    }
+}
+
+impl Display for GbsFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description(9))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gb::GameBoy,
+        test::{build_test, TestOptions},
+    };
+
+    use super::GbsFile;
+
+    fn run_frames(game_boy: &mut GameBoy, count: u32) {
+        let mut cycles = 0u32;
+        while cycles < GameBoy::LCD_CYCLES * count {
+            cycles += game_boy.clock() as u32;
+        }
+    }
+
+    /// Builds a GBS file whose init routine stores the track number
+    /// at 0xC000 and whose play routine increments 0xC001.
+    fn build_gbs(timer_control: u8) -> Vec<u8> {
+        let mut data = vec![0x00; 0x70];
+        data[0x00..0x03].copy_from_slice(b"GBS");
+        data[0x03] = 0x01;
+        data[0x04] = 3;
+        data[0x05] = 2;
+        data[0x06..0x08].copy_from_slice(&0x0400u16.to_le_bytes());
+        data[0x08..0x0a].copy_from_slice(&0x0400u16.to_le_bytes());
+        data[0x0a..0x0c].copy_from_slice(&0x0404u16.to_le_bytes());
+        data[0x0c..0x0e].copy_from_slice(&0xfffeu16.to_le_bytes());
+        data[0x0e] = 0xc0;
+        data[0x0f] = timer_control;
+        data[0x10..0x14].copy_from_slice(b"Test");
+        data[0x30..0x36].copy_from_slice(b"Author");
+        data.extend_from_slice(&[0xea, 0x00, 0xc0, 0xc9]);
+        data.extend_from_slice(&[0x21, 0x01, 0xc0, 0x34, 0xc9]);
+        data
+    }
+
+    #[test]
+    fn test_gbs_header() {
+        let gbs = GbsFile::from_data(&build_gbs(0x04));
+        assert_eq!(gbs.song_count(), 3);
+        assert_eq!(gbs.first_song(), 1);
+        assert_eq!(gbs.load_addr(), 0x0400);
+        assert_eq!(gbs.play_addr(), 0x0404);
+        assert_eq!(gbs.title(), "Test");
+        assert_eq!(gbs.author(), "Author");
+        assert!(gbs.timer_driven());
+
+        let rom = gbs.cartridge(2);
+        assert_eq!(rom.data().len(), 0x8000);
+        assert_eq!(rom.title(), "Test");
+        assert_eq!(rom.rom_type().description(), "MBC5 + RAM");
+        assert_eq!(&rom.data()[0x0400..0x0404], &[0xea, 0x00, 0xc0, 0xc9]);
+        assert_eq!(&rom.data()[0x0038..0x003b], &[0xc3, 0x38, 0x04]);
+        assert_eq!(&rom.data()[0x0050..0x0054], &[0xcd, 0x04, 0x04, 0xd9]);
+        assert!(GbsFile::is_gbs(&build_gbs(0x00)));
+        assert!(!GbsFile::is_gbs(&[0x00; 0x200]));
+    }
+
+    #[test]
+    fn test_gbs_play() {
+        let mut game_boy = build_test(TestOptions::default());
+        game_boy.load_gbs(&build_gbs(0x00));
+        assert_eq!(game_boy.gbs_track(), 1);
+        assert_eq!(game_boy.gbs_track_count(), 3);
+
+        // the play routine is called once per frame (V-Blank) and
+        // the sound is turned on by the driver
+        run_frames(&mut game_boy, 11);
+        assert_eq!(game_boy.mmu().read(0xc000), 1);
+        assert!(game_boy.mmu().read(0xc001) >= 9);
+        assert_eq!(game_boy.mmu().read(0xff26) & 0x80, 0x80);
+        assert_eq!(game_boy.mmu().read(0xff25), 0xff);
+
+        game_boy.set_gbs_track(2);
+        run_frames(&mut game_boy, 2);
+        assert_eq!(game_boy.gbs_track(), 2);
+        assert_eq!(game_boy.mmu().read(0xc000), 2);
+
+        // the timer interrupt (TAC 4096Hz with TMA 0xC0) calls the
+        // play routine 64 times per second
+        game_boy.load_gbs(&build_gbs(0x04));
+        run_frames(&mut game_boy, 60);
+        let count = game_boy.mmu().read(0xc001);
+        assert!((63..=65).contains(&count));
+    }
+
+    #[test]
+    fn test_gbs_track_invalid() {
+        // selecting a track with no GBS file loaded is ignored
+        let mut game_boy = build_test(TestOptions::default());
+        game_boy.set_gbs_track(2);
+        assert_eq!(game_boy.gbs_track(), 0);
+        assert!(!game_boy.gbs_loaded());
+
+        // a file with no songs keeps playing the first track
+        let mut data = build_gbs(0x00);
+        data[0x04] = 0;
+        game_boy.load_gbs(&data);
+        game_boy.set_gbs_track(2);
+        assert_eq!(game_boy.gbs_track(), 0);
+        assert_eq!(game_boy.gbs_track_count(), 0);
+    }
+}
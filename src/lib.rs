@@ -1,19 +1,29 @@
 #![allow(clippy::uninlined_format_args)]
 
 pub mod apu;
+pub mod blip;
+pub mod cdl;
 pub mod cpu;
 pub mod data;
 pub mod devices;
 pub mod dma;
+pub mod env;
+pub mod filter;
 pub mod gb;
+pub mod gbs;
 pub mod gen;
 pub mod inst;
 pub mod macros;
 pub mod mmu;
+pub mod movie;
 pub mod pad;
+pub mod pool;
 pub mod ppu;
 pub mod rom;
 pub mod serial;
+pub mod state;
 pub mod test;
 pub mod timer;
 pub mod util;
+pub mod vgm;
+pub mod wav;
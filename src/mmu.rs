@@ -1,15 +1,18 @@
-use std::{cell::RefCell, rc::Rc};
+use std::sync::{Arc, Mutex};
 
 use crate::{
     apu::Apu,
+    cdl::{CodeDataLogger, CDL_DATA, CDL_DMA},
     debugln,
-    dma::Dma,
+    dma::{Dma, DmaMode, OAM_DMA_CYCLES},
     gb::{Components, GameBoyConfig, GameBoyMode, GameBoySpeed},
     pad::Pad,
-    ppu::Ppu,
+    ppu::{Ppu, PpuMode},
     rom::Cartridge,
     serial::Serial,
+    state::{Serializer, StateComponent},
     timer::Timer,
+    util::SharedThread,
 };
 
 pub const BOOT_SIZE_DMG: usize = 256;
@@ -18,6 +21,7 @@ pub const BOOT_SIZE_CGB: usize = 2304;
 pub const RAM_SIZE_DMG: usize = 8192;
 pub const RAM_SIZE_CGB: usize = 32768;
 
+#[derive(Clone)]
 pub struct Mmu {
     /// Register that controls the interrupts that are considered
     /// to be enabled and should be triggered.
@@ -109,11 +113,20 @@ pub struct Mmu {
     /// The pointer to the parent configuration of the running
     /// Game Boy emulator, that can be used to control the behaviour
     /// of Game Boy emulation.
-    gbc: Rc<RefCell<GameBoyConfig>>,
+    gbc: SharedThread<GameBoyConfig>,
+
+    /// The optional Code/Data Logger that when set keeps track
+    /// of the kind of access (code, data or DMA) performed over
+    /// each of the bytes of the cartridge ROM.
+    cdl: Option<CodeDataLogger>,
 }
 
 impl Mmu {
-    pub fn new(components: Components, mode: GameBoyMode, gbc: Rc<RefCell<GameBoyConfig>>) -> Self {
+    pub fn new(
+        components: Components,
+        mode: GameBoyMode,
+        gbc: SharedThread<GameBoyConfig>,
+    ) -> Self {
         Self {
             ppu: components.ppu,
             apu: components.apu,
@@ -134,6 +147,7 @@ impl Mmu {
             speed_callback: |_| {},
             mode,
             gbc,
+            cdl: None,
         }
     }
 
@@ -169,6 +183,7 @@ impl Mmu {
     pub fn switch_speed(&mut self) {
         self.speed = self.speed.switch();
         self.switching = false;
+        self.serial.set_speed(self.speed);
         (self.speed_callback)(self.speed);
     }
 
@@ -236,18 +251,84 @@ impl Mmu {
         self.boot_active = value;
     }
 
-    pub fn clock_dma(&mut self, _cycles: u16) {
+    pub fn clock_dma(&mut self, cycles: u16) {
+        if self.dma.active_dma() {
+            self.clock_oam_dma(cycles);
+        }
+
         if !self.dma.active() {
             return;
         }
 
-        // @TODO: Implement DMA transfer in a better way
-        let data = self.read_many(self.dma.source(), self.dma.length());
+        match self.dma.mode() {
+            DmaMode::General => self.transfer_hdma(self.dma.length()),
+            DmaMode::HBlank => {
+                // a block of 16 bytes is transferred at the beginning
+                // of each H-Blank period, the PPU has already been
+                // clocked so the H-Blank start is detected here
+                if self.ppu.mode() == PpuMode::HBlank {
+                    if !self.dma.hblank_done() {
+                        self.transfer_hdma(16);
+                        self.dma.set_hblank_done(true);
+                    }
+                } else {
+                    self.dma.set_hblank_done(false);
+                }
+            }
+        }
+    }
+
+    /// Transfers the provided number of bytes of the current HDMA
+    /// transfer into VRAM, stalling the CPU for 8 M-cycles per each
+    /// block of 16 bytes (in double speed the cycles are doubled).
+    fn transfer_hdma(&mut self, length: u16) {
+        let length = length.min(self.dma.length());
+        let data = self.read_many_dma(self.dma.source(), length);
         self.write_many(self.dma.destination(), &data);
-        self.dma.set_active(false);
+
+        self.dma.set_source(self.dma.source().wrapping_add(length));
+        self.dma
+            .set_destination(self.dma.destination().wrapping_add(length));
+        self.dma.set_length(self.dma.length() - length);
+        if self.dma.length() == 0 {
+            self.dma.set_active(false);
+        }
+
+        let stall = (length >> 4) * 32 * self.speed.multiplier() as u16;
+        self.dma
+            .set_stall_cycles(self.dma.stall_cycles().saturating_add(stall));
+    }
+
+    /// Runs the OAM DMA transfer for the provided number of CPU
+    /// cycles, copying one byte into OAM for each M-cycle.
+    fn clock_oam_dma(&mut self, cycles: u16) {
+        let cycles_dma = self.dma.cycles_dma().saturating_sub(cycles);
+        let start = (OAM_DMA_CYCLES - self.dma.cycles_dma()) / 4;
+        let end = (OAM_DMA_CYCLES - cycles_dma) / 4;
+        let source = self.dma.source_dma();
+        for index in start..end {
+            let byte = self.read_dma(source + index);
+            self.ppu.write(0xfe00 + index, byte);
+        }
+        self.dma.set_cycles_dma(cycles_dma);
+        if cycles_dma == 0 {
+            self.dma.set_active_dma(false);
+        }
     }
 
     pub fn read(&mut self, addr: u16) -> u8 {
+        // while an OAM DMA transfer is running the DMG CPU is only
+        // able to access HRAM and I/O, reading the byte being
+        // transferred when accessing the bus used by the DMA
+        if self.dma.active_dma() && addr < 0xff00 && self.mode == GameBoyMode::Dmg {
+            return self.read_conflict(addr);
+        }
+        self.read_raw(addr)
+    }
+
+    /// Reads a byte from memory ignoring any of the bus
+    /// restrictions imposed by a running OAM DMA transfer.
+    pub fn read_raw(&mut self, addr: u16) -> u8 {
         match addr & 0xf000 {
             // BOOT (256 B) + ROM0 (4 KB/16 KB)
             0x0000 => {
@@ -268,14 +349,14 @@ impl Mmu {
                 {
                     return self.boot[addr as usize];
                 }
-                self.rom.read(addr)
+                self.read_rom(addr, CDL_DATA)
             }
 
             // ROM 0 (12 KB/16 KB)
-            0x1000 | 0x2000 | 0x3000 => self.rom.read(addr),
+            0x1000 | 0x2000 | 0x3000 => self.read_rom(addr, CDL_DATA),
 
             // ROM 1 (Banked) (16 KB)
-            0x4000 | 0x5000 | 0x6000 | 0x7000 => self.rom.read(addr),
+            0x4000 | 0x5000 | 0x6000 | 0x7000 => self.read_rom(addr, CDL_DATA),
 
             // Graphics: VRAM (8 KB)
             0x8000 | 0x9000 => self.ppu.read(addr),
@@ -296,7 +377,14 @@ impl Mmu {
             0xf000 => match addr & 0x0f00 {
                 0x000 | 0x100 | 0x200 | 0x300 | 0x400 | 0x500 | 0x600 | 0x700 | 0x800 | 0x900
                 | 0xa00 | 0xb00 | 0xc00 | 0xd00 => self.ram[(addr & 0x1fff) as usize],
-                0xe00 => self.ppu.read(addr),
+                0xe00 => {
+                    // the OAM is not accessible by the CPU while
+                    // an OAM DMA transfer is running
+                    if self.dma.active_dma() && addr <= 0xfe9f {
+                        return 0xff;
+                    }
+                    self.ppu.read(addr)
+                }
                 0xf00 => match addr & 0x00ff {
                     // 0xFF01-0xFF02 - Serial data transfer
                     0x01..=0x02 => self.serial.read(addr),
@@ -312,6 +400,9 @@ impl Mmu {
                             | if self.pad.int_pad() { 0x10 } else { 0x00 })
                     }
 
+                    // 0xFF46 — DMA: OAM DMA source address & start
+                    0x46 => self.dma.read(addr),
+
                     // 0xFF4C - KEY0: Compatibility flag (CGB only)
                     0x4c => self.key0,
 
@@ -324,6 +415,10 @@ impl Mmu {
                     // 0xFF70 - SVBK: WRAM bank (CGB only)
                     0x70 => self.ram_bank & 0x07,
 
+                    // 0xFF76 - PCM12: Channel 1 & 2 output (CGB only)
+                    // 0xFF77 - PCM34: Channel 3 & 4 output (CGB only)
+                    0x76 | 0x77 => self.apu.read(addr),
+
                     // 0xFF80-0xFFFE - High RAM (HRAM)
                     0x80..=0xfe => self.ppu.read(addr),
 
@@ -363,6 +458,12 @@ impl Mmu {
     }
 
     pub fn write(&mut self, addr: u16, value: u8) {
+        // while an OAM DMA transfer is running the DMG CPU is only
+        // able to access HRAM and I/O, other writes are lost
+        if self.dma.active_dma() && addr < 0xff00 && self.mode == GameBoyMode::Dmg {
+            return;
+        }
+
         match addr & 0xf000 {
             // BOOT (256 B) + ROM0 (4 KB/16 KB)
             0x0000 => self.rom.write(addr, value),
@@ -394,7 +495,14 @@ impl Mmu {
                 | 0xa00 | 0xb00 | 0xc00 | 0xd00 => {
                     self.ram[(addr & 0x1fff) as usize] = value;
                 }
-                0xe00 => self.ppu.write(addr, value),
+                0xe00 => {
+                    // the OAM is not accessible by the CPU while
+                    // an OAM DMA transfer is running
+                    if self.dma.active_dma() && addr <= 0xfe9f {
+                        return;
+                    }
+                    self.ppu.write(addr, value)
+                }
                 0xf00 => match addr & 0x00ff {
                     // 0xFF01-0xFF02 - Serial data transfer
                     0x01..=0x02 => self.serial.write(addr, value),
@@ -453,11 +561,18 @@ impl Mmu {
                                 match addr & 0x00ff {
                                     // 0xFF46 — DMA: OAM DMA source address & start
                                     0x0046 => {
-                                        // @TODO must increment the cycle count by 160
-                                        // and make this a separated dma.rs file
                                         debugln!("Going to start DMA transfer to 0x{:x}00", value);
-                                        let data = self.read_many((value as u16) << 8, 160);
-                                        self.write_many(0xfe00, &data);
+                                        self.dma.write(addr, value);
+
+                                        // in case the DMA is not being clocked the transfer
+                                        // is performed immediately (instant copy)
+                                        if !self.gbc.lock().unwrap().dma_enabled() {
+                                            self.dma.set_cycles_dma(0);
+                                            self.dma.set_active_dma(false);
+                                            let data =
+                                                self.read_many_dma(self.dma.source_dma(), 160);
+                                            self.write_many(0xfe00, &data);
+                                        }
                                     }
 
                                     // VRAM related write
@@ -497,6 +612,96 @@ impl Mmu {
         data
     }
 
+    /// Reads a byte from memory flagging the access (in case the
+    /// address is a cartridge ROM one) with the provided CDL flag
+    /// instead of the default data flag, this is used by the CPU
+    /// to log instruction fetching and by the DMA controllers.
+    pub fn read_cdl(&mut self, addr: u16, flag: u8) -> u8 {
+        if self.cdl.is_none() || addr >= 0x8000 || self.boot_mapped(addr) {
+            return self.read(addr);
+        }
+        // under an OAM DMA transfer (DMG) the ROM is not accessed, the
+        // byte being transferred is read instead and nothing is flagged
+        if self.dma.active_dma() && self.mode == GameBoyMode::Dmg {
+            return self.read(addr);
+        }
+        self.read_rom(addr, flag)
+    }
+
+    /// Reads multiple bytes from memory as the source of a DMA
+    /// transfer, properly flagging ROM accesses in the CDL.
+    pub fn read_many_dma(&mut self, addr: u16, count: u16) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![];
+
+        for index in 0..count {
+            let byte = self.read_dma(addr + index);
+            data.push(byte);
+        }
+
+        data
+    }
+
+    /// Reads a byte from memory as the source of a DMA transfer,
+    /// the read is not subject to the DMA bus restrictions.
+    fn read_dma(&mut self, addr: u16) -> u8 {
+        if self.cdl.is_none() || addr >= 0x8000 || self.boot_mapped(addr) {
+            return self.read_raw(addr);
+        }
+        self.read_rom(addr, CDL_DMA)
+    }
+
+    /// Obtains the value read by the CPU from an address outside
+    /// HRAM and I/O while an OAM DMA transfer is running (DMG),
+    /// in case the address is in the same bus as the source of
+    /// the transfer the byte being transferred is read.
+    fn read_conflict(&mut self, addr: u16) -> u8 {
+        let source = self.dma.source_dma();
+        let is_vram = |addr: u16| (0x8000..=0x9fff).contains(&addr);
+        if is_vram(addr) != is_vram(source) {
+            return 0xff;
+        }
+        let index = (OAM_DMA_CYCLES - self.dma.cycles_dma()) / 4;
+        self.read_raw(source + index.min(159))
+    }
+
+    #[inline(always)]
+    fn read_rom(&mut self, addr: u16, flag: u8) -> u8 {
+        if let Some(cdl) = self.cdl.as_mut() {
+            cdl.log(self.rom.rom_addr(addr), flag);
+        }
+        self.rom.read(addr)
+    }
+
+    #[inline(always)]
+    fn boot_mapped(&self, addr: u16) -> bool {
+        self.boot_active
+            && (addr <= 0x00fe
+                || self.mode == GameBoyMode::Cgb && (0x0200..=0x08ff).contains(&addr))
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLogger> {
+        self.cdl.as_ref()
+    }
+
+    pub fn cdl_enabled(&self) -> bool {
+        self.cdl.is_some()
+    }
+
+    /// Starts the logging of ROM accesses, allocating a Code/Data
+    /// Logger for the currently loaded cartridge, in case there's
+    /// one already running it's kept (logged data is not lost).
+    pub fn enable_cdl(&mut self) {
+        if self.cdl.is_none() {
+            self.cdl = Some(CodeDataLogger::new(self.rom.data().len()));
+        }
+    }
+
+    /// Stops the logging of ROM accesses returning the Code/Data
+    /// Logger with the information gathered so far.
+    pub fn disable_cdl(&mut self) -> Option<CodeDataLogger> {
+        self.cdl.take()
+    }
+
     pub fn write_boot(&mut self, addr: u16, buffer: &[u8]) {
         self.boot[addr as usize..addr as usize + buffer.len()].clone_from_slice(buffer);
     }
@@ -509,8 +714,15 @@ impl Mmu {
         &mut self.rom
     }
 
+    pub fn rom_i(&self) -> &Cartridge {
+        &self.rom
+    }
+
     pub fn set_rom(&mut self, rom: Cartridge) {
         self.rom = rom;
+        if let Some(cdl) = self.cdl.as_mut() {
+            cdl.allocate(self.rom.data().len());
+        }
     }
 
     pub fn mode(&self) -> GameBoyMode {
@@ -521,15 +733,37 @@ impl Mmu {
         self.mode = value;
     }
 
-    pub fn set_gbc(&mut self, value: Rc<RefCell<GameBoyConfig>>) {
+    pub fn set_gbc(&mut self, value: SharedThread<GameBoyConfig>) {
         self.gbc = value;
     }
 }
 
+impl StateComponent for Mmu {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.u8(&mut self.ie);
+        s.u8(&mut self.key0);
+        s.bool(&mut self.switching);
+        let mut speed = self.speed as u8;
+        s.u8(&mut speed);
+        self.speed = GameBoySpeed::from_u8(speed & 0x01);
+        s.bool(&mut self.boot_active);
+        s.vec(&mut self.ram);
+        s.u8(&mut self.ram_bank);
+        s.u16(&mut self.ram_offset);
+        self.ppu.serialize(s);
+        self.apu.serialize(s);
+        self.dma.serialize(s);
+        self.pad.serialize(s);
+        self.timer.serialize(s);
+        self.serial.serialize(s);
+        self.rom.serialize(s);
+    }
+}
+
 impl Default for Mmu {
     fn default() -> Self {
         let mode = GameBoyMode::Dmg;
-        let gbc = Rc::new(RefCell::new(GameBoyConfig::default()));
+        let gbc = Arc::new(Mutex::new(GameBoyConfig::default()));
         let components = Components {
             ppu: Ppu::new(mode, gbc.clone()),
             apu: Apu::default(),
@@ -541,3 +775,66 @@ impl Default for Mmu {
         Mmu::new(components, mode, gbc)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::mmu::Mmu;
+
+    #[test]
+    fn test_oam_dma_timing() {
+        let mut mmu = Mmu::default();
+        mmu.allocate_dmg();
+        for index in 0..160 {
+            mmu.write(0xc000 + index, index as u8 + 1);
+        }
+
+        mmu.write(0xff46, 0xc0);
+        mmu.clock_dma(320);
+        assert_eq!(mmu.ppu().read(0xfe00 + 79), 80);
+        assert_eq!(mmu.ppu().read(0xfe00 + 80), 0);
+
+        // the CPU is limited to HRAM while the transfer is running
+        mmu.write(0xff80, 0x12);
+        assert_eq!(mmu.read(0xff80), 0x12);
+        mmu.write(0xc000, 0x34);
+        assert_eq!(mmu.read_raw(0xc000), 0x01);
+
+        mmu.clock_dma(320);
+        assert!(!mmu.dma_i().active_dma());
+        assert_eq!(mmu.read(0xfe00), 1);
+        assert_eq!(mmu.read(0xfe00 + 159), 160);
+    }
+
+    #[test]
+    fn test_hdma_hblank() {
+        let mut mmu = Mmu::default();
+        mmu.allocate_cgb();
+        for index in 0..32 {
+            mmu.write(0xc000 + index, index as u8 + 1);
+        }
+        mmu.write(0xff40, 0x80);
+        mmu.write(0xff51, 0xc0);
+        mmu.write(0xff52, 0x00);
+        mmu.write(0xff53, 0x80);
+        mmu.write(0xff54, 0x00);
+        mmu.write(0xff55, 0x81);
+
+        // no block is transferred outside of H-Blank
+        mmu.clock_dma(4);
+        assert_eq!(mmu.read(0x8000), 0x00);
+        assert_eq!(mmu.read(0xff55), 0x01);
+
+        mmu.ppu().clock(76);
+        mmu.ppu().clock(172);
+        mmu.clock_dma(4);
+        assert_eq!(mmu.read(0x8000), 0x01);
+        assert_eq!(mmu.read(0x800f), 0x10);
+        assert_eq!(mmu.read(0x8010), 0x00);
+        assert_eq!(mmu.read(0xff55), 0x00);
+        assert_eq!(mmu.dma_i().stall_cycles(), 32);
+
+        // writing bit 7 as zero cancels the transfer
+        mmu.write(0xff55, 0x00);
+        assert_eq!(mmu.read(0xff55), 0x80);
+    }
+}
@@ -0,0 +1,529 @@
+use std::{
+    convert::TryInto,
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+use crate::{
+    gb::GameBoyMode,
+    pad::PadKey,
+    util::{read_file, write_file},
+};
+
+/// The magic string that identifies a movie file.
+pub const MOVIE_MAGIC: &[u8; 4] = b"BTMV";
+
+/// The current version of the movie file format.
+pub const MOVIE_VERSION: u8 = 1;
+
+/// The size in bytes of the fixed movie file header.
+const HEADER_SIZE: usize = 24;
+
+/// The size in bytes of each serialized input event.
+const EVENT_SIZE: usize = 10;
+
+/// Enumeration that describes the state from which the
+/// recording of a movie has started.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MovieStart {
+    /// The movie starts with the system at power-on, right
+    /// after the boot ROM and the cartridge have been loaded.
+    PowerOn = 0x00,
+
+    /// The movie starts from the save state embedded in it.
+    SaveState = 0x01,
+}
+
+impl MovieStart {
+    pub fn description(&self) -> &'static str {
+        match self {
+            MovieStart::PowerOn => "Power-on",
+            MovieStart::SaveState => "Save state",
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x00 => Some(MovieStart::PowerOn),
+            0x01 => Some(MovieStart::SaveState),
+            _ => None,
+        }
+    }
+}
+
+impl Display for MovieStart {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+/// Single input event (key press or lift) of a movie, placed
+/// in time by the frame index and the number of CPU cycles
+/// elapsed since the start of that frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MovieEvent {
+    pub frame: u32,
+    pub cycles: u32,
+    pub key: PadKey,
+    pub pressed: bool,
+}
+
+/// Input movie that stores the complete sequence of input
+/// events of an emulation session, allowing its deterministic
+/// playback (eg: to reproduce bugs or speedruns).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Movie {
+    /// The Game Boy mode (DMG, CGB, etc.) the movie
+    /// has been recorded under.
+    mode: GameBoyMode,
+
+    /// The state from which the movie starts.
+    start: MovieStart,
+
+    /// The CRC-32 checksum of the ROM used in the recording,
+    /// playback is rejected for a different ROM.
+    rom_checksum: u32,
+
+    /// The total number of frames of the movie.
+    frames: u32,
+
+    /// The embedded save state for movies that do not start
+    /// at power-on, empty otherwise.
+    state: Vec<u8>,
+
+    /// The sequence of input events sorted by time.
+    events: Vec<MovieEvent>,
+}
+
+impl Movie {
+    pub fn new(mode: GameBoyMode, start: MovieStart, rom_checksum: u32) -> Self {
+        Self {
+            mode,
+            start,
+            rom_checksum,
+            frames: 0,
+            state: vec![],
+            events: vec![],
+        }
+    }
+
+    pub fn from_data(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER_SIZE || &data[0..4] != MOVIE_MAGIC {
+            return Err(String::from("Invalid movie file"));
+        }
+        if data[4] != MOVIE_VERSION {
+            return Err(format!("Unsupported movie version: {}", data[4]));
+        }
+        let mode = match data[5] {
+            1..=3 => GameBoyMode::from_u8(data[5]),
+            _ => return Err(format!("Invalid movie mode: {}", data[5])),
+        };
+        let start = match MovieStart::from_u8(data[6]) {
+            Some(start) => start,
+            None => return Err(format!("Invalid movie start: {}", data[6])),
+        };
+        let rom_checksum = read_u32(data, 8);
+        let frames = read_u32(data, 12);
+        let state_size = read_u32(data, 16) as usize;
+        let event_count = read_u32(data, 20) as usize;
+
+        let events_offset = HEADER_SIZE.saturating_add(state_size);
+        if data.len() < events_offset || (data.len() - events_offset) / EVENT_SIZE < event_count {
+            return Err(String::from("Truncated movie file"));
+        }
+        if start == MovieStart::SaveState && state_size == 0 {
+            return Err(String::from("Missing movie save state"));
+        }
+
+        let state = data[HEADER_SIZE..events_offset].to_vec();
+        let mut events = Vec::with_capacity(event_count);
+        for index in 0..event_count {
+            let offset = events_offset + index * EVENT_SIZE;
+            let key = match PadKey::from_u8(data[offset + 8]) {
+                Some(key) => key,
+                None => return Err(format!("Invalid movie key: {}", data[offset + 8])),
+            };
+            events.push(MovieEvent {
+                frame: read_u32(data, offset),
+                cycles: read_u32(data, offset + 4),
+                key,
+                pressed: data[offset + 9] != 0x00,
+            });
+        }
+
+        Ok(Self {
+            mode,
+            start,
+            rom_checksum,
+            frames,
+            state,
+            events,
+        })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let data = read_file(path);
+        Self::from_data(&data)
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut data =
+            Vec::with_capacity(HEADER_SIZE + self.state.len() + self.events.len() * EVENT_SIZE);
+        data.extend_from_slice(MOVIE_MAGIC);
+        data.push(MOVIE_VERSION);
+        data.push(self.mode as u8);
+        data.push(self.start as u8);
+        data.push(0x00);
+        data.extend_from_slice(&self.rom_checksum.to_le_bytes());
+        data.extend_from_slice(&self.frames.to_le_bytes());
+        data.extend_from_slice(&(self.state.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(self.events.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.state);
+        for event in &self.events {
+            data.extend_from_slice(&event.frame.to_le_bytes());
+            data.extend_from_slice(&event.cycles.to_le_bytes());
+            data.push(event.key as u8);
+            data.push(event.pressed as u8);
+        }
+        data
+    }
+
+    pub fn save_file(&self, path: &str) -> io::Result<()> {
+        write_file(path, &self.to_data())
+    }
+
+    /// Adds a new input event to the movie, events are expected
+    /// to be pushed in chronological order.
+    pub fn push(&mut self, event: MovieEvent) {
+        self.events.push(event);
+    }
+
+    pub fn mode(&self) -> GameBoyMode {
+        self.mode
+    }
+
+    pub fn start(&self) -> MovieStart {
+        self.start
+    }
+
+    pub fn rom_checksum(&self) -> u32 {
+        self.rom_checksum
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn set_frames(&mut self, value: u32) {
+        self.frames = value;
+    }
+
+    pub fn state(&self) -> &[u8] {
+        &self.state
+    }
+
+    pub fn set_state(&mut self, value: Vec<u8>) {
+        self.state = value;
+    }
+
+    pub fn events(&self) -> &Vec<MovieEvent> {
+        &self.events
+    }
+
+    pub fn description(&self, column_length: usize) -> String {
+        let mode_l = format!("{:width$}", "Mode", width = column_length);
+        let start_l = format!("{:width$}", "Start", width = column_length);
+        let checksum_l = format!("{:width$}", "Checksum", width = column_length);
+        let frames_l = format!("{:width$}", "Frames", width = column_length);
+        let events_l = format!("{:width$}", "Events", width = column_length);
+        format!(
+            "{}  {}\n{}  {}\n{}  0x{:08x}\n{}  {}\n{}  {}",
+            mode_l,
+            self.mode,
+            start_l,
+            self.start,
+            checksum_l,
+            self.rom_checksum,
+            frames_l,
+            self.frames,
+            events_l,
+            self.events.len()
+        )
+    }
+}
+
+impl Display for Movie {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description(9))
+    }
+}
+
+/// The possible operations of a movie session.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+}
+
+/// Running movie session (recording or playback) that keeps
+/// track of the current position in time, as the number of
+/// frames and cycles elapsed since the start of the movie.
+#[derive(Clone)]
+pub struct MovieSession {
+    movie: Movie,
+    mode: MovieMode,
+
+    /// The index of the current frame since the start
+    /// of the session.
+    frame: u32,
+
+    /// The number of CPU cycles elapsed since the start
+    /// of the current frame.
+    cycles: u32,
+
+    /// The last PPU frame index seen, used to detect the
+    /// transition into a new frame.
+    ppu_frame: u16,
+
+    /// The index of the next event to be played back.
+    index: usize,
+}
+
+impl MovieSession {
+    pub fn new(movie: Movie, mode: MovieMode, ppu_frame: u16) -> Self {
+        Self {
+            movie,
+            mode,
+            frame: 0,
+            cycles: 0,
+            ppu_frame,
+            index: 0,
+        }
+    }
+
+    /// Updates the session position in time with the number of
+    /// cycles that have just been clocked and the current PPU
+    /// frame index (that signals frame transitions).
+    #[inline(always)]
+    pub fn clock(&mut self, cycles: u16, ppu_frame: u16) {
+        if ppu_frame != self.ppu_frame {
+            self.ppu_frame = ppu_frame;
+            self.frame += 1;
+            self.cycles = 0;
+        } else {
+            self.cycles = self.cycles.saturating_add(cycles as u32);
+        }
+    }
+
+    /// Records an input event at the current position in time,
+    /// only effective when the session is recording.
+    pub fn record(&mut self, key: PadKey, pressed: bool) {
+        if self.mode != MovieMode::Recording {
+            return;
+        }
+        self.movie.push(MovieEvent {
+            frame: self.frame,
+            cycles: self.cycles,
+            key,
+            pressed,
+        });
+    }
+
+    /// Obtains the next event to be played back in case its
+    /// position in time has already been reached.
+    pub fn next_event(&mut self) -> Option<MovieEvent> {
+        if self.mode != MovieMode::Playing {
+            return None;
+        }
+        let event = *self.movie.events.get(self.index)?;
+        if (event.frame, event.cycles) > (self.frame, self.cycles) {
+            return None;
+        }
+        self.index += 1;
+        Some(event)
+    }
+
+    /// Finishes the session returning the underlying movie, for
+    /// recordings the total number of frames is updated.
+    pub fn finish(mut self) -> Movie {
+        if self.mode == MovieMode::Recording {
+            self.movie.set_frames(self.frame);
+        }
+        self.movie
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn cycles(&self) -> u32 {
+        self.cycles
+    }
+
+    /// Checks if the playback has already reached the end
+    /// of the movie, meaning that there are no more events
+    /// pending and the movie length has been reached.
+    pub fn finished(&self) -> bool {
+        self.mode == MovieMode::Playing
+            && self.index >= self.movie.events.len()
+            && self.frame >= self.movie.frames
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gb::{GameBoy, GameBoyMode},
+        movie::{Movie, MovieEvent, MovieMode, MovieSession, MovieStart},
+        pad::PadKey,
+        ppu::FRAME_BUFFER_SIZE,
+    };
+
+    /// Builds a Game Boy (with the boot ROM skipped) running a ROM
+    /// that continuously copies the joypad action keys state (P1)
+    /// into the background palette (BGP), so that the keys pressed
+    /// are visible in the frame buffer.
+    fn build_input_test() -> GameBoy {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100..0x010a].copy_from_slice(&[
+            0x3e, 0x10, // LD A, 0x10
+            0xe0, 0x00, // LDH (0x00), A
+            0xf0, 0x00, // LDH A, (0x00)
+            0xe0, 0x47, // LDH (0x47), A
+            0x18, 0xfa, // JR -6
+        ]);
+        let mut game_boy = GameBoy::new(Some(GameBoyMode::Dmg));
+        game_boy.load(false);
+        game_boy.load_rom(&rom);
+        game_boy.boot();
+        game_boy
+    }
+
+    fn run_input_test(game_boy: &mut GameBoy, record: bool) -> Vec<[u8; FRAME_BUFFER_SIZE]> {
+        let mut frames = vec![];
+        for frame in 0..20 {
+            if record && (frame == 5 || frame == 12) {
+                for _ in 0..100 {
+                    game_boy.clock();
+                }
+                match frame {
+                    5 => game_boy.key_press(PadKey::A),
+                    _ => game_boy.key_lift(PadKey::A),
+                }
+            }
+            game_boy.run_frame();
+            frames.push(*game_boy.frame_buffer());
+        }
+        frames
+    }
+
+    #[test]
+    fn test_movie_data() {
+        let mut movie = Movie::new(GameBoyMode::Cgb, MovieStart::PowerOn, 0x12345678);
+        movie.push(MovieEvent {
+            frame: 10,
+            cycles: 1234,
+            key: PadKey::Start,
+            pressed: true,
+        });
+        movie.set_frames(20);
+
+        let data = movie.to_data();
+        let loaded = Movie::from_data(&data).unwrap();
+        assert_eq!(loaded, movie);
+
+        assert!(Movie::from_data(&data[0..8]).is_err());
+        assert!(Movie::from_data(b"XXXX00000000000000000000").is_err());
+    }
+
+    #[test]
+    fn test_movie_session() {
+        let movie = Movie::new(GameBoyMode::Dmg, MovieStart::PowerOn, 0x0);
+        let mut session = MovieSession::new(movie, MovieMode::Recording, 0);
+        session.clock(100, 0);
+        session.record(PadKey::A, true);
+        session.clock(100, 1);
+        session.clock(24, 1);
+        session.record(PadKey::A, false);
+        let movie = session.finish();
+
+        assert_eq!(movie.frames(), 1);
+        assert_eq!(movie.events()[0].frame, 0);
+        assert_eq!(movie.events()[0].cycles, 100);
+        assert_eq!(movie.events()[1].frame, 1);
+        assert_eq!(movie.events()[1].cycles, 24);
+
+        let mut session = MovieSession::new(movie, MovieMode::Playing, 0);
+        assert_eq!(session.next_event(), None);
+        session.clock(100, 0);
+        assert!(session.next_event().unwrap().pressed);
+        assert_eq!(session.next_event(), None);
+        session.clock(100, 1);
+        session.clock(24, 1);
+        assert!(!session.next_event().unwrap().pressed);
+        assert!(session.finished());
+    }
+
+    #[test]
+    fn test_movie_playback() {
+        let mut game_boy = build_input_test();
+        game_boy.start_movie_recording(MovieStart::PowerOn);
+        let recorded = run_input_test(&mut game_boy, true);
+        let movie = game_boy.stop_movie().unwrap();
+        assert_eq!(movie.events().len(), 2);
+        assert_ne!(recorded[4], recorded[8]);
+        assert_ne!(recorded[8], recorded[16]);
+
+        let movie = Movie::from_data(&movie.to_data()).unwrap();
+        let mut game_boy = build_input_test();
+        game_boy.play_movie(movie).unwrap();
+        let played = run_input_test(&mut game_boy, false);
+        assert!(game_boy.movie().unwrap().finished());
+        assert!(recorded == played);
+    }
+
+    #[test]
+    fn test_movie_save_state() {
+        let mut game_boy = build_input_test();
+        game_boy.key_press(PadKey::B);
+        for _ in 0..7 {
+            game_boy.run_frame();
+        }
+        for _ in 0..500 {
+            game_boy.clock();
+        }
+        game_boy.start_movie_recording(MovieStart::SaveState);
+        let recorded = run_input_test(&mut game_boy, true);
+        let movie = game_boy.stop_movie().unwrap();
+        assert!(!movie.state().is_empty());
+
+        // the playback starts from a power-on instance, meaning that
+        // only the embedded state can lead to the same frames
+        let movie = Movie::from_data(&movie.to_data()).unwrap();
+        let mut game_boy = build_input_test();
+        game_boy.play_movie(movie).unwrap();
+        let played = run_input_test(&mut game_boy, false);
+        assert!(game_boy.movie().unwrap().finished());
+        assert!(recorded == played);
+
+        // an invalid embedded state rejects the playback
+        let mut game_boy = build_input_test();
+        let checksum = game_boy.mmu_i().rom_i().checksum();
+        let mut movie = Movie::new(GameBoyMode::Dmg, MovieStart::SaveState, checksum);
+        movie.set_state(b"XXXX".to_vec());
+        assert!(game_boy.play_movie(movie).is_err());
+        assert!(game_boy.movie().is_none());
+    }
+}
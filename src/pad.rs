@@ -1,4 +1,7 @@
-use crate::warnln;
+use crate::{
+    state::{Serializer, StateComponent},
+    warnln,
+};
 
 #[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
@@ -11,6 +14,7 @@ pub enum PadSelection {
 }
 
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum PadKey {
     Up,
     Down,
@@ -22,6 +26,38 @@ pub enum PadKey {
     B,
 }
 
+impl PadKey {
+    /// Obtains the bit mask associated with the key in the
+    /// input state representation (see `Pad::state()`).
+    pub fn mask(&self) -> u8 {
+        match self {
+            PadKey::A => 0x01,
+            PadKey::B => 0x02,
+            PadKey::Select => 0x04,
+            PadKey::Start => 0x08,
+            PadKey::Right => 0x10,
+            PadKey::Left => 0x20,
+            PadKey::Up => 0x40,
+            PadKey::Down => 0x80,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PadKey::Up),
+            1 => Some(PadKey::Down),
+            2 => Some(PadKey::Left),
+            3 => Some(PadKey::Right),
+            4 => Some(PadKey::Start),
+            5 => Some(PadKey::Select),
+            6 => Some(PadKey::A),
+            7 => Some(PadKey::B),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Pad {
     down: bool,
     up: bool,
@@ -33,6 +69,11 @@ pub struct Pad {
     a: bool,
     selection: PadSelection,
     int_pad: bool,
+
+    /// Flag that is set whenever the P1 register is read,
+    /// meaning that the running game has polled the input,
+    /// useful for stepping (and lag frame detection) logic.
+    polled: bool,
 }
 
 impl Pad {
@@ -48,6 +89,7 @@ impl Pad {
             a: false,
             selection: PadSelection::None,
             int_pad: false,
+            polled: false,
         }
     }
 
@@ -79,6 +121,7 @@ impl Pad {
                     PadSelection::Direction => 0x20,
                     PadSelection::None => 0x30,
                 };
+                self.polled = true;
                 value
             }
             _ => {
@@ -133,6 +176,29 @@ impl Pad {
         }
     }
 
+    /// Obtains the current input state as a bit mask with one
+    /// bit per key, using the following order (from the least
+    /// significant bit): A, B, Select, Start, Right, Left, Up, Down.
+    pub fn state(&self) -> u8 {
+        #[allow(clippy::bool_to_int_with_if)]
+        (if self.a { 0x01 } else { 0x00 }
+            | if self.b { 0x02 } else { 0x00 }
+            | if self.select { 0x04 } else { 0x00 }
+            | if self.start { 0x08 } else { 0x00 }
+            | if self.right { 0x10 } else { 0x00 }
+            | if self.left { 0x20 } else { 0x00 }
+            | if self.up { 0x40 } else { 0x00 }
+            | if self.down { 0x80 } else { 0x00 })
+    }
+
+    pub fn polled(&self) -> bool {
+        self.polled
+    }
+
+    pub fn set_polled(&mut self, value: bool) {
+        self.polled = value;
+    }
+
     #[inline(always)]
     pub fn int_pad(&self) -> bool {
         self.int_pad
@@ -149,6 +215,28 @@ impl Pad {
     }
 }
 
+impl StateComponent for Pad {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.bool(&mut self.down);
+        s.bool(&mut self.up);
+        s.bool(&mut self.left);
+        s.bool(&mut self.right);
+        s.bool(&mut self.start);
+        s.bool(&mut self.select);
+        s.bool(&mut self.b);
+        s.bool(&mut self.a);
+        let mut selection = self.selection as u8;
+        s.u8(&mut selection);
+        self.selection = match selection {
+            0x01 => PadSelection::Action,
+            0x02 => PadSelection::Direction,
+            _ => PadSelection::None,
+        };
+        s.bool(&mut self.int_pad);
+        s.bool(&mut self.polled);
+    }
+}
+
 impl Default for Pad {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,286 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{gb::GameBoy, ppu::FRAME_BUFFER_SIZE};
+
+/// Job sent to the pool workers, with the index of the chunk,
+/// the chunk of instances and the number of frames to run.
+type PoolJob = (usize, Vec<GameBoy>, u32);
+
+/// Result of a job sent back by the pool workers, with the chunk
+/// of instances (always returned, even if the stepping panicked)
+/// and either its frame buffers or the panic message.
+type PoolResult = (usize, Vec<GameBoy>, Result<Vec<u8>, String>);
+
+/// Pool of independent Game Boy instances that are stepped
+/// in parallel across multiple threads, meant for headless
+/// test farms and machine learning rollouts.
+///
+/// The frame buffers of all the instances are gathered after
+/// each step into a single contiguous buffer (instance after
+/// instance, each with `FRAME_BUFFER_SIZE` bytes).
+///
+/// The worker threads are started once, when the pool is created,
+/// and are fed with the chunks of instances to step through a
+/// channel, so that short steps (eg: one frame) are not dominated
+/// by the cost of creating threads.
+pub struct GameBoyPool {
+    /// The Game Boy instances that compose the pool.
+    instances: Vec<GameBoy>,
+
+    /// The maximum number of threads to be used when
+    /// stepping the instances.
+    threads: usize,
+
+    /// Contiguous buffer with the frame buffers of all the
+    /// instances, updated at the end of each step.
+    frame_buffers: Vec<u8>,
+
+    /// The channel used to send the jobs to the workers, shared
+    /// by all of them, dropped to stop the workers.
+    jobs: Option<Sender<PoolJob>>,
+
+    /// The channel where the workers send the results back.
+    results: Receiver<PoolResult>,
+
+    /// The handles of the (persistent) worker threads.
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl GameBoyPool {
+    pub fn new(instances: Vec<GameBoy>) -> Self {
+        let threads = thread::available_parallelism()
+            .map(|value| value.get())
+            .unwrap_or(1);
+        Self::with_threads(instances, threads)
+    }
+
+    pub fn with_threads(instances: Vec<GameBoy>, threads: usize) -> Self {
+        let threads = threads.max(1);
+        let frame_buffers = vec![0u8; instances.len() * FRAME_BUFFER_SIZE];
+        let (jobs, jobs_r) = channel::<PoolJob>();
+        let (results_s, results) = channel::<PoolResult>();
+        let jobs_r = Arc::new(Mutex::new(jobs_r));
+        let workers = (0..threads)
+            .map(|_| {
+                let (jobs_r, results_s) = (jobs_r.clone(), results_s.clone());
+                thread::spawn(move || Self::worker(jobs_r, results_s))
+            })
+            .collect();
+        Self {
+            instances,
+            threads,
+            frame_buffers,
+            jobs: Some(jobs),
+            results,
+            workers,
+        }
+    }
+
+    /// Runs the given number of frames in every instance of the
+    /// pool (in parallel), optionally setting the input state of
+    /// each of the instances before (one mask per instance).
+    /// Returns the contiguous buffer with all the frame buffers.
+    ///
+    /// In case the emulation of any instance panics an error is
+    /// returned, the instances are kept in the pool nonetheless.
+    pub fn step(&mut self, inputs: Option<&[u8]>, frames: u32) -> Result<&[u8], String> {
+        let count = self.instances.len();
+        if let Some(inputs) = inputs {
+            assert_eq!(inputs.len(), count, "Invalid number of inputs");
+            for (instance, input) in self.instances.iter_mut().zip(inputs) {
+                instance.set_input_state(*input);
+            }
+        }
+
+        // splits the instances into (ordered) chunks, one per thread,
+        // the chunks are moved into the workers and then moved back
+        // (in any order) once they have been stepped
+        let chunk_size = count.saturating_sub(1) / self.threads + 1;
+        let mut instances = std::mem::take(&mut self.instances);
+        let mut chunk_count = 0;
+        let jobs = self.jobs.as_ref().unwrap();
+        while !instances.is_empty() {
+            let remaining = instances.split_off(chunk_size.min(instances.len()));
+            jobs.send((chunk_count, instances, frames)).unwrap();
+            instances = remaining;
+            chunk_count += 1;
+        }
+
+        let mut results: Vec<Option<PoolResult>> = (0..chunk_count).map(|_| None).collect();
+        for _ in 0..chunk_count {
+            let result = self.results.recv().unwrap();
+            let index = result.0;
+            results[index] = Some(result);
+        }
+
+        // puts all of the instances back in the pool (in the original
+        // order) before reporting the error of any of the chunks
+        let mut error = None;
+        self.frame_buffers.clear();
+        for (_, chunk, result) in results.into_iter().flatten() {
+            self.instances.extend(chunk);
+            match result {
+                Ok(buffer) => self.frame_buffers.extend(buffer),
+                Err(message) => {
+                    error.get_or_insert(message);
+                }
+            }
+        }
+        self.frame_buffers
+            .resize(self.instances.len() * FRAME_BUFFER_SIZE, 0);
+
+        match error {
+            Some(message) => Err(message),
+            None => Ok(&self.frame_buffers),
+        }
+    }
+
+    pub fn frame_buffers(&self) -> &[u8] {
+        &self.frame_buffers
+    }
+
+    /// Obtains the frame buffer of the instance at the given
+    /// index, as gathered in the last step.
+    pub fn frame_buffer(&self, index: usize) -> &[u8] {
+        &self.frame_buffers[index * FRAME_BUFFER_SIZE..(index + 1) * FRAME_BUFFER_SIZE]
+    }
+
+    pub fn push(&mut self, instance: GameBoy) {
+        self.instances.push(instance);
+        self.frame_buffers
+            .resize(self.instances.len() * FRAME_BUFFER_SIZE, 0);
+    }
+
+    pub fn instance(&mut self, index: usize) -> &mut GameBoy {
+        &mut self.instances[index]
+    }
+
+    pub fn instances(&mut self) -> &mut Vec<GameBoy> {
+        &mut self.instances
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Main loop of a worker thread, that steps the chunks of
+    /// instances received until the jobs channel is closed.
+    fn worker(jobs: Arc<Mutex<Receiver<PoolJob>>>, results: Sender<PoolResult>) {
+        loop {
+            let job = jobs.lock().unwrap().recv();
+            let (index, mut chunk, frames) = match job {
+                Ok(job) => job,
+                Err(_) => break,
+            };
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                let mut buffer = Vec::with_capacity(chunk.len() * FRAME_BUFFER_SIZE);
+                for instance in chunk.iter_mut() {
+                    for _ in 0..frames {
+                        instance.run_frame();
+                    }
+                    buffer.extend_from_slice(instance.frame_buffer());
+                }
+                buffer
+            }))
+            .map_err(|payload| {
+                payload
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| {
+                        payload
+                            .downcast_ref::<&str>()
+                            .map(|value| value.to_string())
+                    })
+                    .unwrap_or_else(|| String::from("Pool worker panicked"))
+            });
+            if results.send((index, chunk, result)).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for GameBoyPool {
+    fn drop(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{gb::GameBoy, pool::GameBoyPool, ppu::FRAME_BUFFER_SIZE};
+
+    fn build_instance() -> GameBoy {
+        let mut game_boy = GameBoy::new(None);
+        game_boy.set_apu_enabled(false);
+        game_boy.load(true);
+        game_boy.load_rom_file("res/roms/test/dmg_acid2.gb");
+        game_boy
+    }
+
+    #[test]
+    fn test_game_boy_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<GameBoy>();
+    }
+
+    #[test]
+    fn test_pool_step() {
+        let instances = (0..3).map(|_| build_instance()).collect();
+        let mut pool = GameBoyPool::with_threads(instances, 2);
+        let buffers = pool.step(None, 30).unwrap().to_vec();
+        assert_eq!(buffers.len(), 3 * FRAME_BUFFER_SIZE);
+
+        let mut game_boy = build_instance();
+        for _ in 0..30 {
+            game_boy.run_frame();
+        }
+        for index in 0..3 {
+            assert_eq!(pool.frame_buffer(index), &game_boy.frame_buffer()[..]);
+        }
+
+        // the same (persistent) workers are used in the next steps
+        for _ in 0..30 {
+            pool.step(None, 1).unwrap();
+            game_boy.run_frame();
+        }
+        for index in 0..3 {
+            assert_eq!(pool.frame_buffer(index), &game_boy.frame_buffer()[..]);
+        }
+    }
+
+    #[test]
+    fn test_pool_panic() {
+        let mut instances: Vec<GameBoy> = (0..2).map(|_| build_instance()).collect();
+        instances.push(GameBoy::new(None));
+        let mut pool = GameBoyPool::with_threads(instances, 2);
+
+        // the instance without a ROM loaded panics when clocked
+        // but none of the instances is lost by the pool
+        assert!(pool.step(None, 1).is_err());
+        assert_eq!(pool.len(), 3);
+        assert_eq!(pool.frame_buffers().len(), 3 * FRAME_BUFFER_SIZE);
+
+        pool.instances().pop();
+        assert!(pool.step(None, 1).is_ok());
+        assert_eq!(pool.len(), 2);
+    }
+}
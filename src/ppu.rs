@@ -1,14 +1,16 @@
 use core::fmt;
 use std::{
     borrow::BorrowMut,
-    cell::RefCell,
     cmp::max,
+    collections::VecDeque,
     fmt::{Display, Formatter},
-    rc::Rc,
+    sync::{Arc, Mutex},
 };
 
 use crate::{
     gb::{GameBoyConfig, GameBoyMode},
+    state::{Serializer, StateComponent},
+    util::SharedThread,
     warnln,
 };
 
@@ -54,6 +56,49 @@ pub const COLOR_BUFFER_SIZE: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
 /// The size of the RGB frame buffer in bytes.
 pub const FRAME_BUFFER_SIZE: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT * RGB_SIZE;
 
+/// The width of a complete background/window tile
+/// map in pixels (32 tiles of 8 pixels).
+pub const MAP_WIDTH: usize = 256;
+
+/// The height of a complete background/window tile
+/// map in pixels (32 tiles of 8 pixels).
+pub const MAP_HEIGHT: usize = 256;
+
+/// The size of the RGB buffer of a rendered tile map in bytes.
+pub const MAP_BUFFER_SIZE: usize = MAP_WIDTH * MAP_HEIGHT * RGB_SIZE;
+
+/// The maximum number of objects that can be drawn in
+/// a single line by the Game Boy.
+pub const OBJ_LINE_COUNT: usize = 10;
+
+/// The width in pixels of each of the extra (left and right)
+/// margins rendered in the widescreen enhancement mode.
+pub const WIDE_MARGIN: usize = 48;
+
+/// The width of the widescreen frame in pixels, making
+/// for a 16:9 aspect ratio.
+pub const WIDE_DISPLAY_WIDTH: usize = DISPLAY_WIDTH + WIDE_MARGIN * 2;
+
+/// The size of the RGB widescreen frame buffer in bytes.
+pub const WIDE_FRAME_BUFFER_SIZE: usize = WIDE_DISPLAY_WIDTH * DISPLAY_HEIGHT * RGB_SIZE;
+
+/// The color used to highlight the window region
+/// in the debug overlay.
+pub const DEBUG_WINDOW_COLOR: Pixel = [0x00, 0x80, 0xff];
+
+/// The color used for the bounding boxes of the objects
+/// in the debug overlay.
+pub const DEBUG_OBJ_COLOR: Pixel = [0x00, 0xff, 0x00];
+
+/// The color used for the bounding boxes of the objects
+/// dropped by the 10 objects per line limit, in the debug
+/// overlay, also used to mark the lines with dropped objects.
+pub const DEBUG_OBJ_DROP_COLOR: Pixel = [0xff, 0x00, 0x00];
+
+/// The number of PPU cycles (dots) of a complete frame,
+/// including the V-Blank lines.
+pub const FRAME_CYCLES: u32 = 70224;
+
 /// The base colors to be used to populate the
 /// custom palettes of the Game Boy.
 pub const PALETTE_COLORS: Palette = [[255, 255, 255], [192, 192, 192], [96, 96, 96], [0, 0, 0]];
@@ -201,6 +246,49 @@ impl ObjectData {
     }
 }
 
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl ObjectData {
+    pub fn x(&self) -> i16 {
+        self.x
+    }
+
+    pub fn y(&self) -> i16 {
+        self.y
+    }
+
+    pub fn tile(&self) -> u8 {
+        self.tile
+    }
+
+    pub fn palette_cgb(&self) -> u8 {
+        self.palette_cgb
+    }
+
+    pub fn tile_bank(&self) -> u8 {
+        self.tile_bank
+    }
+
+    pub fn palette(&self) -> u8 {
+        self.palette
+    }
+
+    pub fn xflip(&self) -> bool {
+        self.xflip
+    }
+
+    pub fn yflip(&self) -> bool {
+        self.yflip
+    }
+
+    pub fn bg_over(&self) -> bool {
+        self.bg_over
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+}
+
 impl Default for ObjectData {
     fn default() -> Self {
         Self::new()
@@ -239,6 +327,29 @@ impl TileData {
     }
 }
 
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl TileData {
+    pub fn palette(&self) -> u8 {
+        self.palette
+    }
+
+    pub fn vram_bank(&self) -> u8 {
+        self.vram_bank
+    }
+
+    pub fn xflip(&self) -> bool {
+        self.xflip
+    }
+
+    pub fn yflip(&self) -> bool {
+        self.yflip
+    }
+
+    pub fn priority(&self) -> bool {
+        self.priority
+    }
+}
+
 impl Default for TileData {
     fn default() -> Self {
         Self::new()
@@ -255,6 +366,239 @@ impl Display for TileData {
     }
 }
 
+/// The strategy used by the PPU to render the scanlines
+/// into the frame buffer.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PpuRenderer {
+    /// Renders a complete scanline at once at the end of the
+    /// pixel transfer (mode 3), fast but ignores any register
+    /// change made during the drawing of the line.
+    Line = 0,
+
+    /// Cycle-accurate renderer that fetches the background, window
+    /// and object pixels dot by dot through a pixel FIFO, meaning
+    /// that mid-scanline register writes take effect at the
+    /// proper pixel (raster effects).
+    Fifo = 1,
+}
+
+impl PpuRenderer {
+    pub fn description(&self) -> &'static str {
+        match self {
+            PpuRenderer::Line => "Line",
+            PpuRenderer::Fifo => "FIFO",
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0 => PpuRenderer::Line,
+            1 => PpuRenderer::Fifo,
+            _ => panic!("Invalid renderer value: {}", value),
+        }
+    }
+
+    pub fn from_string(value: &str) -> Self {
+        match value {
+            "line" => PpuRenderer::Line,
+            "fifo" => PpuRenderer::Fifo,
+            _ => panic!("Invalid renderer value: {}", value),
+        }
+    }
+}
+
+impl Display for PpuRenderer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+/// The color correction curve applied to the RGB555 colors
+/// of the CGB palettes, emulating the response of the LCD.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorCorrection {
+    /// Straight bit expansion of the RGB555 colors, results
+    /// in oversaturated colors when compared with real hardware.
+    None = 0,
+
+    /// Emulates the (darker and less saturated) LCD of the
+    /// Game Boy Color, using Gambatte's formula.
+    Gbc = 1,
+
+    /// Emulates the LCD of the Game Boy Advance (used when
+    /// running CGB games), using Talarubi's formula.
+    Gba = 2,
+}
+
+impl ColorCorrection {
+    pub fn description(&self) -> &'static str {
+        match self {
+            ColorCorrection::None => "None",
+            ColorCorrection::Gbc => "GBC LCD",
+            ColorCorrection::Gba => "GBA",
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0 => ColorCorrection::None,
+            1 => ColorCorrection::Gbc,
+            2 => ColorCorrection::Gba,
+            _ => panic!("Invalid color correction value: {}", value),
+        }
+    }
+
+    pub fn from_string(value: &str) -> Self {
+        match value {
+            "none" => ColorCorrection::None,
+            "gbc" => ColorCorrection::Gbc,
+            "gba" => ColorCorrection::Gba,
+            _ => panic!("Invalid color correction value: {}", value),
+        }
+    }
+}
+
+impl Display for ColorCorrection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+/// Single pixel stored in one of the pixel FIFOs, either
+/// a background/window pixel or an object pixel.
+#[derive(Clone, Copy, Default)]
+struct FifoPixel {
+    /// The raw color index of the pixel (from 0 to 3).
+    color: u8,
+
+    /// The palette to be used for the pixel, the DMG palette
+    /// number or the CGB palette index.
+    palette: u8,
+
+    /// For background pixels the BG-to-OAM priority (CGB only),
+    /// for objects the BG over object priority flag.
+    priority: bool,
+
+    /// The index of the object in OAM, used for the
+    /// OAM based priority of objects (CGB only).
+    index: u8,
+
+    /// If the (background) pixel comes from the window.
+    window: bool,
+}
+
+/// Internal state of the FIFO based renderer, that is
+/// re-started at the beginning of each pixel transfer.
+#[derive(Clone)]
+struct PixelFifo {
+    /// The background/window pixel FIFO.
+    bg: VecDeque<FifoPixel>,
+
+    /// The object pixel FIFO, aligned with the next
+    /// pixels to be shifted out of the background FIFO.
+    obj: VecDeque<FifoPixel>,
+
+    /// The tile row fetched by the fetcher, waiting to be
+    /// pushed into the background FIFO.
+    row: [FifoPixel; TILE_WIDTH],
+
+    /// If the fetched tile row is ready to be pushed.
+    row_ready: bool,
+
+    /// The number of dots spent in the current tile fetch.
+    fetch_step: u8,
+
+    /// The tile column (within the line) to be fetched next.
+    fetch_x: u8,
+
+    /// If the fetcher is currently fetching window tiles.
+    window: bool,
+
+    /// Number of dots to wait before the fetcher starts,
+    /// accounting for the initial (discarded) tile fetch.
+    delay: u8,
+
+    /// Number of pixels to be discarded from the FIFO,
+    /// used for the SCX fine scroll.
+    discard: u8,
+
+    /// The number of dots remaining in the fetch of an object,
+    /// during which the pixel output is stalled.
+    obj_stall: u8,
+
+    /// The OAM index of the object that is being fetched.
+    obj_pending: usize,
+
+    /// The OAM indexes of the objects selected for the line
+    /// in the OAM scan (up to 10).
+    objects: [usize; OBJ_COUNT],
+
+    /// The number of objects selected for the line.
+    obj_count: usize,
+
+    /// The flags that control if each of the selected
+    /// objects has already been fetched.
+    obj_fetched: [bool; OBJ_COUNT],
+
+    /// The X coordinate of the next pixel to be output.
+    lx: u8,
+
+    /// The number of dots spent in the current pixel transfer.
+    dots: u16,
+}
+
+impl PixelFifo {
+    pub fn new() -> Self {
+        Self {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(TILE_WIDTH),
+            row: [FifoPixel::default(); TILE_WIDTH],
+            row_ready: false,
+            fetch_step: 0,
+            fetch_x: 0,
+            window: false,
+            delay: 0,
+            discard: 0,
+            obj_stall: 0,
+            obj_pending: 0,
+            objects: [0; OBJ_COUNT],
+            obj_count: 0,
+            obj_fetched: [false; OBJ_COUNT],
+            lx: 0,
+            dots: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.bg.clear();
+        self.obj.clear();
+        self.row_ready = false;
+        self.fetch_step = 0;
+        self.fetch_x = 0;
+        self.window = false;
+        self.delay = 0;
+        self.discard = 0;
+        self.obj_stall = 0;
+        self.obj_pending = 0;
+        self.obj_count = 0;
+        self.obj_fetched = [false; OBJ_COUNT];
+        self.lx = 0;
+        self.dots = 0;
+    }
+
+    pub fn done(&self) -> bool {
+        self.lx as usize == DISPLAY_WIDTH
+    }
+}
+
+impl Default for PixelFifo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct PpuRegisters {
     pub scy: u8,
     pub scx: u8,
@@ -276,6 +620,7 @@ pub struct PpuRegisters {
 /// let mut ppu = Ppu::default();
 /// ppu.clock(8);
 /// ```
+#[derive(Clone)]
 pub struct Ppu {
     /// The color buffer that is going to store the colors
     /// (from 0 to 3) for all the pixels in the screen.
@@ -285,6 +630,15 @@ pub struct Ppu {
     /// processed set of pixels ready to be displayed on screen.
     pub frame_buffer: Box<[u8; FRAME_BUFFER_SIZE]>,
 
+    /// The RGB frame buffer of the widescreen enhancement mode,
+    /// with the visible frame surrounded by the background
+    /// beyond the 160 pixels viewport (left and right).
+    pub frame_buffer_wide: Box<[u8; WIDE_FRAME_BUFFER_SIZE]>,
+
+    /// The (unblended) RGB frame buffer of the previous frame,
+    /// used by the frame blending mode.
+    frame_buffer_last: Box<[u8; FRAME_BUFFER_SIZE]>,
+
     /// The buffer that will control the background to OAM
     /// priority, allowing the background to be drawn over
     /// the sprites/objects if necessary.
@@ -400,6 +754,77 @@ pub struct Ppu {
     /// spent in each of the PPU modes.
     mode_clock: u16,
 
+    /// The length in dots of the pixel transfer (mode 3) of the
+    /// current line, depends on the SCX fine scroll, the window
+    /// and the objects in the line.
+    vram_length: u16,
+
+    /// The length in dots of the H-Blank of the current line,
+    /// so that every line takes the same 456 dots.
+    hblank_length: u16,
+
+    /// Internal clock counter used while the LCD is off, so that
+    /// (blank) frames keep being produced at the regular rate.
+    lcd_clock: u32,
+
+    /// If the current line is the first one after the LCD has
+    /// been enabled, this line is shorter and has no OAM scan.
+    first_line: bool,
+
+    /// If the last line (153) of the V-Blank is being processed
+    /// after LY has already been reset to zero (LY=153 quirk).
+    line_153: bool,
+
+    /// The renderer that is going to be used to draw the
+    /// scanlines into the frame buffer.
+    renderer: PpuRenderer,
+
+    /// The state of the pixel FIFO, only used by the
+    /// FIFO based renderer.
+    fifo: PixelFifo,
+
+    /// If the background layer is visible, debug switch that
+    /// is independent from the LCDC register.
+    layer_bg: bool,
+
+    /// If the window layer is visible, debug switch that
+    /// is independent from the LCDC register.
+    layer_window: bool,
+
+    /// If the objects layer is visible, debug switch that
+    /// is independent from the LCDC register.
+    layer_obj: bool,
+
+    /// If the window region should be highlighted (debug overlay).
+    debug_window: bool,
+
+    /// If the bounding boxes of the objects should be drawn
+    /// (debug overlay).
+    debug_objects: bool,
+
+    /// If the objects dropped by the 10 objects per line limit
+    /// should be marked (debug overlay).
+    debug_obj_limit: bool,
+
+    /// If the 10 objects per line limit should be lifted, an
+    /// enhancement that removes the flicker of some games, the
+    /// timing of the pixel transfer remains the original one.
+    unlimited_objects: bool,
+
+    /// If the widescreen frame buffer should be rendered, an
+    /// enhancement that extends the background (and objects)
+    /// beyond the 160 pixels viewport.
+    widescreen: bool,
+
+    /// The color correction curve applied to the colors
+    /// of the CGB palettes.
+    color_correction: ColorCorrection,
+
+    /// If each frame should be mixed with the previous one,
+    /// emulating the slow response of the LCD (ghosting) used
+    /// by some games for flicker based transparency.
+    frame_blending: bool,
+
     /// Controls if the background is going to be drawn to screen.
     /// In CGB mode this flag controls the master priority instead
     /// enabling or disabling complex priority rules.
@@ -466,6 +891,11 @@ pub struct Ppu {
     stat_oam: bool,
     stat_lyc: bool,
 
+    /// The current level of the (internal) LCD STAT interrupt line,
+    /// the interrupt is only requested on its rising edge (STAT
+    /// blocking) when all the sources are OR-ed together.
+    stat_line: bool,
+
     /// Boolean value set when the V-Blank interrupt should be handled
     /// by the next CPU clock operation.
     int_vblank: bool,
@@ -487,7 +917,7 @@ pub struct Ppu {
     /// The pointer to the parent configuration of the running
     /// Game Boy emulator, that can be used to control the behaviour
     /// of Game Boy emulation.
-    gbc: Rc<RefCell<GameBoyConfig>>,
+    gbc: SharedThread<GameBoyConfig>,
 }
 
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
@@ -499,11 +929,26 @@ pub enum PpuMode {
     VramRead = 3,
 }
 
+impl PpuMode {
+    /// Obtains the mode from its value (as in the STAT register),
+    /// only the two lower bits are considered.
+    pub fn from_u8(value: u8) -> Self {
+        match value & 0x03 {
+            0 => PpuMode::HBlank,
+            1 => PpuMode::VBlank,
+            2 => PpuMode::OamRead,
+            _ => PpuMode::VramRead,
+        }
+    }
+}
+
 impl Ppu {
-    pub fn new(mode: GameBoyMode, gbc: Rc<RefCell<GameBoyConfig>>) -> Self {
+    pub fn new(mode: GameBoyMode, gbc: SharedThread<GameBoyConfig>) -> Self {
         Self {
             color_buffer: Box::new([0u8; COLOR_BUFFER_SIZE]),
             frame_buffer: Box::new([0u8; FRAME_BUFFER_SIZE]),
+            frame_buffer_wide: Box::new([0u8; WIDE_FRAME_BUFFER_SIZE]),
+            frame_buffer_last: Box::new([0u8; FRAME_BUFFER_SIZE]),
             priority_buffer: Box::new([false; COLOR_BUFFER_SIZE]),
             vram: [0u8; VRAM_SIZE],
             hram: [0u8; HRAM_SIZE],
@@ -531,6 +976,23 @@ impl Ppu {
             lyc: 0x0,
             mode: PpuMode::OamRead,
             mode_clock: 0,
+            vram_length: 172,
+            hblank_length: 204,
+            lcd_clock: 0,
+            first_line: false,
+            line_153: false,
+            renderer: PpuRenderer::Line,
+            fifo: PixelFifo::new(),
+            layer_bg: true,
+            layer_window: true,
+            layer_obj: true,
+            debug_window: false,
+            debug_objects: false,
+            debug_obj_limit: false,
+            unlimited_objects: false,
+            widescreen: false,
+            color_correction: ColorCorrection::None,
+            frame_blending: false,
             switch_bg: false,
             switch_obj: false,
             obj_size: false,
@@ -550,6 +1012,7 @@ impl Ppu {
             stat_vblank: false,
             stat_oam: false,
             stat_lyc: false,
+            stat_line: false,
             int_vblank: false,
             int_stat: false,
             dmg_compat: false,
@@ -559,9 +1022,11 @@ impl Ppu {
     }
 
     pub fn reset(&mut self) {
-        self.color_buffer = Box::new([0u8; COLOR_BUFFER_SIZE]);
-        self.frame_buffer = Box::new([0u8; FRAME_BUFFER_SIZE]);
-        self.priority_buffer = Box::new([false; COLOR_BUFFER_SIZE]);
+        self.color_buffer.fill(0);
+        self.frame_buffer.fill(0);
+        self.frame_buffer_wide.fill(0);
+        self.frame_buffer_last.fill(0);
+        self.priority_buffer.fill(false);
         self.vram = [0u8; VRAM_SIZE_CGB];
         self.hram = [0u8; HRAM_SIZE];
         self.vram_bank = 0x0;
@@ -584,6 +1049,12 @@ impl Ppu {
         self.lyc = 0x0;
         self.mode = PpuMode::OamRead;
         self.mode_clock = 0;
+        self.vram_length = 172;
+        self.hblank_length = 204;
+        self.lcd_clock = 0;
+        self.first_line = false;
+        self.line_153 = false;
+        self.fifo.reset();
         self.switch_bg = false;
         self.switch_obj = false;
         self.obj_size = false;
@@ -603,15 +1074,22 @@ impl Ppu {
         self.stat_vblank = false;
         self.stat_oam = false;
         self.stat_lyc = false;
+        self.stat_line = false;
         self.int_vblank = false;
         self.int_stat = false;
         self.dmg_compat = false;
     }
 
     pub fn clock(&mut self, cycles: u16) {
-        // in case the LCD is currently off then we skip the current
-        // clock operation the PPU should not work
+        // in case the LCD is currently off then the PPU does not work,
+        // but the time keeps running so that the (blank) frames are
+        // produced at the same rate as if the LCD was on
         if !self.switch_lcd {
+            self.lcd_clock += cycles as u32;
+            if self.lcd_clock >= FRAME_CYCLES {
+                self.lcd_clock -= FRAME_CYCLES;
+                self.frame_index = self.frame_index.wrapping_add(1);
+            }
             return;
         }
 
@@ -624,19 +1102,59 @@ impl Ppu {
                 if self.mode_clock >= 80 {
                     self.mode = PpuMode::VramRead;
                     self.mode_clock -= 80;
+                    self.first_line = false;
+                    match self.renderer {
+                        PpuRenderer::Line => self.vram_length = self.compute_vram_length(),
+                        PpuRenderer::Fifo => self.fifo_start(),
+                    }
+                    self.update_stat()
                 }
             }
-            PpuMode::VramRead => {
-                if self.mode_clock >= 172 {
-                    self.render_line();
+            PpuMode::VramRead => match self.renderer {
+                PpuRenderer::Line => {
+                    if self.mode_clock >= self.vram_length {
+                        self.render_line();
+                        if self.frame_blending {
+                            self.blend_line();
+                        }
+                        self.render_debug();
+                        if self.widescreen {
+                            self.render_wide_line();
+                        }
+
+                        self.mode = PpuMode::HBlank;
+                        self.mode_clock -= self.vram_length;
+                        self.hblank_length = 376 - self.vram_length;
+                        self.update_stat()
+                    }
+                }
+                PpuRenderer::Fifo => {
+                    // runs the pixel FIFO dot by dot for the available
+                    // cycles, the remaining cycles (if any) are going
+                    // to be counted as part of the H-Blank
+                    while self.mode_clock > 0 && !self.fifo.done() {
+                        self.fifo_dot();
+                        self.mode_clock -= 1;
+                    }
 
-                    self.mode = PpuMode::HBlank;
-                    self.mode_clock -= 172;
-                    self.update_stat()
+                    if self.fifo.done() {
+                        if self.frame_blending {
+                            self.blend_line();
+                        }
+                        self.render_debug();
+                        if self.widescreen {
+                            self.render_wide_line();
+                        }
+
+                        self.mode = PpuMode::HBlank;
+                        self.vram_length = self.fifo.dots;
+                        self.hblank_length = 376u16.saturating_sub(self.fifo.dots);
+                        self.update_stat()
+                    }
                 }
-            }
+            },
             PpuMode::HBlank => {
-                if self.mode_clock >= 204 {
+                if self.mode_clock >= self.hblank_length {
                     // increments the window counter making sure that the
                     // valid is only incremented when both the WX and WY
                     // registers make sense (are within range), the window
@@ -662,30 +1180,40 @@ impl Ppu {
                         self.mode = PpuMode::OamRead;
                     }
 
-                    self.mode_clock -= 204;
+                    self.mode_clock -= self.hblank_length;
                     self.update_stat()
                 }
             }
             PpuMode::VBlank => {
-                if self.mode_clock >= 456 {
-                    // increments the register that controls the line count,
-                    // notice that these represent the extra 10 horizontal
-                    // scanlines that are virtual and not real (off-screen)
-                    self.ly += 1;
+                // in the last line of the V-Blank (153) LY only holds
+                // its value for a few dots before being reset to zero,
+                // meaning that LY=LYC=0 matches in line 153
+                if self.ly == 153 && !self.line_153 && self.mode_clock >= 4 {
+                    self.ly = 0;
+                    self.line_153 = true;
+                    self.update_stat()
+                }
 
+                if self.mode_clock >= 456 {
                     // in case the end of V-Blank has been reached then
-                    // we must jump again to the OAM read mode and reset
-                    // the scan line counter to the zero value
-                    if self.ly == 154 {
+                    // we must jump again to the OAM read mode, notice
+                    // that the scan line counter is already at zero
+                    if self.line_153 {
                         self.mode = PpuMode::OamRead;
-                        self.ly = 0;
+                        self.line_153 = false;
                         self.window_counter = 0;
                         self.first_frame = false;
                         self.frame_index = self.frame_index.wrapping_add(1);
-                        self.update_stat()
+                    }
+                    // otherwise increments the register that controls the
+                    // line count, notice that these represent the extra 10
+                    // horizontal scanlines that are virtual (off-screen)
+                    else {
+                        self.ly += 1;
                     }
 
                     self.mode_clock -= 456;
+                    self.update_stat()
                 }
             }
         }
@@ -694,7 +1222,7 @@ impl Ppu {
     pub fn read(&mut self, addr: u16) -> u8 {
         match addr {
             0x8000..=0x9fff => self.vram[(self.vram_offset + (addr & 0x1fff)) as usize],
-            0xfe00..=0xfe9f => self.oam[(addr & 0x009f) as usize],
+            0xfe00..=0xfe9f => self.oam[(addr & 0x00ff) as usize],
             // Not Usable
             0xfea0..=0xfeff => 0xff,
             0xff80..=0xfffe => self.hram[(addr & 0x007f) as usize],
@@ -716,7 +1244,7 @@ impl Ppu {
                     | if self.stat_oam { 0x20 } else { 0x00 }
                     | if self.stat_lyc { 0x40 } else { 0x00 }
                     | if self.lyc == self.ly { 0x04 } else { 0x00 }
-                    | (self.mode as u8 & 0x03))
+                    | (self.stat_mode() as u8 & 0x03))
             }
             0xff42 => self.scy,
             0xff43 => self.scx,
@@ -765,13 +1293,15 @@ impl Ppu {
                 }
             }
             0xfe00..=0xfe9f => {
-                self.oam[(addr & 0x009f) as usize] = value;
+                self.oam[(addr & 0x00ff) as usize] = value;
                 self.update_object(addr, value);
             }
             // Not Usable
             0xfea0..=0xfeff => (),
             0xff80..=0xfffe => self.hram[(addr & 0x007f) as usize] = value,
             0xff40 => {
+                let switch_lcd = self.switch_lcd;
+
                 self.switch_bg = value & 0x01 == 0x01;
                 self.switch_obj = value & 0x02 == 0x02;
                 self.obj_size = value & 0x04 == 0x04;
@@ -781,17 +1311,32 @@ impl Ppu {
                 self.window_map = value & 0x40 == 0x40;
                 self.switch_lcd = value & 0x80 == 0x80;
 
-                // in case the LCD is off takes the opportunity
-                // to clear the screen, this is the expected
-                // behaviour for this specific situation
-                if !self.switch_lcd {
+                // in case the LCD has been turned off the PPU is stopped
+                // with LY reset and STAT in mode 0, the screen becomes
+                // blank (white) while the LCD remains off
+                if switch_lcd && !self.switch_lcd {
                     self.mode = PpuMode::HBlank;
                     self.mode_clock = 0;
+                    self.lcd_clock = 0;
                     self.ly = 0;
+                    self.line_153 = false;
+                    self.stat_line = false;
                     self.int_vblank = false;
                     self.int_stat = false;
+                    self.clear_lcd();
+                }
+
+                // in case the LCD has been turned on the first line is
+                // started without OAM scan (STAT reports mode 0) and is
+                // 4 dots shorter, the first frame is not displayed
+                if !switch_lcd && self.switch_lcd {
+                    self.mode = PpuMode::OamRead;
+                    self.mode_clock = 4;
+                    self.ly = 0;
+                    self.window_counter = 0;
+                    self.first_line = true;
                     self.first_frame = true;
-                    self.clear_frame_buffer();
+                    self.update_stat();
                 }
             }
             0xff41 => {
@@ -799,10 +1344,14 @@ impl Ppu {
                 self.stat_vblank = value & 0x10 == 0x10;
                 self.stat_oam = value & 0x20 == 0x20;
                 self.stat_lyc = value & 0x40 == 0x40;
+                self.update_stat();
             }
             0xff42 => self.scy = value,
             0xff43 => self.scx = value,
-            0xff45 => self.lyc = value,
+            0xff45 => {
+                self.lyc = value;
+                self.update_stat();
+            }
             0xff47 => {
                 if value == self.palettes[0] {
                     return;
@@ -864,7 +1413,13 @@ impl Ppu {
                 let palette_color = &mut self.palettes_color[0];
                 palette_color[self.palette_address_bg as usize] = value;
                 let palette = &mut self.palettes_color_bg[palette_index as usize];
-                Self::compute_palette_color(palette, palette_color, palette_index, color_index);
+                Self::compute_palette_color(
+                    palette,
+                    palette_color,
+                    palette_index,
+                    color_index,
+                    self.color_correction,
+                );
 
                 if self.auto_increment_bg {
                     self.palette_address_bg = (self.palette_address_bg + 1) & 0x3f;
@@ -883,7 +1438,13 @@ impl Ppu {
                 let palette_color = &mut self.palettes_color[1];
                 palette_color[self.palette_address_obj as usize] = value;
                 let palette = &mut self.palettes_color_obj[palette_index as usize];
-                Self::compute_palette_color(palette, palette_color, palette_index, color_index);
+                Self::compute_palette_color(
+                    palette,
+                    palette_color,
+                    palette_index,
+                    color_index,
+                    self.color_correction,
+                );
 
                 if self.auto_increment_obj {
                     self.palette_address_obj = (self.palette_address_obj + 1) & 0x3f;
@@ -925,6 +1486,151 @@ impl Ppu {
         self.palette_obj_1
     }
 
+    pub fn palettes_color_bg(&self) -> &[Palette; 8] {
+        &self.palettes_color_bg
+    }
+
+    pub fn palettes_color_obj(&self) -> &[Palette; 8] {
+        &self.palettes_color_obj
+    }
+
+    pub fn obj_data(&self) -> &[ObjectData; OBJ_COUNT] {
+        &self.obj_data
+    }
+
+    /// Obtains the CGB attributes of the tiles in the selected
+    /// tile map (`false` for 0x9800 and `true` for 0x9C00).
+    pub fn bg_map_attrs(&self, map: bool) -> &[TileData; 1024] {
+        if map {
+            &self.bg_map_attrs_1
+        } else {
+            &self.bg_map_attrs_0
+        }
+    }
+
+    /// Renders the complete 256x256 tile map (`false` for 0x9800
+    /// and `true` for 0x9C00) into an RGB buffer, using the currently
+    /// selected tile data area and, in CGB mode, the tile attributes
+    /// (palette, VRAM bank and flips) of each of the tiles.
+    pub fn render_tile_map(&self, map: bool) -> Vec<u8> {
+        let mut buffer = vec![0u8; MAP_BUFFER_SIZE];
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                let (_, _, color) = self.map_pixel(map, x as u8, y as u8);
+                let offset = (y * MAP_WIDTH + x) * RGB_SIZE;
+                buffer[offset..offset + RGB_SIZE].copy_from_slice(&color);
+            }
+        }
+        buffer
+    }
+
+    /// Renders the object at the provided OAM index into an RGBA
+    /// buffer of 8x8 or 8x16 pixels (according to the current object
+    /// size), the transparent color zero is left fully transparent.
+    /// An empty buffer is returned for an index outside of the OAM.
+    pub fn render_object(&self, index: usize) -> Vec<u8> {
+        let obj = match self.obj_data.get(index) {
+            Some(obj) => obj,
+            None => return vec![],
+        };
+        let obj_height = if self.obj_size {
+            TILE_DOUBLE_HEIGHT
+        } else {
+            TILE_HEIGHT
+        };
+        let palette = self.object_palette(obj);
+
+        let mut buffer = vec![0u8; TILE_WIDTH * obj_height * RGBA_SIZE];
+        for y in 0..obj_height {
+            let row = self.object_row(obj, y);
+            for x in 0..TILE_WIDTH {
+                let pixel = row[if obj.xflip { TILE_WIDTH_I - x } else { x }];
+                if pixel == 0 {
+                    continue;
+                }
+                let offset = (y * TILE_WIDTH + x) * RGBA_SIZE;
+                buffer[offset..offset + RGB_SIZE].copy_from_slice(&palette[pixel as usize]);
+                buffer[offset + RGB_SIZE] = 0xff;
+            }
+        }
+
+        buffer
+    }
+
+    /// Obtains the color index (from 0 to 3), the BG-to-OAM priority
+    /// and the RGB color of the pixel at the given coordinates of the
+    /// selected tile map, using the currently selected tile data area
+    /// and (in CGB mode) the attributes of the tile.
+    fn map_pixel(&self, map: bool, x: u8, y: u8) -> (u8, bool, Pixel) {
+        let map_offset: usize = if map { 0x1c00 } else { 0x1800 };
+        let index = (y as usize >> 3) * 32 + (x as usize >> 3);
+
+        // resolves the tile index taking into account the signed
+        // addressing of the tile data set #1
+        let mut tile_index = self.vram[map_offset + index] as usize;
+        if !self.bg_tile && tile_index < 128 {
+            tile_index += 256;
+        }
+
+        let (tile_attr, palette) = if self.gb_mode == GameBoyMode::Cgb && !self.dmg_compat {
+            let tile_attr = &self.bg_map_attrs(map)[index];
+            (
+                tile_attr,
+                &self.palettes_color_bg[tile_attr.palette as usize],
+            )
+        } else {
+            (&DEFAULT_TILE_ATTR, &self.palette_bg)
+        };
+        tile_index += tile_attr.vram_bank as usize * TILE_COUNT_DMG;
+
+        let color = self.tiles[tile_index].get_flipped(
+            x as usize & 0x07,
+            y as usize & 0x07,
+            tile_attr.xflip,
+            tile_attr.yflip,
+        );
+        (color, tile_attr.priority, palette[color as usize])
+    }
+
+    /// Obtains the palette that is going to be used for the provided
+    /// object, according to the current (DMG or CGB) mode.
+    fn object_palette(&self, obj: &ObjectData) -> &Palette {
+        if self.gb_mode == GameBoyMode::Cgb && !self.dmg_compat {
+            &self.palettes_color_obj[obj.palette_cgb as usize]
+        } else if obj.palette == 0 {
+            &self.palette_obj_0
+        } else {
+            &self.palette_obj_1
+        }
+    }
+
+    /// Obtains the row of color indexes (from 0 to 3) of the provided
+    /// object at the given line (relative to the top of the object),
+    /// handling both the vertical flip and the 8x16 objects.
+    fn object_row(&self, obj: &ObjectData, line: usize) -> &[u8] {
+        let obj_height = if self.obj_size {
+            TILE_DOUBLE_HEIGHT
+        } else {
+            TILE_HEIGHT
+        };
+        let line = if obj.yflip {
+            obj_height - line - 1
+        } else {
+            line
+        };
+        let tile_bank_offset = if self.dmg_compat {
+            0
+        } else {
+            obj.tile_bank as usize * TILE_COUNT_DMG
+        };
+        let tile_index = if self.obj_size {
+            obj.tile as usize & 0xfe
+        } else {
+            obj.tile as usize
+        } + tile_bank_offset;
+        self.tiles[tile_index + line / TILE_HEIGHT].get_row(line % TILE_HEIGHT)
+    }
+
     pub fn ly(&self) -> u8 {
         self.ly
     }
@@ -937,62 +1643,167 @@ impl Ppu {
         self.frame_index
     }
 
-    #[inline(always)]
-    pub fn int_vblank(&self) -> bool {
-        self.int_vblank
+    pub fn first_frame(&self) -> bool {
+        self.first_frame
     }
 
-    #[inline(always)]
-    pub fn set_int_vblank(&mut self, value: bool) {
-        self.int_vblank = value;
+    pub fn switch_lcd(&self) -> bool {
+        self.switch_lcd
     }
 
-    #[inline(always)]
-    pub fn ack_vblank(&mut self) {
-        self.set_int_vblank(false);
+    /// Obtains the length in dots of the pixel transfer (mode 3)
+    /// of the current (or last) line.
+    pub fn vram_length(&self) -> u16 {
+        self.vram_length
     }
 
-    #[inline(always)]
-    pub fn int_stat(&self) -> bool {
-        self.int_stat
+    pub fn layer_bg(&self) -> bool {
+        self.layer_bg
     }
 
-    #[inline(always)]
-    pub fn set_int_stat(&mut self, value: bool) {
-        self.int_stat = value;
+    pub fn set_layer_bg(&mut self, value: bool) {
+        self.layer_bg = value;
     }
 
-    #[inline(always)]
-    pub fn ack_stat(&mut self) {
-        self.set_int_stat(false);
+    pub fn layer_window(&self) -> bool {
+        self.layer_window
     }
 
-    pub fn dmg_compat(&self) -> bool {
-        self.dmg_compat
+    pub fn set_layer_window(&mut self, value: bool) {
+        self.layer_window = value;
     }
 
-    pub fn set_dmg_compat(&mut self, value: bool) {
-        self.dmg_compat = value;
+    pub fn layer_obj(&self) -> bool {
+        self.layer_obj
+    }
 
-        // if we're switching to the DMG compat mode
-        // then we need to recompute the palettes so
-        // that the colors are correct according to
-        // the compat palettes set by the Boot ROM
-        if value {
-            self.compute_palettes();
-        }
+    pub fn set_layer_obj(&mut self, value: bool) {
+        self.layer_obj = value;
     }
 
-    pub fn gb_mode(&self) -> GameBoyMode {
-        self.gb_mode
+    pub fn debug_window(&self) -> bool {
+        self.debug_window
     }
 
-    pub fn set_gb_mode(&mut self, value: GameBoyMode) {
-        self.gb_mode = value;
+    pub fn set_debug_window(&mut self, value: bool) {
+        self.debug_window = value;
     }
 
-    pub fn set_gbc(&mut self, value: Rc<RefCell<GameBoyConfig>>) {
-        self.gbc = value;
+    pub fn debug_objects(&self) -> bool {
+        self.debug_objects
+    }
+
+    pub fn set_debug_objects(&mut self, value: bool) {
+        self.debug_objects = value;
+    }
+
+    pub fn debug_obj_limit(&self) -> bool {
+        self.debug_obj_limit
+    }
+
+    pub fn set_debug_obj_limit(&mut self, value: bool) {
+        self.debug_obj_limit = value;
+    }
+
+    pub fn unlimited_objects(&self) -> bool {
+        self.unlimited_objects
+    }
+
+    pub fn set_unlimited_objects(&mut self, value: bool) {
+        self.unlimited_objects = value;
+    }
+
+    pub fn widescreen(&self) -> bool {
+        self.widescreen
+    }
+
+    pub fn set_widescreen(&mut self, value: bool) {
+        self.widescreen = value;
+    }
+
+    pub fn color_correction(&self) -> ColorCorrection {
+        self.color_correction
+    }
+
+    pub fn set_color_correction(&mut self, value: ColorCorrection) {
+        self.color_correction = value;
+        self.compute_palettes_color();
+    }
+
+    pub fn frame_blending(&self) -> bool {
+        self.frame_blending
+    }
+
+    pub fn set_frame_blending(&mut self, value: bool) {
+        self.frame_blending = value;
+        self.frame_buffer_last
+            .copy_from_slice(self.frame_buffer.as_ref());
+    }
+
+    pub fn renderer(&self) -> PpuRenderer {
+        self.renderer
+    }
+
+    pub fn set_renderer(&mut self, value: PpuRenderer) {
+        self.renderer = value;
+    }
+
+    #[inline(always)]
+    pub fn int_vblank(&self) -> bool {
+        self.int_vblank
+    }
+
+    #[inline(always)]
+    pub fn set_int_vblank(&mut self, value: bool) {
+        self.int_vblank = value;
+    }
+
+    #[inline(always)]
+    pub fn ack_vblank(&mut self) {
+        self.set_int_vblank(false);
+    }
+
+    #[inline(always)]
+    pub fn int_stat(&self) -> bool {
+        self.int_stat
+    }
+
+    #[inline(always)]
+    pub fn set_int_stat(&mut self, value: bool) {
+        self.int_stat = value;
+    }
+
+    #[inline(always)]
+    pub fn ack_stat(&mut self) {
+        self.set_int_stat(false);
+    }
+
+    pub fn dmg_compat(&self) -> bool {
+        self.dmg_compat
+    }
+
+    pub fn set_dmg_compat(&mut self, value: bool) {
+        self.dmg_compat = value;
+
+        // if we're switching to the DMG compat mode
+        // then we need to recompute the palettes so
+        // that the colors are correct according to
+        // the compat palettes set by the Boot ROM
+        if value {
+            self.compute_palettes();
+        }
+    }
+
+    pub fn gb_mode(&self) -> GameBoyMode {
+        self.gb_mode
+    }
+
+    pub fn set_gb_mode(&mut self, value: GameBoyMode) {
+        self.gb_mode = value;
+    }
+
+    pub fn set_gbc(&mut self, value: SharedThread<GameBoyConfig>) {
+        self.gbc = value;
     }
 
     /// Fills the frame buffer with pixels of the provided color,
@@ -1005,6 +1816,9 @@ impl Ppu {
             self.frame_buffer[index + 1] = color[1];
             self.frame_buffer[index + 2] = color[2];
         }
+        for index in (0..self.frame_buffer_wide.len()).step_by(RGB_SIZE) {
+            self.frame_buffer_wide[index..index + RGB_SIZE].copy_from_slice(&color);
+        }
     }
 
     /// Clears the current frame buffer, setting the background color
@@ -1013,6 +1827,17 @@ impl Ppu {
         self.fill_frame_buffer(self.palette_colors[0]);
     }
 
+    /// Fills the frame buffer with the blank (white) screen
+    /// that is displayed while the LCD is off, in CGB mode
+    /// this is pure white instead of the palette's lightest.
+    pub fn clear_lcd(&mut self) {
+        if self.gb_mode == GameBoyMode::Cgb && !self.dmg_compat {
+            self.fill_frame_buffer([0xff, 0xff, 0xff]);
+        } else {
+            self.clear_frame_buffer();
+        }
+    }
+
     /// Prints the tile data information to the stdout, this is
     /// useful for debugging purposes.
     pub fn print_tile_stdout(&self, tile_index: usize) {
@@ -1086,6 +1911,35 @@ impl Ppu {
         tile_data.priority = value & 0x80 == 0x80;
     }
 
+    /// Re-builds the tiles, objects, background map attributes and
+    /// palettes from the VRAM, OAM and palette registers, used after
+    /// the loading of a save state.
+    fn update_derived(&mut self) {
+        let (vram_bank, vram_offset) = (self.vram_bank, self.vram_offset);
+        for bank in 0..(VRAM_SIZE / VRAM_SIZE_DMG) as u8 {
+            self.vram_bank = bank;
+            self.vram_offset = bank as u16 * 0x2000;
+            for addr in (0x8000..0x9800).step_by(2) {
+                self.update_tile(addr, 0x00);
+            }
+            if bank == 0x1 {
+                for addr in 0x9800..0xa000 {
+                    let value = self.vram[(self.vram_offset + (addr & 0x1fff)) as usize];
+                    self.update_bg_map_attrs(addr, value);
+                }
+            }
+        }
+        self.vram_bank = vram_bank;
+        self.vram_offset = vram_offset;
+
+        for index in 0..OBJ_COUNT * 4 {
+            self.update_object(0xfe00 + index as u16, self.oam[index]);
+        }
+
+        self.compute_palettes_color();
+        self.compute_palettes();
+    }
+
     pub fn registers(&self) -> PpuRegisters {
         PpuRegisters {
             scy: self.scy,
@@ -1097,6 +1951,395 @@ impl Ppu {
         }
     }
 
+    /// Starts the pixel transfer of the current line for the
+    /// FIFO based renderer, resetting the FIFO state and running
+    /// the OAM scan that selects the objects of the line.
+    fn fifo_start(&mut self) {
+        self.fifo.reset();
+
+        // the initial tile fetch is discarded by the hardware and
+        // the fine scroll pixels are shifted out of the FIFO
+        self.fifo.delay = 6;
+        self.fifo.discard = self.scx & 0x07;
+
+        let limit = if self.unlimited_objects {
+            OBJ_COUNT
+        } else {
+            OBJ_LINE_COUNT
+        };
+        let (objects, obj_count) = self.line_objects(limit);
+        self.fifo.objects = objects;
+        self.fifo.obj_count = obj_count;
+    }
+
+    /// Runs the OAM scan for the current line, selecting (in OAM
+    /// order) the first `limit` objects that are contained in the
+    /// line, the limit is applied even to objects that are off-screen.
+    fn line_objects(&self, limit: usize) -> ([usize; OBJ_COUNT], usize) {
+        let mut objects = [0usize; OBJ_COUNT];
+        let mut count = 0;
+        let obj_height = if self.obj_size {
+            TILE_DOUBLE_HEIGHT
+        } else {
+            TILE_HEIGHT
+        };
+        for (index, obj) in self.obj_data.iter().enumerate() {
+            if count == limit {
+                break;
+            }
+            let is_contained =
+                (obj.y <= self.ly as i16) && ((obj.y + obj_height as i16) > self.ly as i16);
+            if is_contained {
+                objects[count] = index;
+                count += 1;
+            }
+        }
+        (objects, count)
+    }
+
+    /// Computes the length in dots of the pixel transfer (mode 3)
+    /// for the current line, used by the line renderer. The base
+    /// 172 dots are extended by the SCX fine scroll, the window
+    /// activation and the fetch of each of the objects in the line.
+    fn compute_vram_length(&self) -> u16 {
+        let mut length = 172 + (self.scx & 0x07) as u16;
+
+        if self.switch_window && self.ly >= self.wy && self.wx < 167 {
+            length += 6;
+        }
+
+        if self.switch_obj {
+            let (objects, obj_count) = self.line_objects(OBJ_LINE_COUNT);
+            let mut considered = 0u32;
+            for index in objects.iter().take(obj_count) {
+                let x = self.obj_data[*index].x + TILE_WIDTH as i16;
+                if x >= DISPLAY_WIDTH as i16 + TILE_WIDTH as i16 {
+                    continue;
+                }
+                if x == 0 {
+                    length += 11;
+                    continue;
+                }
+
+                // the first object in each background tile waits for the
+                // fetch of that tile, taking longer when the object is
+                // placed in the left pixels of the tile
+                let position = x as u16 + (self.scx & 0x07) as u16;
+                let tile = position >> 3;
+                if considered & (1 << tile) == 0 {
+                    length += 5 - (position & 0x07).min(5);
+                    considered |= 1 << tile;
+                }
+                length += 6;
+            }
+        }
+
+        length.min(289)
+    }
+
+    /// Runs a single dot of the FIFO based renderer, shifting
+    /// at most one pixel out to the screen and advancing the
+    /// background/window fetcher.
+    fn fifo_dot(&mut self) {
+        self.fifo.dots += 1;
+
+        if self.fifo.delay > 0 {
+            self.fifo.delay -= 1;
+            return;
+        }
+
+        // while an object is being fetched both the pixel output
+        // and the background fetcher are stalled
+        if self.fifo.obj_stall > 0 {
+            // the object fetch only starts after the background
+            // fetcher finishes its current tile fetch
+            if !self.fifo.row_ready {
+                self.fifo_fetch();
+                return;
+            }
+            self.fifo.obj_stall -= 1;
+            if self.fifo.obj_stall == 0 {
+                self.fifo_fetch_object(self.fifo.obj_pending);
+            }
+            return;
+        }
+
+        if !self.fifo.bg.is_empty() {
+            if self.fifo.discard > 0 {
+                self.fifo.bg.pop_front();
+                self.fifo.discard -= 1;
+            } else if self.fifo_window_trigger() {
+                // the background FIFO is cleared and the fetcher is
+                // restarted, now fetching tiles from the window map
+                self.fifo.bg.clear();
+                self.fifo.window = true;
+                self.fifo.row_ready = false;
+                self.fifo.fetch_step = 0;
+                self.fifo.fetch_x = 0;
+                if self.wx < 7 {
+                    self.fifo.discard = 7 - self.wx;
+                }
+            } else if let Some(index) = self.fifo_object_trigger() {
+                self.fifo.obj_pending = index;
+                self.fifo.obj_stall = 6;
+                return;
+            } else {
+                let bg = self.fifo.bg.pop_front().unwrap();
+                let obj = self.fifo.obj.pop_front();
+                self.fifo_output(bg, obj);
+                self.fifo.lx += 1;
+            }
+        }
+
+        self.fifo_fetch();
+
+        if self.fifo.row_ready && self.fifo.bg.is_empty() {
+            self.fifo.bg.extend(self.fifo.row.iter());
+            self.fifo.row_ready = false;
+            self.fifo.fetch_x = self.fifo.fetch_x.wrapping_add(1);
+        }
+    }
+
+    /// Advances the background/window fetcher by one dot, the tile
+    /// row becomes ready after the 6 dots of the fetch.
+    fn fifo_fetch(&mut self) {
+        if self.fifo.row_ready {
+            return;
+        }
+        self.fifo.fetch_step += 1;
+        if self.fifo.fetch_step == 6 {
+            self.fifo.row = self.fifo_fetch_row();
+            self.fifo.row_ready = true;
+            self.fifo.fetch_step = 0;
+        }
+    }
+
+    /// Determines if the window should start being drawn at
+    /// the current pixel, using the live WX and LCDC values.
+    fn fifo_window_trigger(&self) -> bool {
+        if self.fifo.window || !self.switch_window || self.ly < self.wy {
+            return false;
+        }
+        if self.wx < 7 {
+            self.fifo.lx == 0
+        } else {
+            self.fifo.lx as u16 + 7 == self.wx as u16
+        }
+    }
+
+    /// Obtains the OAM index of the next object that should be
+    /// fetched at the current pixel, marking it as fetched.
+    fn fifo_object_trigger(&mut self) -> Option<usize> {
+        if !self.switch_obj {
+            return None;
+        }
+        for index in 0..self.fifo.obj_count {
+            if self.fifo.obj_fetched[index] {
+                continue;
+            }
+            let obj_index = self.fifo.objects[index];
+            let obj = &self.obj_data[obj_index];
+            if obj.x + TILE_WIDTH as i16 > 0 && max(obj.x, 0) == self.fifo.lx as i16 {
+                self.fifo.obj_fetched[index] = true;
+
+                // the objects beyond the hardware limit (only selected
+                // with unlimited objects) are fetched right away, with
+                // no stall, keeping the original pixel transfer timing
+                if index >= OBJ_LINE_COUNT {
+                    self.fifo_fetch_object(obj_index);
+                    continue;
+                }
+                return Some(obj_index);
+            }
+        }
+        None
+    }
+
+    /// Fetches the row of the background or window tile that is
+    /// next in line, using the live scroll and LCDC values.
+    fn fifo_fetch_row(&self) -> [FifoPixel; TILE_WIDTH] {
+        let (map, y, column) = if self.fifo.window {
+            (
+                self.window_map,
+                self.window_counter as usize,
+                self.fifo.fetch_x as usize % 32,
+            )
+        } else {
+            (
+                self.bg_map,
+                (self.ly as usize + self.scy as usize) & 0xff,
+                ((self.scx >> 3) as usize + self.fifo.fetch_x as usize) % 32,
+            )
+        };
+
+        let map_offset: usize = if map { 0x1c00 } else { 0x1800 };
+        let tile_offset = ((y >> 3) % 32) * 32 + column;
+
+        let mut tile_index = self.vram[map_offset + tile_offset] as usize;
+        if !self.bg_tile && tile_index < 128 {
+            tile_index += 256;
+        }
+
+        let tile_attr = if self.gb_mode == GameBoyMode::Cgb && !self.dmg_compat {
+            if map {
+                &self.bg_map_attrs_1[tile_offset]
+            } else {
+                &self.bg_map_attrs_0[tile_offset]
+            }
+        } else {
+            &DEFAULT_TILE_ATTR
+        };
+        tile_index += tile_attr.vram_bank as usize * TILE_COUNT_DMG;
+
+        let tile = &self.tiles[tile_index];
+        let mut row = [FifoPixel::default(); TILE_WIDTH];
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = FifoPixel {
+                color: tile.get_flipped(x, y & 0x07, tile_attr.xflip, tile_attr.yflip),
+                palette: tile_attr.palette,
+                priority: tile_attr.priority,
+                index: 0,
+                window: self.fifo.window,
+            };
+        }
+        row
+    }
+
+    /// Fetches the row of the object with the provided OAM index
+    /// for the current line, merging it into the object FIFO.
+    fn fifo_fetch_object(&mut self, index: usize) {
+        let obj = self.obj_data[index];
+        let cgb = self.gb_mode == GameBoyMode::Cgb && !self.dmg_compat;
+        let obj_priority_mode = self.gb_mode != GameBoyMode::Cgb || self.obj_priority;
+
+        let obj_height = if self.obj_size {
+            TILE_DOUBLE_HEIGHT
+        } else {
+            TILE_HEIGHT
+        };
+
+        let mut tile_offset = self.ly as i16 - obj.y;
+        if obj.yflip {
+            tile_offset = obj_height as i16 - tile_offset - 1;
+        }
+
+        let tile_bank_offset = if self.dmg_compat {
+            0
+        } else {
+            obj.tile_bank as usize * TILE_COUNT_DMG
+        };
+
+        let tile_index = if self.obj_size {
+            if tile_offset < 8 {
+                (obj.tile as usize & 0xfe) + tile_bank_offset
+            } else {
+                tile_offset -= 8;
+                (obj.tile as usize | 0x01) + tile_bank_offset
+            }
+        } else {
+            obj.tile as usize + tile_bank_offset
+        };
+        let tile_row = self.tiles[tile_index].get_row(tile_offset as usize);
+
+        // the pixels of the object that are before the current
+        // pixel (left side off-screen) are skipped
+        let skip = (self.fifo.lx as i16 - obj.x) as usize;
+
+        while self.fifo.obj.len() < TILE_WIDTH {
+            self.fifo.obj.push_back(FifoPixel::default());
+        }
+
+        for tile_x in skip..TILE_WIDTH {
+            let color = tile_row[if obj.xflip {
+                TILE_WIDTH_I - tile_x
+            } else {
+                tile_x
+            }];
+            if color == 0 {
+                continue;
+            }
+
+            // an object pixel only replaces the one already in the
+            // FIFO if that one is transparent or, in the CGB OAM
+            // priority mode, if the object has a lower OAM index
+            let slot = &mut self.fifo.obj[tile_x - skip];
+            if slot.color != 0 && (obj_priority_mode || slot.index < obj.index) {
+                continue;
+            }
+            *slot = FifoPixel {
+                color,
+                palette: if cgb { obj.palette_cgb } else { obj.palette },
+                priority: obj.bg_over,
+                index: obj.index,
+                window: false,
+            };
+        }
+    }
+
+    /// Mixes the provided background and object pixels and writes
+    /// the resulting pixel to the screen, using the live palettes.
+    fn fifo_output(&mut self, bg: FifoPixel, obj: Option<FifoPixel>) {
+        if self.first_frame {
+            return;
+        }
+
+        let cgb = self.gb_mode == GameBoyMode::Cgb && !self.dmg_compat;
+
+        // the pixels of hidden layers (debug) are handled
+        // as transparent (color zero) background pixels
+        let layer = if bg.window {
+            self.layer_window
+        } else {
+            self.layer_bg
+        };
+        let bg = if layer { bg } else { FifoPixel::default() };
+
+        // in DMG (and DMG compatibility) the LCDC bit 0 disables both
+        // the background and the window, in CGB it controls the master
+        // priority, meaning that objects are always placed over
+        let bg_enabled = cgb || self.switch_bg;
+        let always_over = cgb && !self.switch_bg;
+
+        let bg_color = if bg_enabled { bg.color } else { 0 };
+        let mut pixel = bg_color;
+        let mut color = if !bg_enabled {
+            if self.dmg_compat {
+                self.palettes_color_bg[0][0]
+            } else {
+                self.palette_colors[0]
+            }
+        } else if cgb {
+            self.palettes_color_bg[bg.palette as usize][bg_color as usize]
+        } else {
+            self.palette_bg[bg_color as usize]
+        };
+
+        if let Some(obj) = obj {
+            let is_visible = self.switch_obj
+                && self.layer_obj
+                && obj.color != 0
+                && (always_over || bg_color == 0 || !obj.priority && !bg.priority);
+            if is_visible {
+                pixel = obj.color;
+                color = if cgb {
+                    self.palettes_color_obj[obj.palette as usize][obj.color as usize]
+                } else if obj.palette == 0 {
+                    self.palette_obj_0[obj.color as usize]
+                } else {
+                    self.palette_obj_1[obj.color as usize]
+                };
+            }
+        }
+
+        let color_offset = self.ly as usize * DISPLAY_WIDTH + self.fifo.lx as usize;
+        let frame_offset = color_offset * RGB_SIZE;
+        self.color_buffer[color_offset] = pixel;
+        self.priority_buffer[color_offset] = bg.priority && bg_color != 0;
+        self.frame_buffer[frame_offset] = color[0];
+        self.frame_buffer[frame_offset + 1] = color[1];
+        self.frame_buffer[frame_offset + 2] = color[2];
+    }
+
     fn render_line(&mut self) {
         if self.gb_mode == GameBoyMode::Dmg {
             self.render_line_dmg();
@@ -1109,13 +2352,16 @@ impl Ppu {
         if self.first_frame {
             return;
         }
-        if self.switch_bg {
+        if self.switch_bg && !self.layer_bg {
+            self.clear_line();
+        }
+        if self.switch_bg && self.layer_bg {
             self.render_map_dmg(self.bg_map, self.scx, self.scy, 0, 0, self.ly);
         }
-        if self.switch_bg && self.switch_window {
+        if self.switch_bg && self.switch_window && self.layer_window {
             self.render_map_dmg(self.window_map, 0, 0, self.wx, self.wy, self.window_counter);
         }
-        if self.switch_obj {
+        if self.switch_obj && self.layer_obj {
             self.render_objects();
         }
     }
@@ -1125,17 +2371,234 @@ impl Ppu {
             return;
         }
         let switch_bg_window = (self.gb_mode.is_cgb() && !self.dmg_compat) || self.switch_bg;
-        if switch_bg_window {
+        if switch_bg_window && !self.layer_bg {
+            self.clear_line();
+        }
+        if switch_bg_window && self.layer_bg {
             self.render_map(self.bg_map, self.scx, self.scy, 0, 0, self.ly);
         }
-        if switch_bg_window && self.switch_window {
+        if switch_bg_window && self.switch_window && self.layer_window {
             self.render_map(self.window_map, 0, 0, self.wx, self.wy, self.window_counter);
         }
-        if self.switch_obj {
+        if self.switch_obj && self.layer_obj {
             self.render_objects();
         }
     }
 
+    /// Clears the current line with the background color zero,
+    /// used when the background layer is hidden (debug).
+    fn clear_line(&mut self) {
+        let color = if self.gb_mode == GameBoyMode::Cgb && !self.dmg_compat {
+            self.palettes_color_bg[0][0]
+        } else {
+            self.palette_bg[0]
+        };
+        let color_offset = self.ly as usize * DISPLAY_WIDTH;
+        self.color_buffer[color_offset..color_offset + DISPLAY_WIDTH].fill(0);
+        self.priority_buffer[color_offset..color_offset + DISPLAY_WIDTH].fill(false);
+        for x in 0..DISPLAY_WIDTH {
+            let frame_offset = (color_offset + x) * RGB_SIZE;
+            self.frame_buffer[frame_offset..frame_offset + RGB_SIZE].copy_from_slice(&color);
+        }
+    }
+
+    /// Draws the debug overlays (window region, object bounding
+    /// boxes and dropped objects) over the current line, these
+    /// only change the frame buffer and not the color buffer.
+    fn render_debug(&mut self) {
+        if self.first_frame || !(self.debug_window || self.debug_objects || self.debug_obj_limit) {
+            return;
+        }
+
+        let line_offset = self.ly as usize * DISPLAY_WIDTH;
+
+        if self.debug_window && self.switch_window && self.ly >= self.wy && self.wx < 167 {
+            for x in max(self.wx as i16 - 7, 0) as usize..DISPLAY_WIDTH {
+                let frame_offset = (line_offset + x) * RGB_SIZE;
+                for (index, value) in DEBUG_WINDOW_COLOR.iter().enumerate() {
+                    let pixel = &mut self.frame_buffer[frame_offset + index];
+                    *pixel = ((*pixel as u16 + *value as u16) / 2) as u8;
+                }
+            }
+        }
+
+        if !(self.debug_objects || self.debug_obj_limit) {
+            return;
+        }
+
+        let obj_height = if self.obj_size {
+            TILE_DOUBLE_HEIGHT
+        } else {
+            TILE_HEIGHT
+        } as i16;
+        let mut count = 0;
+        let mut dropped = false;
+
+        for index in 0..OBJ_COUNT {
+            let obj = self.obj_data[index];
+            let is_contained = (obj.y <= self.ly as i16) && ((obj.y + obj_height) > self.ly as i16);
+            if !is_contained {
+                continue;
+            }
+
+            // the objects after the first 10 in the line are not
+            // drawn by the PPU (10 objects per line limit)
+            let is_dropped = count == OBJ_LINE_COUNT && !self.unlimited_objects;
+            if !is_dropped {
+                count += 1;
+            }
+            dropped |= is_dropped;
+
+            let color = if is_dropped {
+                if !self.debug_obj_limit {
+                    continue;
+                }
+                DEBUG_OBJ_DROP_COLOR
+            } else {
+                if !self.debug_objects {
+                    continue;
+                }
+                DEBUG_OBJ_COLOR
+            };
+
+            // the top and bottom lines of the box are complete, the
+            // other lines only have the left and right pixels
+            let is_edge = obj.y == self.ly as i16 || obj.y + obj_height - 1 == self.ly as i16;
+            for tile_x in 0..TILE_WIDTH as i16 {
+                if !is_edge && tile_x != 0 && tile_x != TILE_WIDTH_I as i16 {
+                    continue;
+                }
+                let x = obj.x + tile_x;
+                if x < 0 || x >= DISPLAY_WIDTH as i16 {
+                    continue;
+                }
+                let frame_offset = (line_offset + x as usize) * RGB_SIZE;
+                self.frame_buffer[frame_offset..frame_offset + RGB_SIZE].copy_from_slice(&color);
+            }
+        }
+
+        // marks the lines where objects have been dropped with
+        // a small indicator at the right edge of the screen
+        if self.debug_obj_limit && dropped {
+            for x in DISPLAY_WIDTH - 4..DISPLAY_WIDTH {
+                let frame_offset = (line_offset + x) * RGB_SIZE;
+                self.frame_buffer[frame_offset..frame_offset + RGB_SIZE]
+                    .copy_from_slice(&DEBUG_OBJ_DROP_COLOR);
+            }
+        }
+    }
+
+    /// Mixes the current line of the frame buffer with the same
+    /// line of the previous frame (50% each), emulating the slow
+    /// response of the LCD. The first frame after the LCD is
+    /// enabled is not blended, as there's no previous frame.
+    fn blend_line(&mut self) {
+        let offset = self.ly as usize * DISPLAY_WIDTH * RGB_SIZE;
+        if self.first_frame {
+            self.frame_buffer_last[offset..offset + DISPLAY_WIDTH * RGB_SIZE]
+                .copy_from_slice(&self.frame_buffer[offset..offset + DISPLAY_WIDTH * RGB_SIZE]);
+            return;
+        }
+        for index in offset..offset + DISPLAY_WIDTH * RGB_SIZE {
+            let value = self.frame_buffer[index];
+            self.frame_buffer[index] =
+                ((value as u16 + self.frame_buffer_last[index] as u16) / 2) as u8;
+            self.frame_buffer_last[index] = value;
+        }
+    }
+
+    /// Renders the current line into the widescreen frame buffer,
+    /// copying the visible line into its center and drawing the
+    /// background and the objects over the left and right margins,
+    /// notice that the window is not extended into the margins.
+    fn render_wide_line(&mut self) {
+        let line_offset = self.ly as usize * WIDE_DISPLAY_WIDTH;
+        let frame_offset = self.ly as usize * DISPLAY_WIDTH * RGB_SIZE;
+        let center_offset = (line_offset + WIDE_MARGIN) * RGB_SIZE;
+        self.frame_buffer_wide[center_offset..center_offset + DISPLAY_WIDTH * RGB_SIZE]
+            .copy_from_slice(
+                &self.frame_buffer[frame_offset..frame_offset + DISPLAY_WIDTH * RGB_SIZE],
+            );
+
+        // in CGB mode the background is always drawn as the
+        // LCDC bit 0 controls the master priority instead
+        let is_cgb = self.gb_mode == GameBoyMode::Cgb && !self.dmg_compat;
+        let is_bg = (is_cgb || self.switch_bg) && self.layer_bg;
+        let blank = if is_cgb {
+            self.palettes_color_bg[0][0]
+        } else {
+            self.palette_bg[0]
+        };
+
+        let y = self.ly.wrapping_add(self.scy);
+        let mut colors = [0u8; WIDE_DISPLAY_WIDTH];
+        let mut priorities = [false; WIDE_DISPLAY_WIDTH];
+
+        for wide_x in (0..WIDE_MARGIN).chain(WIDE_MARGIN + DISPLAY_WIDTH..WIDE_DISPLAY_WIDTH) {
+            let color = if is_bg {
+                let x = (self.scx as usize + wide_x + MAP_WIDTH - WIDE_MARGIN) as u8;
+                let (index, priority, color) = self.map_pixel(self.bg_map, x, y);
+                colors[wide_x] = index;
+                priorities[wide_x] = priority;
+                color
+            } else {
+                blank
+            };
+            let offset = (line_offset + wide_x) * RGB_SIZE;
+            self.frame_buffer_wide[offset..offset + RGB_SIZE].copy_from_slice(&color);
+        }
+
+        if !self.switch_obj || !self.layer_obj {
+            return;
+        }
+
+        let always_over = is_cgb && !self.switch_bg;
+        let limit = if self.unlimited_objects {
+            OBJ_COUNT
+        } else {
+            OBJ_LINE_COUNT
+        };
+        let (objects, obj_count) = self.line_objects(limit);
+
+        // draws the objects in reverse OAM order so that the ones
+        // with a lower index end up over the other ones
+        for index in objects.iter().take(obj_count).rev() {
+            let obj = self.obj_data[*index];
+            let palette = *self.object_palette(&obj);
+            let mut row = [0u8; TILE_WIDTH];
+            row.copy_from_slice(self.object_row(&obj, (self.ly as i16 - obj.y) as usize));
+
+            for tile_x in 0..TILE_WIDTH {
+                // the pixels inside the viewport have already been
+                // drawn by the regular renderer, skipping them
+                let x = obj.x + tile_x as i16;
+                if x >= 0 && x < DISPLAY_WIDTH as i16 {
+                    continue;
+                }
+                let wide_x = x + WIDE_MARGIN as i16;
+                if wide_x < 0 || wide_x >= WIDE_DISPLAY_WIDTH as i16 {
+                    continue;
+                }
+                let wide_x = wide_x as usize;
+
+                let pixel = row[if obj.xflip {
+                    TILE_WIDTH_I - tile_x
+                } else {
+                    tile_x
+                }];
+                let is_visible =
+                    always_over || colors[wide_x] == 0 || !obj.bg_over && !priorities[wide_x];
+                if pixel == 0 || !is_visible {
+                    continue;
+                }
+
+                let offset = (line_offset + wide_x) * RGB_SIZE;
+                self.frame_buffer_wide[offset..offset + RGB_SIZE]
+                    .copy_from_slice(&palette[pixel as usize]);
+            }
+        }
+    }
+
     fn render_map(&mut self, map: bool, scx: u8, scy: u8, wx: u8, wy: u8, ld: u8) {
         // in case the target window Y position has not yet been reached
         // then there's nothing to be done, returns control flow immediately
@@ -1446,7 +2909,7 @@ impl Ppu {
         for index in 0..OBJ_COUNT {
             // in case the limit on the number of objects to be draw per
             // line has been reached breaks the loop avoiding more draws
-            if draw_count == 10 {
+            if draw_count == OBJ_LINE_COUNT as u8 && !self.unlimited_objects {
                 break;
             }
 
@@ -1618,16 +3081,35 @@ impl Ppu {
     /// that the flag that control will be updated in case the conditions
     /// required for the LCD STAT interrupt to be triggered are met.
     fn update_stat(&mut self) {
-        self.int_stat = self.stat_level();
+        // the interrupt is only requested on the rising edge of
+        // the STAT line, meaning that consecutive sources block
+        // each other (STAT blocking)
+        let level = self.switch_lcd && self.stat_level();
+        if level && !self.stat_line {
+            self.int_stat = true;
+        }
+        self.stat_line = level;
     }
 
     /// Obtains the current level of the LCD STAT interrupt by
     /// checking the current PPU state in various sections.
     fn stat_level(&self) -> bool {
+        let mode = self.stat_mode();
         self.stat_lyc && self.lyc == self.ly
-            || self.stat_oam && self.mode == PpuMode::OamRead
-            || self.stat_vblank && self.mode == PpuMode::VBlank
-            || self.stat_hblank && self.mode == PpuMode::HBlank
+            || self.stat_oam && mode == PpuMode::OamRead
+            || self.stat_vblank && mode == PpuMode::VBlank
+            || self.stat_hblank && mode == PpuMode::HBlank
+    }
+
+    /// Obtains the mode reported in the STAT register, that is
+    /// mode 0 during the OAM scan of the first line after
+    /// the LCD has been enabled.
+    fn stat_mode(&self) -> PpuMode {
+        if self.first_line && self.mode == PpuMode::OamRead {
+            PpuMode::HBlank
+        } else {
+            self.mode
+        }
     }
 
     /// Computes the values for all of the palettes, this method
@@ -1685,6 +3167,36 @@ impl Ppu {
         }
     }
 
+    /// Re-computes the RGB888 colors of all the CGB palettes from
+    /// the raw RGB555 values, required whenever the color correction
+    /// curve is changed.
+    fn compute_palettes_color(&mut self) {
+        for palette_index in 0..8u8 {
+            for color_index in 0..4u8 {
+                Self::compute_palette_color(
+                    &mut self.palettes_color_bg[palette_index as usize],
+                    &self.palettes_color[0],
+                    palette_index,
+                    color_index,
+                    self.color_correction,
+                );
+                Self::compute_palette_color(
+                    &mut self.palettes_color_obj[palette_index as usize],
+                    &self.palettes_color[1],
+                    palette_index,
+                    color_index,
+                    self.color_correction,
+                );
+            }
+        }
+
+        // the DMG compatibility palettes are derived from the
+        // CGB ones, so they must be re-computed as well
+        if self.dmg_compat {
+            self.compute_palettes();
+        }
+    }
+
     /// Static method that computes an RGB888 color palette ready to
     /// be used for frame buffer operations from 4 (colors) x 2 bytes (RGB555)
     /// that represent an RGB555 set of colors. This method should be
@@ -1694,20 +3206,162 @@ impl Ppu {
         palette_color: &[u8; 64],
         palette_index: u8,
         color_index: u8,
+        correction: ColorCorrection,
     ) {
         let palette_offset = (palette_index * 4 * 2) as usize;
         let color_offset = (color_index * 2) as usize;
         palette[color_index as usize] = Self::rgb555_to_rgb888(
             palette_color[palette_offset + color_offset],
             palette_color[palette_offset + color_offset + 1],
+            correction,
         );
     }
 
-    fn rgb555_to_rgb888(first: u8, second: u8) -> Pixel {
-        let r = (first & 0x1f) << 3;
-        let g = (((first & 0xe0) >> 5) | ((second & 0x03) << 3)) << 3;
-        let b = ((second & 0x7c) >> 2) << 3;
-        [r, g, b]
+    fn rgb555_to_rgb888(first: u8, second: u8, correction: ColorCorrection) -> Pixel {
+        let r = first & 0x1f;
+        let g = ((first & 0xe0) >> 5) | ((second & 0x03) << 3);
+        let b = (second & 0x7c) >> 2;
+        match correction {
+            ColorCorrection::None => [r << 3, g << 3, b << 3],
+            ColorCorrection::Gbc => {
+                let (r, g, b) = (r as u16, g as u16, b as u16);
+                [
+                    ((r * 13 + g * 2 + b) >> 1) as u8,
+                    ((g * 3 + b) << 1) as u8,
+                    ((r * 3 + g * 2 + b * 11) >> 1) as u8,
+                ]
+            }
+            ColorCorrection::Gba => {
+                // linearizes the colors using the gamma of the GBA
+                // LCD, mixes the channels and re-applies the gamma
+                // of a regular display (sRGB), each row of the mix
+                // matrix is normalized so that white remains white
+                let lr = (r as f32 / 31.0).powf(4.0);
+                let lg = (g as f32 / 31.0).powf(4.0);
+                let lb = (b as f32 / 31.0).powf(4.0);
+                let mix = |wr: f32, wg: f32, wb: f32| {
+                    let value = (wr * lr + wg * lg + wb * lb) / (wr + wg + wb);
+                    (value.powf(1.0 / 2.2) * 255.0).round() as u8
+                };
+                [
+                    mix(255.0, 50.0, 0.0),
+                    mix(10.0, 230.0, 30.0),
+                    mix(50.0, 10.0, 220.0),
+                ]
+            }
+        }
+    }
+}
+
+impl StateComponent for FifoPixel {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.u8(&mut self.color);
+        s.u8(&mut self.palette);
+        s.bool(&mut self.priority);
+        s.u8(&mut self.index);
+        s.bool(&mut self.window);
+    }
+}
+
+impl PixelFifo {
+    fn serialize_pixels(pixels: &mut VecDeque<FifoPixel>, s: &mut Serializer) {
+        let mut length = pixels.len();
+        s.length(&mut length);
+        pixels.resize(length, FifoPixel::default());
+        for pixel in pixels.iter_mut() {
+            pixel.serialize(s);
+        }
+    }
+}
+
+impl StateComponent for PixelFifo {
+    fn serialize(&mut self, s: &mut Serializer) {
+        Self::serialize_pixels(&mut self.bg, s);
+        Self::serialize_pixels(&mut self.obj, s);
+        for pixel in self.row.iter_mut() {
+            pixel.serialize(s);
+        }
+        s.bool(&mut self.row_ready);
+        s.u8(&mut self.fetch_step);
+        s.u8(&mut self.fetch_x);
+        s.bool(&mut self.window);
+        s.u8(&mut self.delay);
+        s.u8(&mut self.discard);
+        s.u8(&mut self.obj_stall);
+        s.usize(&mut self.obj_pending);
+        for index in self.objects.iter_mut() {
+            s.usize(index);
+        }
+        s.usize(&mut self.obj_count);
+        s.bools(&mut self.obj_fetched);
+        s.u8(&mut self.lx);
+        s.u16(&mut self.dots);
+    }
+}
+
+/// The tiles, objects and background map attributes are not part
+/// of the state as they are re-built from the VRAM and OAM contents,
+/// the same goes for the palettes (re-computed from their registers).
+impl StateComponent for Ppu {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.bytes(&mut self.vram);
+        s.bytes(&mut self.hram);
+        s.bytes(&mut self.oam);
+        s.u8(&mut self.vram_bank);
+        s.u16(&mut self.vram_offset);
+        s.bytes(&mut self.palettes);
+        s.bytes(&mut self.palettes_color[0]);
+        s.bytes(&mut self.palettes_color[1]);
+        s.bool(&mut self.obj_priority);
+        s.u8(&mut self.scy);
+        s.u8(&mut self.scx);
+        s.u8(&mut self.wy);
+        s.u8(&mut self.wx);
+        s.u8(&mut self.ly);
+        s.u8(&mut self.lyc);
+        let mut mode = self.mode as u8;
+        s.u8(&mut mode);
+        self.mode = PpuMode::from_u8(mode);
+        s.u16(&mut self.mode_clock);
+        s.u16(&mut self.vram_length);
+        s.u16(&mut self.hblank_length);
+        s.u32(&mut self.lcd_clock);
+        s.bool(&mut self.first_line);
+        s.bool(&mut self.line_153);
+        self.fifo.serialize(s);
+        s.bool(&mut self.switch_bg);
+        s.bool(&mut self.switch_obj);
+        s.bool(&mut self.obj_size);
+        s.bool(&mut self.bg_map);
+        s.bool(&mut self.bg_tile);
+        s.bool(&mut self.switch_window);
+        s.bool(&mut self.window_map);
+        s.bool(&mut self.switch_lcd);
+        s.u8(&mut self.window_counter);
+        s.bool(&mut self.auto_increment_bg);
+        s.u8(&mut self.palette_address_bg);
+        s.bool(&mut self.auto_increment_obj);
+        s.u8(&mut self.palette_address_obj);
+        s.bool(&mut self.first_frame);
+        s.u16(&mut self.frame_index);
+        s.bool(&mut self.stat_hblank);
+        s.bool(&mut self.stat_vblank);
+        s.bool(&mut self.stat_oam);
+        s.bool(&mut self.stat_lyc);
+        s.bool(&mut self.stat_line);
+        s.bool(&mut self.int_vblank);
+        s.bool(&mut self.int_stat);
+        s.bool(&mut self.dmg_compat);
+
+        // re-builds the derived state before the frame buffers are
+        // loaded, as the computation of the palettes clears them
+        if s.loading() {
+            self.update_derived();
+        }
+
+        s.bytes(&mut self.color_buffer[..]);
+        s.bytes(&mut self.frame_buffer[..]);
+        s.bytes(&mut self.frame_buffer_last[..]);
     }
 }
 
@@ -1715,14 +3369,18 @@ impl Default for Ppu {
     fn default() -> Self {
         Self::new(
             GameBoyMode::Dmg,
-            Rc::new(RefCell::new(GameBoyConfig::default())),
+            Arc::new(Mutex::new(GameBoyConfig::default())),
         )
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Ppu;
+    use super::{
+        ColorCorrection, Ppu, PpuMode, PpuRenderer, DEBUG_OBJ_DROP_COLOR, DISPLAY_WIDTH,
+        MAP_BUFFER_SIZE, MAP_WIDTH, OBJ_COUNT, PALETTE_COLORS, RGBA_SIZE, RGB_SIZE, TILE_WIDTH,
+        WIDE_DISPLAY_WIDTH, WIDE_MARGIN,
+    };
 
     #[test]
     fn test_update_tile_simple() {
@@ -1751,4 +3409,339 @@ mod tests {
         let result = ppu.tiles()[256].get(0, 0);
         assert_eq!(result, 3);
     }
+
+    #[test]
+    fn test_fifo_mid_line_palette() {
+        let mut ppu = Ppu::default();
+        ppu.set_renderer(PpuRenderer::Fifo);
+        for y in 0..8 {
+            ppu.write(0x8000 + y * 2, 0xff);
+        }
+        ppu.write(0xff47, 0xe4);
+        ppu.write(0xff40, 0x91);
+        ppu.first_frame = false;
+
+        // runs the (shorter) OAM scan and the first 80 pixels
+        // of the pixel transfer (12 dots of initial fetch)
+        ppu.clock(76);
+        ppu.clock(92);
+        assert!(ppu.mode() == PpuMode::VramRead);
+
+        // changes the palette mid-line and finishes the line
+        ppu.write(0xff47, 0x00);
+        ppu.clock(80);
+        assert!(ppu.mode() == PpuMode::HBlank);
+
+        let left = &ppu.frame_buffer[79 * RGB_SIZE..80 * RGB_SIZE];
+        let right = &ppu.frame_buffer[80 * RGB_SIZE..81 * RGB_SIZE];
+        let last = &ppu.frame_buffer[(DISPLAY_WIDTH - 1) * RGB_SIZE..DISPLAY_WIDTH * RGB_SIZE];
+        assert_eq!(left, &PALETTE_COLORS[1]);
+        assert_eq!(right, &PALETTE_COLORS[0]);
+        assert_eq!(last, &PALETTE_COLORS[0]);
+    }
+
+    #[test]
+    fn test_vram_length() {
+        let mut ppu = Ppu::default();
+        for index in 0..OBJ_COUNT as u16 {
+            ppu.write(0xfe00 + index * 4, 0x00);
+        }
+        ppu.write(0xff40, 0x82);
+        assert_eq!(ppu.compute_vram_length(), 172);
+
+        ppu.write(0xff43, 0x05);
+        assert_eq!(ppu.compute_vram_length(), 177);
+
+        ppu.write(0xfe00, 16);
+        ppu.write(0xfe01, 8);
+        assert_eq!(ppu.compute_vram_length(), 183);
+
+        ppu.write(0xff43, 0x00);
+        assert_eq!(ppu.compute_vram_length(), 183);
+    }
+
+    #[test]
+    fn test_stat_blocking() {
+        let mut ppu = Ppu::default();
+        ppu.write(0xff40, 0x80);
+        ppu.write(0xff41, 0x48);
+        ppu.write(0xff45, 0x00);
+        assert!(ppu.int_stat());
+        ppu.ack_stat();
+
+        // entering H-Blank while the LYC line is still high
+        // must not request a new interrupt
+        ppu.clock(80);
+        ppu.clock(172);
+        assert!(ppu.mode() == PpuMode::HBlank);
+        assert!(!ppu.int_stat());
+    }
+
+    #[test]
+    fn test_line_153() {
+        let mut ppu = Ppu::default();
+        ppu.write(0xff40, 0x80);
+        while ppu.ly() != 153 {
+            ppu.clock(4);
+        }
+        assert!(ppu.mode() == PpuMode::VBlank);
+
+        ppu.clock(4);
+        assert_eq!(ppu.ly(), 0);
+        assert!(ppu.mode() == PpuMode::VBlank);
+
+        ppu.clock(452);
+        assert_eq!(ppu.ly(), 0);
+        assert!(ppu.mode() == PpuMode::OamRead);
+    }
+
+    #[test]
+    fn test_lcd_transitions() {
+        let mut ppu = Ppu::default();
+        ppu.write(0xff40, 0x80);
+        assert!(ppu.first_frame());
+
+        // the first line has no OAM scan (mode 0) and is 4 dots shorter
+        assert_eq!(ppu.read(0xff41) & 0x03, 0x00);
+        ppu.clock(76);
+        assert!(ppu.mode() == PpuMode::VramRead);
+        assert_eq!(ppu.read(0xff41) & 0x03, 0x03);
+
+        while ppu.frame_index() == 0 {
+            ppu.clock(4);
+        }
+        assert!(!ppu.first_frame());
+
+        // turning the LCD off resets LY and puts STAT in mode 0,
+        // while frames keep being produced at the regular rate
+        ppu.write(0xff40, 0x00);
+        assert_eq!(ppu.ly(), 0);
+        assert_eq!(ppu.read(0xff41) & 0x03, 0x00);
+        assert_eq!(&ppu.frame_buffer[0..RGB_SIZE], &PALETTE_COLORS[0]);
+
+        let frame_index = ppu.frame_index();
+        for _ in 0..154 {
+            ppu.clock(456);
+        }
+        assert_eq!(ppu.frame_index(), frame_index + 1);
+        assert_eq!(ppu.ly(), 0);
+    }
+
+    #[test]
+    fn test_layers_debug() {
+        let mut ppu = Ppu::default();
+        for y in 0..8 {
+            ppu.write(0x8000 + y * 2, 0xff);
+        }
+        ppu.write(0xff47, 0xe4);
+        ppu.write(0xff40, 0x93);
+        ppu.first_frame = false;
+        ppu.set_layer_bg(false);
+        ppu.set_layer_obj(false);
+        ppu.set_debug_obj_limit(true);
+
+        while !(ppu.ly() == 1 && ppu.mode() == PpuMode::HBlank) {
+            ppu.clock(4);
+        }
+
+        // the hidden background is drawn with color zero and the
+        // line with more than 10 objects is marked at the right
+        let offset = (DISPLAY_WIDTH + 1) * RGB_SIZE;
+        assert_eq!(
+            &ppu.frame_buffer[offset..offset + RGB_SIZE],
+            &PALETTE_COLORS[0]
+        );
+        let offset = (DISPLAY_WIDTH * 2 - 1) * RGB_SIZE;
+        assert_eq!(
+            &ppu.frame_buffer[offset..offset + RGB_SIZE],
+            &DEBUG_OBJ_DROP_COLOR
+        );
+    }
+
+    #[test]
+    fn test_tile_map_object_viewer() {
+        let mut ppu = Ppu::default();
+        for y in 0..8 {
+            ppu.write(0x8010 + y * 2, 0xff);
+        }
+        ppu.write(0xff47, 0xe4);
+        ppu.write(0xff49, 0xe4);
+        ppu.write(0xff40, 0x10);
+        ppu.write(0x9c21, 0x01);
+
+        // the second tile of the second row of the 0x9c00 map is
+        // the only one using the tile filled with color 1
+        let buffer = ppu.render_tile_map(true);
+        assert_eq!(buffer.len(), MAP_BUFFER_SIZE);
+        let offset = (8 * MAP_WIDTH + 8) * RGB_SIZE;
+        assert_eq!(&buffer[offset..offset + RGB_SIZE], &PALETTE_COLORS[1]);
+        assert_eq!(&buffer[0..RGB_SIZE], &PALETTE_COLORS[0]);
+        let buffer = ppu.render_tile_map(false);
+        assert_eq!(&buffer[offset..offset + RGB_SIZE], &PALETTE_COLORS[0]);
+
+        ppu.write(0xfe0a, 0x01);
+        ppu.write(0xfe0b, 0x10);
+        let obj = ppu.obj_data()[2];
+        assert_eq!(obj.tile(), 1);
+        assert_eq!(obj.palette(), 1);
+        assert_eq!(obj.index(), 2);
+
+        let buffer = ppu.render_object(2);
+        assert_eq!(buffer.len(), TILE_WIDTH * 8 * RGBA_SIZE);
+        assert_eq!(&buffer[0..RGB_SIZE], &PALETTE_COLORS[1]);
+        assert_eq!(buffer[RGB_SIZE], 0xff);
+        let buffer = ppu.render_object(0);
+        assert_eq!(buffer[RGB_SIZE], 0x00);
+        assert!(ppu.render_object(OBJ_COUNT).is_empty());
+    }
+
+    #[test]
+    fn test_unlimited_objects_widescreen() {
+        let build = |unlimited: bool| {
+            let mut ppu = Ppu::default();
+            for y in 0..8 {
+                ppu.write(0x8010 + y * 2, 0xff);
+            }
+            for index in 0..OBJ_COUNT as u16 {
+                ppu.write(0xfe00 + index * 4, 0x00);
+            }
+
+            // places 12 objects in the visible area of the line and
+            // an extra one in the right (widescreen) margin
+            for index in 0..13u16 {
+                let x = if index == 12 { 176 } else { 8 + index * 12 };
+                ppu.write(0xfe00 + index * 4, 17);
+                ppu.write(0xfe01 + index * 4, x as u8);
+                ppu.write(0xfe02 + index * 4, 0x01);
+            }
+            ppu.write(0xff47, 0xe4);
+            ppu.write(0xff48, 0xe4);
+            ppu.write(0xff40, 0x93);
+            ppu.first_frame = false;
+            ppu.set_unlimited_objects(unlimited);
+            ppu.set_widescreen(true);
+
+            while !(ppu.ly() == 1 && ppu.mode() == PpuMode::HBlank) {
+                ppu.clock(4);
+            }
+            ppu
+        };
+
+        let offset = (DISPLAY_WIDTH + 132) * RGB_SIZE;
+        let wide_offset = (WIDE_DISPLAY_WIDTH + WIDE_MARGIN + 168) * RGB_SIZE;
+
+        let ppu = build(false);
+        assert_eq!(
+            &ppu.frame_buffer[offset..offset + RGB_SIZE],
+            &PALETTE_COLORS[0]
+        );
+        assert_eq!(
+            &ppu.frame_buffer_wide[wide_offset..wide_offset + RGB_SIZE],
+            &PALETTE_COLORS[0]
+        );
+
+        let ppu = build(true);
+        assert_eq!(
+            &ppu.frame_buffer[offset..offset + RGB_SIZE],
+            &PALETTE_COLORS[1]
+        );
+        assert_eq!(
+            &ppu.frame_buffer_wide[wide_offset..wide_offset + RGB_SIZE],
+            &PALETTE_COLORS[1]
+        );
+
+        // the center of the widescreen frame is the visible frame
+        let wide_offset = (WIDE_DISPLAY_WIDTH + WIDE_MARGIN) * RGB_SIZE;
+        let offset = DISPLAY_WIDTH * RGB_SIZE;
+        assert_eq!(
+            &ppu.frame_buffer_wide[wide_offset..wide_offset + DISPLAY_WIDTH * RGB_SIZE],
+            &ppu.frame_buffer[offset..offset + DISPLAY_WIDTH * RGB_SIZE]
+        );
+    }
+
+    #[test]
+    fn test_color_correction() {
+        let mut ppu = Ppu::default();
+        ppu.write(0xff68, 0x80);
+        ppu.write(0xff69, 0x1f);
+        ppu.write(0xff69, 0x00);
+        assert_eq!(ppu.palettes_color_bg()[0][0], [248, 0, 0]);
+
+        ppu.set_color_correction(ColorCorrection::Gbc);
+        assert_eq!(ppu.palettes_color_bg()[0][0], [201, 0, 46]);
+
+        ppu.set_color_correction(ColorCorrection::Gba);
+        assert_eq!(ppu.palettes_color_bg()[0][0], [235, 57, 117]);
+
+        // new colors are computed using the current correction,
+        // with both white and black kept unchanged
+        ppu.write(0xff69, 0xff);
+        ppu.write(0xff69, 0x7f);
+        ppu.write(0xff69, 0x00);
+        ppu.write(0xff69, 0x00);
+        assert_eq!(ppu.palettes_color_bg()[0][1], [255, 255, 255]);
+        assert_eq!(ppu.palettes_color_bg()[0][2], [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_frame_blending() {
+        let mut ppu = Ppu::default();
+        ppu.set_frame_blending(true);
+        ppu.frame_buffer[0..DISPLAY_WIDTH * RGB_SIZE].fill(200);
+        ppu.blend_line();
+        assert_eq!(ppu.frame_buffer[0], 100);
+        assert_eq!(ppu.frame_buffer_last[0], 200);
+
+        ppu.blend_line();
+        assert_eq!(ppu.frame_buffer[0], 150);
+
+        // the first frame after the LCD is enabled is not blended
+        ppu.first_frame = true;
+        ppu.frame_buffer[0..DISPLAY_WIDTH * RGB_SIZE].fill(50);
+        ppu.blend_line();
+        assert_eq!(ppu.frame_buffer[0], 50);
+        assert_eq!(ppu.frame_buffer_last[0], 50);
+    }
+
+    #[test]
+    fn test_fifo_unlimited_objects_timing() {
+        let mut ppu = Ppu::default();
+        ppu.set_renderer(PpuRenderer::Fifo);
+        for y in 0..8 {
+            ppu.write(0x8010 + y * 2, 0xff);
+        }
+
+        // places all of the objects in the line, the first 10 at the
+        // same position (worst case for the object fetch stalls) and
+        // the remaining ones (beyond the limit) further to the right
+        for index in 0..OBJ_COUNT as u16 {
+            let x = if index < 10 { 8 } else { 88 };
+            ppu.write(0xfe00 + index * 4, 17);
+            ppu.write(0xfe01 + index * 4, x);
+            ppu.write(0xfe02 + index * 4, 0x01);
+        }
+        ppu.write(0xff47, 0xe4);
+        ppu.write(0xff48, 0xe4);
+        ppu.write(0xff40, 0x93);
+        ppu.first_frame = false;
+        ppu.set_unlimited_objects(true);
+
+        while ppu.ly() != 1 {
+            ppu.clock(1);
+        }
+        let mut dots = 0;
+        while ppu.ly() == 1 {
+            ppu.clock(1);
+            dots += 1;
+        }
+        assert_eq!(dots, 456);
+        assert!(ppu.vram_length <= 289);
+
+        // the objects beyond the limit of 10 are still rendered
+        let offset = (DISPLAY_WIDTH + 80) * RGB_SIZE;
+        assert_eq!(
+            &ppu.frame_buffer[offset..offset + RGB_SIZE],
+            &PALETTE_COLORS[1]
+        );
+    }
 }
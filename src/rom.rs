@@ -4,7 +4,13 @@ use std::{
     fmt::{Display, Formatter},
 };
 
-use crate::{debugln, gb::GameBoyMode, util::read_file, warnln};
+use crate::{
+    debugln,
+    gb::GameBoyMode,
+    state::{Serializer, StateComponent},
+    util::{crc32, read_file},
+    warnln,
+};
 
 #[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
@@ -332,6 +338,22 @@ impl Cartridge {
         &self.rom_data
     }
 
+    /// Translates the provided ROM address in the CPU memory map
+    /// (0x0000-0x7FFF) into the absolute offset inside the ROM
+    /// data, taking into account the currently selected bank.
+    pub fn rom_addr(&self, addr: u16) -> usize {
+        match addr & 0xc000 {
+            0x0000 => addr as usize,
+            _ => self.rom_offset + (addr & 0x3fff) as usize,
+        }
+    }
+
+    /// Computes the CRC-32 checksum of the complete ROM data,
+    /// allowing the unique identification of the cartridge.
+    pub fn checksum(&self) -> u32 {
+        crc32(&self.rom_data)
+    }
+
     pub fn get_bank(&self, index: u8) -> &[u8] {
         let start = index as usize * ROM_BANK_SIZE;
         let end = (index + 1) as usize * ROM_BANK_SIZE;
@@ -617,6 +639,18 @@ impl Cartridge {
     }
 }
 
+/// Only the banking and RAM state is serialized, the ROM data
+/// is expected to be loaded (and the same) when loading a state.
+impl StateComponent for Cartridge {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.vec(&mut self.ram_data);
+        s.usize(&mut self.rom_offset);
+        s.usize(&mut self.ram_offset);
+        s.bool(&mut self.ram_enabled);
+        s.bool(&mut self.rumble_active);
+    }
+}
+
 impl Default for Cartridge {
     fn default() -> Self {
         Self::new()
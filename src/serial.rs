@@ -1,6 +1,10 @@
-use crate::warnln;
+use crate::{
+    gb::{GameBoyMode, GameBoySpeed},
+    state::{Serializer, StateComponent},
+    warnln,
+};
 
-pub trait SerialDevice {
+pub trait SerialDevice: Send {
     /// Sends a byte (u8) to the attached serial connection.
     fn send(&mut self) -> u8;
 
@@ -10,7 +14,8 @@ pub trait SerialDevice {
 
     /// Whether the serial device "driver" supports slave mode
     /// simulating an external clock source. Or if instead the
-    /// clock should always be generated by the running device.
+    /// clock should always be generated by the running device,
+    /// with slave mode transfers then never completing.
     fn allow_slave(&self) -> bool;
 
     /// Returns a short description of the serial device.
@@ -19,6 +24,30 @@ pub trait SerialDevice {
     /// Returns a string describing the current state of the
     /// serial device. Could be used for debugging purposes.
     fn state(&self) -> String;
+
+    /// Notifies the device of the byte that is waiting to be
+    /// transferred in slave mode (external clock), `None` meaning
+    /// that there's no longer a slave transfer pending.
+    ///
+    /// Only relevant for devices that connect to another Game Boy
+    /// (eg: link cable), the default implementation ignores it.
+    fn set_slave(&mut self, _byte: Option<u8>) {}
+
+    /// Notifies the device of the byte that is about to be transferred
+    /// in master mode (internal clock) and of the number of (normal
+    /// speed) cycles the transfer takes, called right before `send()`.
+    fn set_master(&mut self, _byte: u8, _cycles: u16) {}
+
+    /// Advances the device by the given number of (normal speed)
+    /// cycles, called on every clock of the serial controller so that
+    /// devices can keep track of the emulated time.
+    fn clock(&mut self, _cycles: u16) {}
+
+    /// Polls the device for the byte received in a slave mode transfer
+    /// clocked by the other end, completing the pending transfer.
+    fn poll(&mut self) -> Option<u8> {
+        None
+    }
 }
 
 pub struct Serial {
@@ -34,10 +63,16 @@ pub struct Serial {
     byte_receive: u8,
     int_serial: bool,
     device: Box<dyn SerialDevice>,
+    gb_mode: GameBoyMode,
+    speed: GameBoySpeed,
 }
 
 impl Serial {
     pub fn new() -> Self {
+        Self::with_mode(GameBoyMode::Dmg)
+    }
+
+    pub fn with_mode(mode: GameBoyMode) -> Self {
         Self {
             data: 0x0,
             control: 0x0,
@@ -51,6 +86,8 @@ impl Serial {
             byte_receive: 0x0,
             int_serial: false,
             device: Box::<NullDevice>::default(),
+            gb_mode: mode,
+            speed: GameBoySpeed::Normal,
         }
     }
 
@@ -66,21 +103,46 @@ impl Serial {
         self.byte_send = 0x0;
         self.byte_receive = 0x0;
         self.int_serial = false;
+        self.speed = GameBoySpeed::Normal;
+        self.device.set_slave(None);
     }
 
     pub fn clock(&mut self, cycles: u16) {
+        self.device.clock(cycles / self.speed.multiplier() as u16);
+
         if !self.transferring {
             return;
         }
 
+        // in slave mode the transfer is clocked by the other end
+        // so the device is polled for the completion of it, in case
+        // the device is not able to provide an external clock the
+        // transfer stays pending (as with no cable attached)
+        if !self.shift_clock {
+            if !self.device.allow_slave() {
+                return;
+            }
+            if let Some(byte) = self.device.poll() {
+                self.data = byte;
+                self.transferring = false;
+                self.bit_count = 0;
+                self.int_serial = true;
+            }
+            return;
+        }
+
+        // shifts one bit per period of the serial clock, with the
+        // CGB high speed clock multiple bits may be shifted during
+        // a single instruction, the byte is sent to the device as
+        // soon as its last bit is shifted
         self.timer = self.timer.saturating_sub(cycles as i16);
-        if self.timer <= 0 {
+        while self.timer <= 0 && self.transferring {
             let bit = (self.byte_receive >> (7 - self.bit_count)) & 0x01;
             self.data = (self.data << 1) | bit;
 
             self.tick_transfer();
 
-            self.timer = self.length as i16;
+            self.timer += self.length as i16;
         }
     }
 
@@ -93,8 +155,17 @@ impl Serial {
             {
                 #[allow(clippy::bool_to_int_with_if)]
                 (if self.shift_clock { 0x01 } else { 0x00 }
-                    | if self.clock_speed { 0x02 } else { 0x00 }
-                    | if self.transferring { 0x80 } else { 0x00 })
+                    | if self.transferring { 0x80 } else { 0x00 }
+                    | match self.gb_mode {
+                        GameBoyMode::Cgb => {
+                            if self.clock_speed {
+                                0x7e
+                            } else {
+                                0x7c
+                            }
+                        }
+                        _ => 0x7e,
+                    })
             }
             _ => {
                 warnln!("Reding from unknown Serial location 0x{:04x}", addr);
@@ -106,31 +177,40 @@ impl Serial {
     pub fn write(&mut self, addr: u16, value: u8) {
         match addr {
             // 0xFF01 — SB: Serial transfer data
-            0xff01 => self.data = value,
+            0xff01 => {
+                self.data = value;
+                if self.transferring && !self.shift_clock {
+                    self.device.set_slave(Some(self.data));
+                }
+            }
             // 0xFF02 — SC: Serial transfer control
             0xff02 => {
                 self.shift_clock = value & 0x01 == 0x01;
                 self.clock_speed = value & 0x02 == 0x02;
                 self.transferring = value & 0x80 == 0x80;
 
-                // in case the clock is meant to be set by the attached device
-                // and the current Game Boy is meant to be running in slave mode
-                // then checks if the attached device "driver" allows clock set
-                // by external device and if not then ignores the transfer request
-                // by immediately disabling the transferring flag
-                if !self.shift_clock && !self.device.allow_slave() {
-                    self.transferring = false;
+                // in case a slave mode transfer has been requested the
+                // byte is made available to the other end (if any)
+                if self.transferring && !self.shift_clock {
+                    self.device.set_slave(Some(self.data));
+                } else {
+                    self.device.set_slave(None);
                 }
 
                 // in case a transfer of byte has been requested and
                 // this is the then we need to start the transfer setup
-                if self.transferring {
-                    // @TODO: if the GBC mode exists there should
-                    // be special check logic here
-                    //self.length = if self.gb.is_cgb() && self.clock_speed { 16 } else { 512 };
-                    self.length = 512;
+                if self.transferring && self.shift_clock {
+                    // the internal clock runs at 8192Hz (512 cycles per bit)
+                    // or in the CGB high speed mode at 262144Hz (16 cycles
+                    // per bit), both being doubled in double speed mode
+                    self.length = match self.gb_mode {
+                        GameBoyMode::Cgb if self.clock_speed => 16,
+                        _ => 512,
+                    };
                     self.bit_count = 0;
                     self.timer = self.length as i16;
+                    self.device
+                        .set_master(self.data, self.length * 8 / self.speed.multiplier() as u16);
 
                     // executes the send and receive operation immediately
                     // this is considered an operational optimization with
@@ -175,6 +255,22 @@ impl Serial {
         self.set_int_serial(false);
     }
 
+    pub fn gb_mode(&self) -> GameBoyMode {
+        self.gb_mode
+    }
+
+    pub fn set_gb_mode(&mut self, value: GameBoyMode) {
+        self.gb_mode = value;
+    }
+
+    pub fn speed(&self) -> GameBoySpeed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, value: GameBoySpeed) {
+        self.speed = value;
+    }
+
     pub fn device(&self) -> &dyn SerialDevice {
         self.device.as_ref()
     }
@@ -202,12 +298,58 @@ impl Serial {
     }
 }
 
+/// The attached device is not part of the state, any transfer
+/// pending on it is lost when a state is loaded.
+impl StateComponent for Serial {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.u8(&mut self.data);
+        s.u8(&mut self.control);
+        s.bool(&mut self.shift_clock);
+        s.bool(&mut self.clock_speed);
+        s.bool(&mut self.transferring);
+        s.i16(&mut self.timer);
+        s.u16(&mut self.length);
+        s.u8(&mut self.bit_count);
+        s.u8(&mut self.byte_send);
+        s.u8(&mut self.byte_receive);
+        s.bool(&mut self.int_serial);
+        let mut speed = self.speed as u8;
+        s.u8(&mut speed);
+        self.speed = GameBoySpeed::from_u8(speed & 0x01);
+    }
+}
+
 impl Default for Serial {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Clones the serial state, the attached device is not cloned
+/// (as it may own external resources like a socket or the
+/// other end of a link cable) and a null device is attached
+/// to the clone instead.
+impl Clone for Serial {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data,
+            control: self.control,
+            shift_clock: self.shift_clock,
+            clock_speed: self.clock_speed,
+            transferring: self.transferring,
+            timer: self.timer,
+            length: self.length,
+            bit_count: self.bit_count,
+            byte_send: self.byte_send,
+            byte_receive: self.byte_receive,
+            int_serial: self.int_serial,
+            device: Box::<NullDevice>::default(),
+            gb_mode: self.gb_mode,
+            speed: self.speed,
+        }
+    }
+}
+
 pub struct NullDevice {}
 
 impl NullDevice {
@@ -241,3 +383,72 @@ impl Default for NullDevice {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Serial;
+    use crate::gb::{GameBoyMode, GameBoySpeed};
+
+    fn clock_until_done(serial: &mut Serial, cycles: u16) -> u32 {
+        let mut total = 0;
+        while !serial.int_serial() {
+            serial.clock(cycles);
+            total += cycles as u32;
+        }
+        total
+    }
+
+    #[test]
+    fn test_serial_clock_speed() {
+        let mut serial = Serial::new();
+        serial.write(0xff02, 0x83);
+        assert_eq!(serial.read(0xff02), 0xff);
+        assert_eq!(clock_until_done(&mut serial, 4), 8 * 512);
+        assert_eq!(serial.read(0xff02), 0x7f);
+        assert_eq!(serial.read(0xff01), 0xff);
+
+        let mut serial = Serial::with_mode(GameBoyMode::Cgb);
+        serial.write(0xff02, 0x83);
+        assert_eq!(serial.read(0xff02), 0xff);
+        assert_eq!(clock_until_done(&mut serial, 4), 8 * 16);
+        assert_eq!(serial.read(0xff02), 0x7f);
+
+        // in the CGB high speed mode multiple bits are shifted
+        // during a single (long) instruction
+        let mut serial = Serial::with_mode(GameBoyMode::Cgb);
+        serial.write(0xff02, 0x83);
+        serial.clock(24);
+        serial.clock(24);
+        assert_eq!(serial.read(0xff01), 0x07);
+
+        let mut serial = Serial::with_mode(GameBoyMode::Cgb);
+        serial.write(0xff02, 0x81);
+        assert_eq!(serial.read(0xff02), 0xfd);
+        assert_eq!(clock_until_done(&mut serial, 4), 8 * 512);
+        assert_eq!(serial.read(0xff02), 0x7d);
+
+        // in double speed mode the serial is clocked with double speed
+        // cycles so that the same count of them takes half the time
+        for (control, cycles) in [(0x81, 8 * 512), (0x83, 8 * 16)] {
+            let mut serial = Serial::with_mode(GameBoyMode::Cgb);
+            serial.set_speed(GameBoySpeed::Double);
+            serial.write(0xff02, control);
+            assert_eq!(clock_until_done(&mut serial, 8), cycles);
+            assert_eq!(serial.read(0xff02), control & 0x7f | 0x7c);
+        }
+    }
+
+    #[test]
+    fn test_serial_slave_pending() {
+        // with no device able to clock the transfer it stays pending
+        let mut serial = Serial::new();
+        serial.write(0xff01, 0x42);
+        serial.write(0xff02, 0x80);
+        for _ in 0..8 * 512 {
+            serial.clock(4);
+        }
+        assert!(!serial.int_serial());
+        assert_eq!(serial.read(0xff02), 0xfe);
+        assert_eq!(serial.read(0xff01), 0x42);
+    }
+}
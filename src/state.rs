@@ -0,0 +1,298 @@
+use std::convert::TryInto;
+
+/// The magic string that identifies a save state.
+pub const STATE_MAGIC: &[u8; 4] = b"BTST";
+
+/// The current version of the save state format, to be
+/// increased whenever the layout of any component changes.
+pub const STATE_VERSION: u8 = 1;
+
+/// The size in bytes of the header of a save state, as magic (4 bytes),
+/// version (1 byte), mode (1 byte) and ROM checksum (4 bytes).
+pub const STATE_HEADER_SIZE: usize = 10;
+
+/// The direction of the operation of a serializer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SerializerMode {
+    Save,
+    Load,
+}
+
+/// Symmetric serializer of the state of the emulator components,
+/// the same sequence of calls either writes the provided values
+/// into the buffer (save) or overwrites them with the values read
+/// from it (load), so that both operations can never diverge.
+///
+/// All the values are stored in little endian, with no padding
+/// or field names, meaning that the layout is only defined by
+/// the order of the calls made by each component.
+pub struct Serializer {
+    mode: SerializerMode,
+    data: Vec<u8>,
+    offset: usize,
+    overflow: bool,
+}
+
+macro_rules! serialize_number {
+    ($name:ident, $type:ty) => {
+        pub fn $name(&mut self, value: &mut $type) {
+            let mut buffer = value.to_le_bytes();
+            self.bytes(&mut buffer);
+            *value = <$type>::from_le_bytes(buffer);
+        }
+    };
+}
+
+impl Serializer {
+    pub fn saver() -> Self {
+        Self {
+            mode: SerializerMode::Save,
+            data: vec![],
+            offset: 0,
+            overflow: false,
+        }
+    }
+
+    pub fn loader(data: &[u8]) -> Self {
+        Self {
+            mode: SerializerMode::Load,
+            data: data.to_vec(),
+            offset: 0,
+            overflow: false,
+        }
+    }
+
+    pub fn mode(&self) -> SerializerMode {
+        self.mode
+    }
+
+    pub fn loading(&self) -> bool {
+        self.mode == SerializerMode::Load
+    }
+
+    /// Serializes a fixed size sequence of bytes, in case there's
+    /// not enough data to be loaded the values are kept untouched
+    /// and the serializer is marked as failed.
+    pub fn bytes(&mut self, value: &mut [u8]) {
+        match self.mode {
+            SerializerMode::Save => self.data.extend_from_slice(value),
+            SerializerMode::Load => {
+                if self.overflow || self.offset + value.len() > self.data.len() {
+                    self.overflow = true;
+                    return;
+                }
+                value.copy_from_slice(&self.data[self.offset..self.offset + value.len()]);
+                self.offset += value.len();
+            }
+        }
+    }
+
+    serialize_number!(u8, u8);
+    serialize_number!(u16, u16);
+    serialize_number!(u32, u32);
+    serialize_number!(u64, u64);
+    serialize_number!(i16, i16);
+    serialize_number!(i32, i32);
+
+    pub fn bool(&mut self, value: &mut bool) {
+        let mut buffer = *value as u8;
+        self.u8(&mut buffer);
+        *value = buffer != 0x00;
+    }
+
+    pub fn bools(&mut self, value: &mut [bool]) {
+        for item in value.iter_mut() {
+            self.bool(item);
+        }
+    }
+
+    /// Serializes an offset or index, stored as 32 bits so that
+    /// the state is the same across platforms.
+    pub fn usize(&mut self, value: &mut usize) {
+        let mut buffer = *value as u32;
+        self.u32(&mut buffer);
+        *value = buffer as usize;
+    }
+
+    /// Serializes the number of items of a variable length sequence,
+    /// as a count larger than the data still to be loaded can only
+    /// come from a corrupted state the serializer fails for it.
+    pub fn length(&mut self, value: &mut usize) {
+        self.usize(value);
+        if self.loading() && *value > self.data.len() - self.offset {
+            self.overflow = true;
+            *value = 0;
+        }
+    }
+
+    /// Serializes a variable length buffer, prefixed by its length.
+    pub fn vec(&mut self, value: &mut Vec<u8>) {
+        let mut length = value.len();
+        self.length(&mut length);
+        value.resize(length, 0x00);
+        self.bytes(value);
+    }
+
+    /// Checks that the complete data has been loaded, with no
+    /// values missing nor trailing data.
+    pub fn finish(&self) -> Result<(), String> {
+        if self.overflow {
+            return Err(String::from("Truncated state data"));
+        }
+        if self.loading() && self.offset != self.data.len() {
+            return Err(String::from("Trailing state data"));
+        }
+        Ok(())
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Component of the emulator whose state can be saved into and
+/// loaded from a save state.
+///
+/// Only the state of the emulated machine is expected to be
+/// serialized, the configuration of the emulator (eg: renderer,
+/// debug layers) and the host side buffers (eg: audio samples)
+/// are kept as they are.
+pub trait StateComponent {
+    fn serialize(&mut self, s: &mut Serializer);
+}
+
+/// Reads the header of a save state returning the mode (as a
+/// number) and the ROM checksum it has been saved with.
+pub fn read_header(data: &[u8]) -> Result<(u8, u32), String> {
+    if data.len() < STATE_HEADER_SIZE || &data[0..4] != STATE_MAGIC {
+        return Err(String::from("Invalid state data"));
+    }
+    if data[4] != STATE_VERSION {
+        return Err(format!("Unsupported state version: {}", data[4]));
+    }
+    Ok((data[5], u32::from_le_bytes(data[6..10].try_into().unwrap())))
+}
+
+/// Builds the header of a save state for the given mode (as a
+/// number) and ROM checksum.
+pub fn write_header(mode: u8, rom_checksum: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(STATE_HEADER_SIZE);
+    data.extend_from_slice(STATE_MAGIC);
+    data.push(STATE_VERSION);
+    data.push(mode);
+    data.extend_from_slice(&rom_checksum.to_le_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Serializer, STATE_HEADER_SIZE};
+    use crate::gb::{GameBoy, GameBoyMode};
+
+    /// Builds a Game Boy (with the boot ROM skipped) running a ROM
+    /// that keeps changing the background palette (BGP) and the
+    /// square wave frequency, so that any state that is not properly
+    /// restored shows up in the frame buffer or in the registers.
+    fn build_state_test() -> GameBoy {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100..0x010a].copy_from_slice(&[
+            0x04, // INC B
+            0x78, // LD A, B
+            0xe0, 0x47, // LDH (0x47), A
+            0xe0, 0x18, // LDH (0x18), A
+            0x3e, 0x87, // LD A, 0x87
+            0xe0, 0x19, // LDH (0x19), A
+        ]);
+        rom[0x010a..0x010c].copy_from_slice(&[0x18, 0xf4]); // JR -12
+        let mut game_boy = GameBoy::new(Some(GameBoyMode::Dmg));
+        game_boy.load(false);
+        game_boy.load_rom(&rom);
+        game_boy.boot();
+        game_boy
+    }
+
+    #[test]
+    fn test_serializer() {
+        let (mut a, mut b, mut c, mut d) = (0x12u8, 0x3456u16, -2i16, true);
+        let mut buffer = vec![0x01, 0x02, 0x03];
+        let mut s = Serializer::saver();
+        s.u8(&mut a);
+        s.u16(&mut b);
+        s.i16(&mut c);
+        s.bool(&mut d);
+        s.vec(&mut buffer);
+        assert!(s.finish().is_ok());
+        let data = s.into_data();
+        assert_eq!(data.len(), 1 + 2 + 2 + 1 + 4 + 3);
+
+        let (mut a, mut b, mut c, mut d) = (0u8, 0u16, 0i16, false);
+        let mut buffer = vec![];
+        let mut s = Serializer::loader(&data);
+        s.u8(&mut a);
+        s.u16(&mut b);
+        s.i16(&mut c);
+        s.bool(&mut d);
+        s.vec(&mut buffer);
+        assert!(s.finish().is_ok());
+        assert_eq!((a, b, c, d), (0x12, 0x3456, -2, true));
+        assert_eq!(buffer, vec![0x01, 0x02, 0x03]);
+
+        let mut s = Serializer::loader(&data[0..4]);
+        s.u8(&mut a);
+        s.u16(&mut b);
+        s.i16(&mut c);
+        assert!(s.finish().is_err());
+    }
+
+    #[test]
+    fn test_state_game_boy() {
+        let mut game_boy = build_state_test();
+        for _ in 0..3 {
+            game_boy.run_frame();
+        }
+        for _ in 0..1000 {
+            game_boy.clock();
+        }
+        let state = game_boy.save_state();
+
+        let mut expected = vec![];
+        for _ in 0..3 {
+            game_boy.run_frame();
+            expected.push(*game_boy.frame_buffer());
+        }
+
+        // the state is loaded into a fresh (power-on) instance that
+        // must then run into the exact same frames and final state
+        let mut other = build_state_test();
+        other.load_state(&state).unwrap();
+        let mut played = vec![];
+        for _ in 0..3 {
+            other.run_frame();
+            played.push(*other.frame_buffer());
+        }
+        assert!(expected == played);
+        assert!(game_boy.save_state() == other.save_state());
+    }
+
+    #[test]
+    fn test_state_invalid() {
+        let mut game_boy = build_state_test();
+        game_boy.run_frame();
+        let state = game_boy.save_state();
+        let pc = game_boy.cpu().pc();
+
+        let mut other = build_state_test();
+        assert!(other
+            .load_state(&state[0..STATE_HEADER_SIZE + 100])
+            .is_err());
+        assert!(other.load_state(b"XXXX").is_err());
+        assert_eq!(other.cpu().pc(), 0x0100);
+
+        let mut cgb = GameBoy::new(Some(GameBoyMode::Cgb));
+        cgb.load(false);
+        assert!(cgb.load_state(&state).is_err());
+
+        assert!(other.load_state(&state).is_ok());
+        assert_eq!(other.cpu().pc(), pc);
+    }
+}
@@ -1,13 +1,14 @@
 use crate::{
     devices::buffer::BufferDevice,
     gb::{GameBoy, GameBoyMode},
-    ppu::FRAME_BUFFER_SIZE,
+    ppu::{PpuRenderer, FRAME_BUFFER_SIZE},
 };
 
 #[derive(Default)]
 pub struct TestOptions {
     pub mode: Option<GameBoyMode>,
     pub ppu_enabled: Option<bool>,
+    pub ppu_renderer: Option<PpuRenderer>,
     pub apu_enabled: Option<bool>,
     pub dma_enabled: Option<bool>,
     pub timer_enabled: Option<bool>,
@@ -17,6 +18,7 @@ pub fn build_test(options: TestOptions) -> GameBoy {
     let device = Box::<BufferDevice>::default();
     let mut game_boy = GameBoy::new(options.mode);
     game_boy.set_ppu_enabled(options.ppu_enabled.unwrap_or(true));
+    game_boy.set_ppu_renderer(options.ppu_renderer.unwrap_or(PpuRenderer::Line));
     game_boy.set_apu_enabled(options.apu_enabled.unwrap_or(true));
     game_boy.set_dma_enabled(options.dma_enabled.unwrap_or(true));
     game_boy.set_timer_enabled(options.timer_enabled.unwrap_or(true));
@@ -47,6 +49,26 @@ pub fn run_serial_test(rom_path: &str, max_cycles: Option<u64>, options: TestOpt
     game_boy.serial().device().state()
 }
 
+/// Runs a test ROM that reports its result to the cartridge RAM
+/// (as the blargg sound tests do) instead of the serial port,
+/// returning the text written after the 0xDE 0xB0 0x61 signature.
+pub fn run_memory_test(rom_path: &str, max_cycles: Option<u64>, options: TestOptions) -> String {
+    let mut game_boy = run_test(rom_path, max_cycles, options);
+    let mmu = game_boy.mmu();
+    if [mmu.read(0xa001), mmu.read(0xa002), mmu.read(0xa003)] != [0xde, 0xb0, 0x61] {
+        return String::new();
+    }
+    let mut result = String::new();
+    for addr in 0xa004..0xc000 {
+        let byte = mmu.read(addr);
+        if byte == 0x00 {
+            break;
+        }
+        result.push(byte as char);
+    }
+    result
+}
+
 pub fn run_image_test(
     rom_path: &str,
     max_cycles: Option<u64>,
@@ -58,7 +80,13 @@ pub fn run_image_test(
 
 #[cfg(test)]
 mod tests {
-    use super::{run_serial_test, TestOptions};
+    use crate::{
+        gb::{GameBoy, GameBoyMode},
+        pad::PadKey,
+        ppu::{PpuMode, PpuRenderer},
+    };
+
+    use super::{build_test, run_image_test, run_memory_test, run_serial_test, TestOptions};
 
     #[test]
     fn test_blargg_cpu_instrs() {
@@ -69,4 +97,85 @@ mod tests {
         );
         assert_eq!(result, "cpu_instrs\n\n01:ok  02:ok  03:ok  04:ok  05:ok  06:ok  07:ok  08:ok  09:ok  10:ok  11:ok  \n\nPassed all tests\n");
     }
+
+    #[test]
+    fn test_blargg_dmg_sound() {
+        let result = run_memory_test(
+            "res/roms/test/blargg/dmg_sound/dmg_sound.gb",
+            Some(300000000),
+            TestOptions {
+                mode: Some(GameBoyMode::Dmg),
+                ..TestOptions::default()
+            },
+        );
+        assert_eq!(result, "dmg_sound\n\n01:ok  02:ok  03:ok  04:ok  05:ok  06:ok  07:ok  08:ok  09:ok  10:ok  11:ok  12:ok  \n\nPassed\n");
+    }
+
+    #[test]
+    fn test_blargg_cgb_sound() {
+        let result = run_memory_test(
+            "res/roms/test/blargg/cgb_sound/cgb_sound.gb",
+            Some(300000000),
+            TestOptions {
+                mode: Some(GameBoyMode::Cgb),
+                ..TestOptions::default()
+            },
+        );
+        assert_eq!(result, "cgb_sound\n\n01:ok  02:ok  03:ok  04:ok  05:ok  06:ok  07:ok  08:ok  09:ok  10:ok  11:ok  12:ok  \n\nPassed\n");
+    }
+
+    #[test]
+    fn test_fifo_renderer_acid2() {
+        for (rom_path, mode) in [
+            ("res/roms/test/dmg_acid2.gb", GameBoyMode::Dmg),
+            ("res/roms/test/cgb_acid2.gbc", GameBoyMode::Cgb),
+        ] {
+            let line = run_image_test(
+                rom_path,
+                Some(50000000),
+                TestOptions {
+                    mode: Some(mode),
+                    ..TestOptions::default()
+                },
+            );
+            let fifo = run_image_test(
+                rom_path,
+                Some(50000000),
+                TestOptions {
+                    mode: Some(mode),
+                    ppu_renderer: Some(PpuRenderer::Fifo),
+                    ..TestOptions::default()
+                },
+            );
+            assert_eq!(line, fifo);
+        }
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut game_boy = build_test(TestOptions::default());
+        game_boy.load_rom_file("res/roms/test/dmg_acid2.gb");
+
+        // runs some frames so that the boot sequence is able
+        // to turn on the LCD (frames are no longer time limited)
+        for _ in 0..10 {
+            game_boy.run_frame();
+        }
+
+        let frame = game_boy.ppu_frame();
+        let result = game_boy.run_frame();
+        assert!(game_boy.ppu_mode() == PpuMode::VBlank);
+        assert_eq!(result.frame, frame.wrapping_add(1));
+        assert!(result.cycles >= GameBoy::LCD_CYCLES - 24);
+        assert!(result.cycles <= GameBoy::LCD_CYCLES + 24);
+    }
+
+    #[test]
+    fn test_input_state() {
+        let mut game_boy = build_test(TestOptions::default());
+        game_boy.set_input_state(0x09);
+        assert_eq!(game_boy.input_state(), 0x09);
+        game_boy.set_input_state(PadKey::Down.mask());
+        assert_eq!(game_boy.input_state(), 0x80);
+    }
 }
@@ -1,5 +1,9 @@
-use crate::warnln;
+use crate::{
+    state::{Serializer, StateComponent},
+    warnln,
+};
 
+#[derive(Clone)]
 pub struct Timer {
     div: u8,
     tima: u8,
@@ -124,6 +128,20 @@ impl Timer {
     }
 }
 
+impl StateComponent for Timer {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.u8(&mut self.div);
+        s.u8(&mut self.tima);
+        s.u8(&mut self.tma);
+        s.u8(&mut self.tac);
+        s.u16(&mut self.div_clock);
+        s.u16(&mut self.tima_clock);
+        s.bool(&mut self.tima_enabled);
+        s.u16(&mut self.tima_ratio);
+        s.bool(&mut self.int_tima);
+    }
+}
+
 impl Default for Timer {
     fn default() -> Self {
         Self::new()
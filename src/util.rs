@@ -1,7 +1,15 @@
-use std::{cell::RefCell, fs::File, io::Read, rc::Rc};
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{self, Read, Write},
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
 
 pub type SharedMut<T> = Rc<RefCell<T>>;
 
+pub type SharedThread<T> = Arc<Mutex<T>>;
+
 pub fn read_file(path: &str) -> Vec<u8> {
     let mut file = match File::open(path) {
         Ok(file) => file,
@@ -11,3 +19,33 @@ pub fn read_file(path: &str) -> Vec<u8> {
     file.read_to_end(&mut data).unwrap();
     data
 }
+
+pub fn write_file(path: &str, data: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(data)
+}
+
+/// Computes the CRC-32 (IEEE 802.3) checksum of the provided
+/// data buffer, used to uniquely identify ROM contents.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 0x1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::crc32;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0x00000000);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+}
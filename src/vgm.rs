@@ -0,0 +1,351 @@
+use std::io;
+
+use crate::util::write_file;
+
+/// The clock (in Hz) of the Game Boy DMG sound chip, as
+/// stored in the VGM header.
+pub const VGM_CLOCK: u32 = 4194304;
+
+/// The sampling rate (in Hz) used by the VGM format for
+/// all of its wait commands.
+pub const VGM_RATE: u32 = 44100;
+
+/// The number of (APU) cycles of a frame, used as the unit
+/// for the detection of loops in the logged writes.
+pub const VGM_FRAME_CYCLES: u64 = 70224;
+
+/// The size of the VGM header written, enough to include the
+/// Game Boy DMG clock field (0x80) of the 1.61 version.
+const VGM_HEADER_SIZE: usize = 0x100;
+
+/// VGM command that writes a value to a Game Boy DMG register,
+/// with the register being relative to NR10 (0xFF10).
+const VGM_CMD_DMG: u8 = 0xb3;
+
+/// VGM command that waits for a 16 bit number of samples.
+const VGM_CMD_WAIT: u8 = 0x61;
+
+/// VGM command that marks the end of the sound data.
+const VGM_CMD_END: u8 = 0x66;
+
+/// Logger of the writes to the APU registers (0xFF10-0xFF3F)
+/// that is able to export them as a VGM file, a compact and
+/// accurate capture of the music playable in chiptune players.
+#[derive(Clone)]
+pub struct VgmLogger {
+    /// The number of cycles elapsed since the start of the
+    /// logging, used to timestamp each of the writes.
+    cycles: u64,
+
+    /// The writes performed to the APU registers, as tuples
+    /// of the cycle timestamp, register (relative to 0xFF10)
+    /// and the written value.
+    writes: Vec<(u64, u8, u8)>,
+}
+
+impl VgmLogger {
+    pub fn new() -> Self {
+        Self {
+            cycles: 0,
+            writes: vec![],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.cycles = 0;
+        self.writes.clear();
+    }
+
+    #[inline(always)]
+    pub fn clock(&mut self, cycles: u16) {
+        self.cycles += cycles as u64;
+    }
+
+    /// Logs the write of the value to the APU register at the
+    /// provided address, addresses outside of the APU range
+    /// (0xFF10-0xFF3F) are silently ignored.
+    pub fn log(&mut self, addr: u16, value: u8) {
+        if !(0xff10..=0xff3f).contains(&addr) {
+            return;
+        }
+        self.writes
+            .push((self.cycles, (addr - 0xff10) as u8, value));
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn writes(&self) -> &Vec<(u64, u8, u8)> {
+        &self.writes
+    }
+
+    /// The number of (complete or partial) frames logged.
+    pub fn frame_count(&self) -> usize {
+        let frames = self.cycles / VGM_FRAME_CYCLES;
+        if frames * VGM_FRAME_CYCLES < self.cycles {
+            frames as usize + 1
+        } else {
+            frames as usize
+        }
+    }
+
+    /// Groups the writes by frame, keeping only the register and
+    /// value so that frames can be compared between each other.
+    pub fn frames(&self) -> Vec<Vec<(u8, u8)>> {
+        let mut frames = vec![vec![]; self.frame_count()];
+        for (cycles, register, value) in &self.writes {
+            let index = (*cycles / VGM_FRAME_CYCLES) as usize;
+            if index >= frames.len() {
+                frames.resize(index + 1, vec![]);
+            }
+            frames[index].push((*register, *value));
+        }
+        frames
+    }
+
+    /// Tries to find a loop in the logged frames, meaning a sequence
+    /// of frames that is repeated (at least twice) until the end of
+    /// the log, returning the index of the first frame of the loop
+    /// and the loop length (in frames).
+    ///
+    /// The loop with the earliest start is chosen, with the shortest
+    /// length used to break ties, loops without writes are ignored.
+    ///
+    /// Only complete frames are considered, as the last (partial)
+    /// frame can not be compared with the others, its writes are
+    /// only exported in case no loop is found.
+    pub fn detect_loop(&self) -> Option<(usize, usize)> {
+        let mut frames = self.frames();
+        frames.truncate((self.cycles / VGM_FRAME_CYCLES) as usize);
+        let count = frames.len();
+        let mut result: Option<(usize, usize)> = None;
+
+        for length in 1..=count / 2 {
+            // goes back from the end of the log while the frames
+            // match the ones a loop length ahead of them
+            let mut start = count - length;
+            while start > 0 && frames[start - 1] == frames[start - 1 + length] {
+                start -= 1;
+            }
+            if count - start < length * 2 {
+                continue;
+            }
+            if frames[start..start + length].iter().all(|f| f.is_empty()) {
+                continue;
+            }
+            let better = match result {
+                Some((best, _)) => start < best,
+                None => true,
+            };
+            if better {
+                result = Some((start, length));
+            }
+        }
+
+        result
+    }
+
+    /// Exports the logged writes as the contents of a VGM (1.61)
+    /// file, in case a loop is detected the data is truncated at
+    /// the end of its first iteration and the loop is marked.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let loop_frames = self.detect_loop();
+        let end_cycles = match loop_frames {
+            Some((start, length)) => (start + length) as u64 * VGM_FRAME_CYCLES,
+            None => self.cycles,
+        };
+        let loop_cycles = loop_frames.map(|(start, _)| start as u64 * VGM_FRAME_CYCLES);
+
+        let mut data = vec![0u8; VGM_HEADER_SIZE];
+        let mut samples = 0u64;
+        let mut loop_offset = None;
+
+        for (cycles, register, value) in &self.writes {
+            // the writes of the frame where the loop ends belong
+            // to the next iteration of the loop (not logged)
+            if loop_frames.is_some() && *cycles >= end_cycles {
+                break;
+            }
+            if let Some(loop_cycles) = loop_cycles {
+                if loop_offset.is_none() && *cycles >= loop_cycles {
+                    Self::wait(&mut data, &mut samples, loop_cycles);
+                    loop_offset = Some(data.len());
+                }
+            }
+            Self::wait(&mut data, &mut samples, *cycles);
+            data.extend_from_slice(&[VGM_CMD_DMG, *register, *value]);
+        }
+        if let Some(loop_cycles) = loop_cycles {
+            if loop_offset.is_none() {
+                Self::wait(&mut data, &mut samples, loop_cycles);
+                loop_offset = Some(data.len());
+            }
+        }
+        Self::wait(&mut data, &mut samples, end_cycles);
+        data.push(VGM_CMD_END);
+
+        let loop_samples = loop_cycles.map_or(0, |cycles| samples - Self::samples(cycles));
+        let length = data.len() as u32;
+
+        data[0x00..0x04].copy_from_slice(b"Vgm ");
+        data[0x04..0x08].copy_from_slice(&(length - 0x04).to_le_bytes());
+        data[0x08..0x0c].copy_from_slice(&0x00000161u32.to_le_bytes());
+        data[0x18..0x1c].copy_from_slice(&(samples as u32).to_le_bytes());
+        if let Some(loop_offset) = loop_offset {
+            data[0x1c..0x20].copy_from_slice(&(loop_offset as u32 - 0x1c).to_le_bytes());
+            data[0x20..0x24].copy_from_slice(&(loop_samples as u32).to_le_bytes());
+        }
+        data[0x34..0x38].copy_from_slice(&(VGM_HEADER_SIZE as u32 - 0x34).to_le_bytes());
+        data[0x80..0x84].copy_from_slice(&VGM_CLOCK.to_le_bytes());
+
+        data
+    }
+
+    pub fn save_file(&self, path: &str) -> io::Result<()> {
+        write_file(path, &self.to_bytes())
+    }
+
+    /// Converts a number of (APU) cycles into VGM samples.
+    fn samples(cycles: u64) -> u64 {
+        cycles * VGM_RATE as u64 / VGM_CLOCK as u64
+    }
+
+    /// Appends the wait commands required to move from the current
+    /// sample position into the one of the provided cycles.
+    fn wait(data: &mut Vec<u8>, samples: &mut u64, cycles: u64) {
+        let target = Self::samples(cycles);
+        while *samples < target {
+            let count = (target - *samples).min(0xffff);
+            match count {
+                1..=16 => data.push(0x70 + count as u8 - 1),
+                735 => data.push(0x62),
+                882 => data.push(0x63),
+                _ => {
+                    data.push(VGM_CMD_WAIT);
+                    data.extend_from_slice(&(count as u16).to_le_bytes());
+                }
+            }
+            *samples += count;
+        }
+    }
+}
+
+impl Default for VgmLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VgmLogger, VGM_FRAME_CYCLES};
+
+    fn clock_frame(vgm: &mut VgmLogger) {
+        vgm.clock((VGM_FRAME_CYCLES / 2) as u16);
+        vgm.clock((VGM_FRAME_CYCLES / 2) as u16);
+    }
+
+    #[test]
+    fn test_vgm_bytes() {
+        let mut vgm = VgmLogger::new();
+        vgm.log(0xff26, 0x80);
+        vgm.clock(95);
+        vgm.log(0xff12, 0xf0);
+        vgm.log(0xff40, 0x91);
+
+        let data = vgm.to_bytes();
+        assert_eq!(&data[0x00..0x04], b"Vgm ");
+        assert_eq!(&data[0x04..0x08], &(data.len() as u32 - 4).to_le_bytes());
+        assert_eq!(&data[0x08..0x0c], &0x161u32.to_le_bytes());
+        assert_eq!(&data[0x18..0x1c], &0u32.to_le_bytes());
+        assert_eq!(&data[0x1c..0x20], &0u32.to_le_bytes());
+        assert_eq!(&data[0x34..0x38], &0xccu32.to_le_bytes());
+        assert_eq!(&data[0x80..0x84], &4194304u32.to_le_bytes());
+        assert_eq!(&data[0x100..], &[0xb3, 0x16, 0x80, 0xb3, 0x02, 0xf0, 0x66]);
+
+        for _ in 0..128 {
+            vgm.clock(0x8000);
+        }
+        vgm.log(0xff13, 0x42);
+        let data = vgm.to_bytes();
+        assert_eq!(&data[0x18..0x1c], &44100u32.to_le_bytes());
+        assert_eq!(&data[0x106..], &[0x61, 0x44, 0xac, 0xb3, 0x03, 0x42, 0x66]);
+    }
+
+    #[test]
+    fn test_vgm_loop() {
+        let mut vgm = VgmLogger::new();
+
+        // an intro of two frames followed by a loop of three
+        // frames (with an empty one) repeated three times
+        vgm.log(0xff26, 0x80);
+        clock_frame(&mut vgm);
+        vgm.log(0xff24, 0x77);
+        clock_frame(&mut vgm);
+        for _ in 0..3 {
+            vgm.log(0xff13, 0x10);
+            clock_frame(&mut vgm);
+            vgm.log(0xff13, 0x20);
+            clock_frame(&mut vgm);
+            clock_frame(&mut vgm);
+        }
+        assert_eq!(vgm.frame_count(), 11);
+        assert_eq!(vgm.detect_loop(), Some((2, 3)));
+
+        let data = vgm.to_bytes();
+        let frame_samples = |frames: u64| frames * VGM_FRAME_CYCLES * 44100 / 4194304;
+        let loop_offset = u32::from_le_bytes([data[0x1c], data[0x1d], data[0x1e], data[0x1f]]);
+        let loop_samples = u32::from_le_bytes([data[0x20], data[0x21], data[0x22], data[0x23]]);
+        let samples = u32::from_le_bytes([data[0x18], data[0x19], data[0x1a], data[0x1b]]);
+        assert_eq!(samples as u64, frame_samples(5));
+        assert_eq!(loop_samples as u64, frame_samples(5) - frame_samples(2));
+        assert_eq!(
+            &data[loop_offset as usize + 0x1c..][..3],
+            &[0xb3, 0x03, 0x10]
+        );
+        assert_eq!(data.last(), Some(&0x66));
+    }
+
+    #[test]
+    fn test_vgm_loop_partial() {
+        let mut vgm = VgmLogger::new();
+
+        // a loop of two frames repeated three times, with the log
+        // ending in the middle of the following frame
+        vgm.log(0xff26, 0x80);
+        clock_frame(&mut vgm);
+        for _ in 0..3 {
+            vgm.log(0xff13, 0x10);
+            clock_frame(&mut vgm);
+            vgm.log(0xff13, 0x20);
+            clock_frame(&mut vgm);
+        }
+        vgm.log(0xff13, 0x30);
+        vgm.clock((VGM_FRAME_CYCLES / 2) as u16);
+        assert_eq!(vgm.frame_count(), 8);
+        assert_eq!(vgm.detect_loop(), Some((1, 2)));
+
+        // the writes of the partial frame are not exported
+        let data = vgm.to_bytes();
+        let samples = u32::from_le_bytes([data[0x18], data[0x19], data[0x1a], data[0x1b]]);
+        assert_eq!(samples as u64, 3 * VGM_FRAME_CYCLES * 44100 / 4194304);
+        assert!(!data[0x100..].windows(3).any(|w| w == [0xb3, 0x03, 0x30]));
+    }
+
+    #[test]
+    fn test_vgm_no_loop() {
+        let mut vgm = VgmLogger::new();
+        for index in 0..8 {
+            vgm.log(0xff13, index);
+            clock_frame(&mut vgm);
+        }
+        assert_eq!(vgm.detect_loop(), None);
+
+        // with no loop the writes of the partial frame are kept
+        vgm.log(0xff13, 0x30);
+        vgm.clock((VGM_FRAME_CYCLES / 2) as u16);
+        let data = vgm.to_bytes();
+        assert!(data[0x100..].windows(3).any(|w| w == [0xb3, 0x03, 0x30]));
+    }
+}
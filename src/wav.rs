@@ -0,0 +1,277 @@
+use crate::{
+    blip::{BlipBuffer, HighPass},
+    util::write_file,
+};
+
+use std::io;
+
+/// The number of sound channels of the APU, each of them
+/// can be recorded into its own (stem) file.
+pub const STEM_COUNT: usize = 4;
+
+/// Audio recorder that captures the (stereo) mixed output of the
+/// APU and optionally the output of each of the four channels
+/// (stems), so that they can be exported as WAV files.
+///
+/// Both the mixed output and the stems are synthesized from the
+/// output levels with the recorder's own band-limited buffers, at
+/// the nominal sampling rate, so that the recording is not affected
+/// by the dynamic rate control applied to the APU output.
+#[derive(Clone)]
+pub struct WavRecorder {
+    /// The sampling rate (in Hz) of the recorded samples.
+    sampling_rate: u32,
+
+    /// The mixed output, as interleaved left and right samples.
+    mixed: Vec<i16>,
+
+    /// The per channel (mono) samples, only available in case
+    /// the recording of stems has been requested.
+    stems: Option<Vec<Vec<i16>>>,
+
+    /// The tracks used to synthesize the left and right
+    /// terminals of the mixed output.
+    mixed_tracks: [WavTrack; 2],
+
+    /// The tracks used to synthesize each of the stems.
+    stem_tracks: Vec<WavTrack>,
+
+    /// The multiplier that converts an output level into
+    /// the signed 16 bit sample range.
+    scale: f32,
+}
+
+/// Synthesizes the samples of an output from its level changes,
+/// using a band-limited buffer and a DC blocking filter.
+#[derive(Clone)]
+struct WavTrack {
+    blip: BlipBuffer,
+    high_pass: HighPass,
+
+    /// The previous output level.
+    level: u8,
+}
+
+impl WavTrack {
+    fn new(clock_rate: u32, sampling_rate: u32, factor: f32) -> Self {
+        Self {
+            blip: BlipBuffer::new(clock_rate, sampling_rate),
+            high_pass: HighPass::new(factor),
+            level: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.blip.reset();
+        self.high_pass.reset();
+        self.level = 0;
+    }
+
+    fn set_rates(&mut self, ratio: f64, factor: f32) {
+        self.blip.set_ratio(ratio);
+        self.high_pass.set_factor(factor);
+    }
+
+    /// Advances the track by the provided number of clocks, with
+    /// the level changing (if different) at the current position.
+    fn clock(&mut self, cycles: u16, level: u8) {
+        self.blip.advance(cycles as u32);
+        if level != self.level {
+            self.blip.add_delta(level as f32 - self.level as f32);
+            self.level = level;
+        }
+    }
+
+    fn read_sample(&mut self, scale: f32) -> Option<i16> {
+        let sample = self.high_pass.filter(self.blip.read_sample()?);
+        Some((sample * scale).clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+    }
+}
+
+impl WavRecorder {
+    pub fn new(sampling_rate: u32, clock_rate: u32, factor: f32, scale: f32, stems: bool) -> Self {
+        let count = if stems { STEM_COUNT } else { 0 };
+        Self {
+            sampling_rate,
+            mixed: vec![],
+            stems: if stems {
+                Some(vec![vec![]; STEM_COUNT])
+            } else {
+                None
+            },
+            mixed_tracks: [
+                WavTrack::new(clock_rate, sampling_rate, factor),
+                WavTrack::new(clock_rate, sampling_rate, factor),
+            ],
+            stem_tracks: (0..count)
+                .map(|_| WavTrack::new(clock_rate, sampling_rate, factor))
+                .collect(),
+            scale,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.mixed.clear();
+        if let Some(stems) = self.stems.as_mut() {
+            stems.iter_mut().for_each(|stem| stem.clear());
+        }
+        self.mixed_tracks.iter_mut().for_each(|track| track.reset());
+        self.stem_tracks.iter_mut().for_each(|track| track.reset());
+    }
+
+    /// Updates the rate conversion ratio and the high-pass factor of
+    /// the tracks, the ratio is expected to be the nominal one (from
+    /// the sampling rate of the recording) with no rate control.
+    pub fn set_rates(&mut self, ratio: f64, factor: f32) {
+        self.mixed_tracks
+            .iter_mut()
+            .chain(self.stem_tracks.iter_mut())
+            .for_each(|track| track.set_rates(ratio, factor));
+    }
+
+    /// Advances the recording by the provided number of clocks, using
+    /// the current output level of the left and right terminals and
+    /// of each of the channels (for the stems).
+    pub fn clock(&mut self, cycles: u16, outputs: [u8; 2], levels: [u8; STEM_COUNT]) {
+        let [left, right] = &mut self.mixed_tracks;
+        left.clock(cycles, outputs[0]);
+        right.clock(cycles, outputs[1]);
+        while let Some(left) = left.read_sample(self.scale) {
+            let right = right.read_sample(self.scale).unwrap_or(0);
+            self.mixed.push(left);
+            self.mixed.push(right);
+        }
+
+        let stems = match self.stems.as_mut() {
+            Some(stems) => stems,
+            None => return,
+        };
+        for (index, stem) in stems.iter_mut().enumerate() {
+            let track = &mut self.stem_tracks[index];
+            track.clock(cycles, levels[index]);
+            while let Some(sample) = track.read_sample(self.scale) {
+                stem.push(sample);
+            }
+        }
+    }
+
+    pub fn sampling_rate(&self) -> u32 {
+        self.sampling_rate
+    }
+
+    pub fn mixed(&self) -> &Vec<i16> {
+        &self.mixed
+    }
+
+    pub fn stem(&self, index: usize) -> Option<&Vec<i16>> {
+        self.stems.as_ref().and_then(|stems| stems.get(index))
+    }
+
+    pub fn has_stems(&self) -> bool {
+        self.stems.is_some()
+    }
+
+    /// The duration (in seconds) of the recorded audio.
+    pub fn duration(&self) -> f32 {
+        self.mixed.len() as f32 / 2.0 / self.sampling_rate as f32
+    }
+
+    /// Exports the mixed output as the contents of a 16 bit
+    /// stereo PCM WAV file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        wav_bytes(&self.mixed, 2, self.sampling_rate)
+    }
+
+    /// Exports the stem of the channel with the provided index
+    /// (0 for channel 1) as the contents of a mono WAV file.
+    pub fn stem_bytes(&self, index: usize) -> Option<Vec<u8>> {
+        self.stem(index)
+            .map(|stem| wav_bytes(stem, 1, self.sampling_rate))
+    }
+
+    /// Saves the mixed output to the WAV file at the provided path
+    /// and in case they are available the stems to sibling files
+    /// with a channel suffix (eg: `song.wav` and `song-ch1.wav`).
+    pub fn save_file(&self, path: &str) -> io::Result<()> {
+        write_file(path, &self.to_bytes())?;
+        for index in 0..STEM_COUNT {
+            if let Some(data) = self.stem_bytes(index) {
+                write_file(&stem_path(path, index), &data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds the path of the stem file of the channel with the given
+/// index from the path of the mixed output file.
+pub fn stem_path(path: &str, index: usize) -> String {
+    let base = path.strip_suffix(".wav").unwrap_or(path);
+    format!("{}-ch{}.wav", base, index + 1)
+}
+
+/// Encodes the provided (interleaved) 16 bit samples as a
+/// canonical RIFF/WAVE file with a PCM format chunk.
+pub fn wav_bytes(samples: &[i16], channels: u16, sampling_rate: u32) -> Vec<u8> {
+    let data_size = samples.len() as u32 * 2;
+    let block_align = channels * 2;
+    let mut data = Vec::with_capacity(44 + data_size as usize);
+    data.extend_from_slice(b"RIFF");
+    data.extend_from_slice(&(36 + data_size).to_le_bytes());
+    data.extend_from_slice(b"WAVE");
+    data.extend_from_slice(b"fmt ");
+    data.extend_from_slice(&16u32.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&channels.to_le_bytes());
+    data.extend_from_slice(&sampling_rate.to_le_bytes());
+    data.extend_from_slice(&(sampling_rate * block_align as u32).to_le_bytes());
+    data.extend_from_slice(&block_align.to_le_bytes());
+    data.extend_from_slice(&16u16.to_le_bytes());
+    data.extend_from_slice(b"data");
+    data.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        data.extend_from_slice(&sample.to_le_bytes());
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{stem_path, wav_bytes, WavRecorder};
+
+    #[test]
+    fn test_wav_bytes() {
+        let data = wav_bytes(&[1, -1, 256, -256], 2, 44100);
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(&data[4..8], &44u32.to_le_bytes());
+        assert_eq!(&data[22..24], &2u16.to_le_bytes());
+        assert_eq!(&data[24..28], &44100u32.to_le_bytes());
+        assert_eq!(&data[28..32], &176400u32.to_le_bytes());
+        assert_eq!(&data[40..44], &8u32.to_le_bytes());
+        assert_eq!(
+            &data[44..],
+            &[0x01, 0x00, 0xff, 0xff, 0x00, 0x01, 0x00, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_stems() {
+        let mut recorder = WavRecorder::new(44100, 4194304, 0.99, 256.0, true);
+        for _ in 0..4096 {
+            recorder.clock(64, [15, 0], [15, 0, 0, 8]);
+            recorder.clock(64, [0, 0], [0, 0, 0, 8]);
+        }
+
+        assert_eq!(recorder.stem(0).unwrap().len(), 5512);
+        assert!(recorder.stem(0).unwrap().iter().any(|v| *v != 0));
+        assert!(recorder.stem(1).unwrap().iter().all(|v| *v == 0));
+        assert_eq!(recorder.stem_bytes(3).unwrap().len(), 44 + 5512 * 2);
+        assert_eq!(recorder.mixed().len(), 5512 * 2);
+        assert!(recorder.mixed().iter().step_by(2).any(|v| *v != 0));
+        assert!(recorder.mixed().iter().skip(1).step_by(2).all(|v| *v == 0));
+        assert_eq!(recorder.to_bytes().len(), 44 + 5512 * 4);
+        assert_eq!(stem_path("song.wav", 0), "song-ch1.wav");
+        assert_eq!(stem_path("song", 3), "song-ch4.wav");
+    }
+}